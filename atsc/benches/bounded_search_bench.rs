@@ -0,0 +1,78 @@
+/*
+Copyright 2024 NetApp, Inc.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    https://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+//! Benchmarks of the search for the smallest number of frequencies (FFT) and points (Polynomial, IDW) that meets
+//! the error, on frames of real data of several sizes and for several error levels.
+
+use atsc::compressor::fft::fft_allowed_error;
+use atsc::compressor::polynomial::{polynomial_allowed_error, PolynomialType};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use std::path::PathBuf;
+use wavbrro::wavbrro::WavBrro;
+
+const TEST_WBRO_PATH: &str = "tests/wbros/memory_used.wbro";
+const FRAME_SIZES: [usize; 3] = [512, 2048, 8192];
+const ERRORS: [f64; 3] = [0.01, 0.03, 0.05];
+
+/// Loads the file, repeated up to `size` samples
+fn load_frame(size: usize) -> Vec<f64> {
+    let data: Vec<f64> = WavBrro::from_file(&PathBuf::from(TEST_WBRO_PATH))
+        .unwrap()
+        .into_iter()
+        .filter(|x| x.is_finite())
+        .collect();
+    data.iter().cycle().take(size).copied().collect()
+}
+
+fn fft_bounded_benchmark(c: &mut Criterion) {
+    let mut group = c.benchmark_group("FFT Bounded Search");
+    for size in FRAME_SIZES {
+        let data = load_frame(size);
+        for error in ERRORS {
+            group.bench_with_input(
+                BenchmarkId::new(format!("{} samples", size), error),
+                &error,
+                |b, &error| b.iter(|| fft_allowed_error(black_box(&data), error)),
+            );
+        }
+    }
+    group.finish();
+}
+
+fn polynomial_bounded_benchmark(c: &mut Criterion) {
+    for (name, p_type) in [
+        ("Polynomial Bounded Search", PolynomialType::Polynomial),
+        ("IDW Bounded Search", PolynomialType::Idw),
+    ] {
+        let mut group = c.benchmark_group(name);
+        for size in FRAME_SIZES {
+            let data = load_frame(size);
+            for error in ERRORS {
+                group.bench_with_input(
+                    BenchmarkId::new(format!("{} samples", size), error),
+                    &error,
+                    |b, &error| {
+                        b.iter(|| polynomial_allowed_error(black_box(&data), error, p_type.clone()))
+                    },
+                );
+            }
+        }
+        group.finish();
+    }
+}
+
+criterion_group!(benches, fft_bounded_benchmark, polynomial_bounded_benchmark);
+criterion_main!(benches);
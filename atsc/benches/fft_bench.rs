@@ -15,7 +15,9 @@ limitations under the License.
 */
 
 use atsc::compressor::fft::{fft, fft_allowed_error, fft_set, fft_to_data, FFT};
+use atsc::compressor::fft_cache::FftCache;
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use rustfft::{num_complex::Complex, FftPlanner};
 use std::path::PathBuf;
 use wavbrro::wavbrro::WavBrro;
 
@@ -169,6 +171,57 @@ fn multiple_compression_rounds_benchmark(c: &mut Criterion) {
     });
 }
 
+// Many small frames, planning a new FFT for each frame vs reusing the shared plans and scratch buffers
+fn fft_plan_cache_benchmark(c: &mut Criterion) {
+    let data = load_data_from_wbro_file();
+    // 256 samples frames get Gibbs sized to 288
+    let data: Vec<f64> = data.iter().cycle().take(256 * 1000).copied().collect();
+    let frames: Vec<Vec<f64>> = data.chunks_exact(256).map(|c| c.to_vec()).collect();
+    let buffers: Vec<Vec<Complex<f32>>> = frames
+        .iter()
+        .map(|frame| {
+            FFT::gibbs_sizing(frame)
+                .iter()
+                .map(|&x| Complex::new(x as f32, 0.0))
+                .collect()
+        })
+        .collect();
+
+    let mut group = c.benchmark_group("FFT Plan Cache (1000 small frames)");
+    group.bench_function("New planner per frame", |b| {
+        b.iter(|| {
+            for buffer in &buffers {
+                let mut buffer = buffer.clone();
+                let mut planner = FftPlanner::new();
+                planner.plan_fft_forward(buffer.len()).process(&mut buffer);
+                let mut planner = FftPlanner::new();
+                planner.plan_fft_inverse(buffer.len()).process(&mut buffer);
+                black_box(buffer);
+            }
+        });
+    });
+    group.bench_function("Shared plan cache", |b| {
+        let cache = FftCache::global();
+        b.iter(|| {
+            for buffer in &buffers {
+                let mut buffer = buffer.clone();
+                cache.forward(&mut buffer);
+                cache.inverse(&mut buffer);
+                black_box(buffer);
+            }
+        });
+    });
+    group.bench_function("Compress and decompress", |b| {
+        b.iter(|| {
+            for frame in &frames {
+                let compressed_data = fft_set(black_box(frame), 8);
+                black_box(fft_to_data(frame.len(), &compressed_data));
+            }
+        });
+    });
+    group.finish();
+}
+
 //Criterion benchmark group
 criterion_group!(
     benches,
@@ -181,5 +234,6 @@ criterion_group!(
     fft_varying_data_size_benchmark,
     compression_ratio_vs_time_benchmark,
     multiple_compression_rounds_benchmark,
+    fft_plan_cache_benchmark,
 );
 criterion_main!(benches);
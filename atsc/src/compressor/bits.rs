@@ -0,0 +1,140 @@
+/*
+Copyright 2024 NetApp, Inc.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    https://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+//! Bit level streams for the compressors that pack values in fewer bits than a byte.
+
+/// Writes bits from the most significant one
+#[derive(Default)]
+pub(crate) struct BitWriter {
+    bytes: Vec<u8>,
+    used: u64,
+}
+
+impl BitWriter {
+    pub(crate) fn push(&mut self, bit: bool) {
+        if self.used % 8 == 0 {
+            self.bytes.push(0);
+        }
+        if bit {
+            *self.bytes.last_mut().unwrap() |= 0x80 >> (self.used % 8);
+        }
+        self.used += 1;
+    }
+
+    /// The `bits` low bits of `value`
+    pub(crate) fn fixed(&mut self, value: u64, bits: u8) {
+        for bit in (0..bits).rev() {
+            self.push((value >> bit) & 1 == 1);
+        }
+    }
+
+    /// Rice code of `value` with parameter `bits`, the quotient in unary and the `bits` low bits
+    pub(crate) fn rice(&mut self, value: u64, bits: u8) {
+        for _ in 0..value >> bits {
+            self.push(true);
+        }
+        self.push(false);
+        self.fixed(value, bits);
+    }
+
+    /// Bytes written, the last one padded with zeros
+    pub(crate) fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+pub(crate) struct BitReader<'a> {
+    bytes: &'a [u8],
+    read: usize,
+}
+
+impl<'a> BitReader<'a> {
+    pub(crate) fn new(bytes: &'a [u8]) -> Self {
+        BitReader { bytes, read: 0 }
+    }
+
+    /// Bits past the end are zeros
+    pub(crate) fn next(&mut self) -> bool {
+        let bit = self
+            .bytes
+            .get(self.read / 8)
+            .is_some_and(|byte| byte & (0x80 >> (self.read % 8)) != 0);
+        self.read += 1;
+        bit
+    }
+
+    pub(crate) fn fixed(&mut self, bits: u8) -> u64 {
+        (0..bits).fold(0, |value, _| (value << 1) | self.next() as u64)
+    }
+
+    pub(crate) fn rice(&mut self, bits: u8) -> u64 {
+        let mut quotient = 0;
+        while self.next() {
+            quotient += 1;
+        }
+        (0..bits).fold(quotient, |value, _| (value << 1) | self.next() as u64)
+    }
+}
+
+/// Rice parameter that codes `values` in the fewest bits
+pub(crate) fn rice_bits(values: &[u64]) -> u8 {
+    (0..63u8)
+        .min_by_key(|&bits| {
+            values
+                .iter()
+                .map(|&value| (value >> bits) as u128 + 1 + bits as u128)
+                .sum::<u128>()
+        })
+        .unwrap_or(0)
+}
+
+/// Bits needed to tell `count` values apart, 0 for a single one
+pub(crate) fn bits_for(count: usize) -> u8 {
+    (usize::BITS - count.saturating_sub(1).leading_zeros()) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rice() {
+        let values = [0, 1, 5, 1000, 3, 2, 70000];
+        let bits = rice_bits(&values);
+        let mut writer = BitWriter::default();
+        for &value in &values {
+            writer.rice(value, bits);
+            writer.fixed(value & 0x1f, 5);
+        }
+        let bytes = writer.into_bytes();
+        let mut reader = BitReader::new(&bytes);
+        for &value in &values {
+            assert_eq!(reader.rice(bits), value);
+            assert_eq!(reader.fixed(5), value & 0x1f);
+        }
+    }
+
+    #[test]
+    fn test_bits_for() {
+        assert_eq!(bits_for(0), 0);
+        assert_eq!(bits_for(1), 0);
+        assert_eq!(bits_for(2), 1);
+        assert_eq!(bits_for(3), 2);
+        assert_eq!(bits_for(4), 2);
+        assert_eq!(bits_for(5), 3);
+        assert_eq!(bits_for(256), 8);
+    }
+}
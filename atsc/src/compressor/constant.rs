@@ -17,6 +17,7 @@ limitations under the License.
 use crate::{
     compressor::CompressorResult,
     optimizer::utils::{Bitdepth, DataStats},
+    utils::{error::calculate_error, median},
 };
 
 use super::BinConfig;
@@ -24,6 +25,7 @@ use bincode::{Decode, Encode};
 use log::debug;
 
 const CONSTANT_COMPRESSOR_ID: u8 = 30;
+const TREND_COMPRESSOR_ID: u8 = 31;
 
 /// Compressor frame for static data, stores the value and nothing else.
 #[derive(PartialEq, Debug, Clone)]
@@ -138,11 +140,118 @@ pub fn constant_compressor(data: &[f64], stats: DataStats) -> CompressorResult {
     CompressorResult::new(c.to_bytes(), 0.0)
 }
 
+/// Compresses the data as a single value, the median or the mean, whichever has the smallest error. For integer
+/// data the value is rounded, so it fits the bitdepth.
+pub fn constant_allowed_error(data: &[f64], stats: DataStats) -> CompressorResult {
+    debug!("Initializing Constant Compressor. Lossy");
+    // The median goes first, it is the exact value of constant data
+    let candidates = [median(data), stats.mean].map(|value| match stats.bitdepth {
+        Bitdepth::F64 => value,
+        _ => value.round(),
+    });
+    candidates
+        .into_iter()
+        .map(|value| {
+            let c = Constant::new(data.len(), value, stats.bitdepth);
+            let error = calculate_error(data, &c.to_data(data.len()));
+            CompressorResult::new(c.to_bytes(), error)
+        })
+        .min_by(|a, b| a.error.total_cmp(&b.error))
+        .unwrap()
+}
+
 pub fn constant_to_data(sample_number: usize, compressed_data: &[u8]) -> Vec<f64> {
     let c = Constant::decompress(compressed_data);
     c.to_data(sample_number)
 }
 
+/// Compressor frame for data that follows a line, stores the values of the line at the first and the last sample.
+#[derive(Encode, Decode, PartialEq, Debug, Clone)]
+pub struct Trend {
+    pub id: u8,
+    pub first: f64,
+    pub last: f64,
+}
+
+impl Trend {
+    pub fn new(first: f64, last: f64) -> Self {
+        debug!("Trend compressor");
+        Trend {
+            id: TREND_COMPRESSOR_ID,
+            first,
+            last,
+        }
+    }
+
+    /// Least squares line of the data
+    fn fit(data: &[f64]) -> Self {
+        let n = data.len() as f64;
+        let center = (n - 1.0) / 2.0;
+        let mean = data.iter().sum::<f64>() / n;
+        let (covariance, variance) = data.iter().enumerate().fold((0.0, 0.0), |(c, v), (x, y)| {
+            let dx = x as f64 - center;
+            (c + dx * (y - mean), v + dx * dx)
+        });
+        let slope = if variance > 0.0 {
+            covariance / variance
+        } else {
+            0.0
+        };
+        Trend::new(mean - slope * center, mean + slope * center)
+    }
+
+    /// Line through the medians of both halves of the data, outliers don't move it
+    fn fit_median(data: &[f64]) -> Self {
+        let half = data.len() / 2;
+        if half == 0 {
+            return Trend::new(median(data), median(data));
+        }
+        let (left, right) = (&data[..half], &data[data.len() - half..]);
+        // Centers of the halves, they are `len - half` samples apart
+        let slope = (median(right) - median(left)) / (data.len() - half) as f64;
+        let center = (half as f64 - 1.0) / 2.0;
+        let first = median(left) - slope * center;
+        Trend::new(first, first + slope * (data.len() - 1) as f64)
+    }
+
+    pub fn decompress(data: &[u8]) -> Self {
+        let config = BinConfig::get();
+        let (trend, _) = bincode::decode_from_slice(data, config).unwrap();
+        trend
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let config = BinConfig::get();
+        bincode::encode_to_vec(self, config).unwrap()
+    }
+
+    /// Returns the `frame_size` samples of the line
+    pub fn to_data(&self, frame_size: usize) -> Vec<f64> {
+        let steps = frame_size.saturating_sub(1).max(1) as f64;
+        (0..frame_size)
+            .map(|x| self.first + (self.last - self.first) * x as f64 / steps)
+            .collect()
+    }
+}
+
+/// Compresses the data as a line, the least squares one or the one through the medians, whichever has the
+/// smallest error
+pub fn trend_compressor(data: &[f64]) -> CompressorResult {
+    debug!("Initializing Trend Compressor");
+    [Trend::fit_median(data), Trend::fit(data)]
+        .into_iter()
+        .map(|c| {
+            let error = calculate_error(data, &c.to_data(data.len()));
+            CompressorResult::new(c.to_bytes(), error)
+        })
+        .min_by(|a, b| a.error.total_cmp(&b.error))
+        .unwrap()
+}
+
+pub fn trend_to_data(sample_number: usize, compressed_data: &[u8]) -> Vec<f64> {
+    Trend::decompress(compressed_data).to_data(sample_number)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -176,4 +285,54 @@ mod tests {
 
         assert_eq!(vector1, c2);
     }
+
+    #[test]
+    fn test_near_constant() {
+        // A gauge jittering around a level
+        let data: Vec<f64> = (0..1000)
+            .map(|x| 50.0 + ((x * 7) % 3) as f64 * 0.001)
+            .collect();
+        let result = constant_allowed_error(&data, DataStats::new(&data));
+        assert!(result.error < 0.0001, "{}", result.error);
+        let out = constant_to_data(data.len(), &result.compressed_data);
+        assert_eq!(calculate_error(&data, &out), result.error);
+        // The median, an actual sample, beats the mean
+        assert_eq!(out[0], 50.001);
+        // Constant data is exact
+        let result = constant_allowed_error(&[1.23456; 5], DataStats::new(&[1.23456; 5]));
+        assert_eq!(result.error, 0.0);
+    }
+
+    #[test]
+    fn test_near_constant_integers() {
+        let data = [10.0, 10.0, 11.0, 12.0, 12.0, 12.0];
+        let result = constant_allowed_error(&data, DataStats::new(&data));
+        // Stored in a byte
+        assert_eq!(result.compressed_data.len(), 3);
+        assert_eq!(
+            constant_to_data(data.len(), &result.compressed_data)[0],
+            11.0
+        );
+    }
+
+    #[test]
+    fn test_trend() {
+        let data: Vec<f64> = (0..500).map(|x| 100.0 + x as f64 * 0.5).collect();
+        let result = trend_compressor(&data);
+        assert!(result.error < 1e-12, "{}", result.error);
+        let out = trend_to_data(data.len(), &result.compressed_data);
+        assert!((out[499] - 349.5).abs() < 1e-9);
+        // An outlier barely moves the line through the medians, it pulls the least squares one
+        let mut spiky = data.clone();
+        spiky[10] = 10000.0;
+        let trend = Trend::fit_median(&spiky);
+        assert!((trend.first - 100.0).abs() < 1.0 && (trend.last - 349.5).abs() < 1.0);
+        assert!((Trend::fit(&spiky).first - 100.0).abs() > 10.0);
+        let out = trend_to_data(spiky.len(), &trend_compressor(&spiky).compressed_data);
+        assert_eq!(out[0], trend.first);
+        for data in [vec![], vec![3.0], vec![3.0, 5.0]] {
+            let out = trend_to_data(data.len(), &trend_compressor(&data).compressed_data);
+            assert_eq!(out, data);
+        }
+    }
 }
@@ -0,0 +1,403 @@
+/*
+Copyright 2024 NetApp, Inc.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    https://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use crate::{
+    compressor::CompressorResult,
+    optimizer::utils::DataStats,
+    utils::{error::calculate_error, round_and_limit_f64, smallest_passing, DECIMAL_PRECISION},
+};
+
+use super::quantization::{
+    coarsest_exponent, dequantize, fine_exponent, quantize, top_exponent, Quantized,
+};
+use super::{fft_cache::FftCache, BinConfig};
+use bincode::{Decode, Encode};
+use log::{debug, info, trace};
+use rustfft::num_complex::Complex;
+use std::f64::consts::PI;
+
+const DCT_COMPRESSOR_ID: u8 = 25;
+/// Shares of the error bound the number of coefficients is searched for, the rest of the bound is left to the
+/// quantization. With the fewest coefficients the error is at the bound, a few more allow a much coarser step.
+const COUNT_ERROR_SHARES: [f64; 3] = [1.0, 0.98, 0.9];
+
+/// DCT Compressor. Applies a DCT-II to the frame, picks the N biggest coefficients, discards the rest.
+/// The DCT sees the frame followed by its mirror, so unlike FFT there is no jump from the end of the frame back to
+/// its start, and smooth frames that are not periodic need no padding and fewer coefficients. Always LOSSY
+#[derive(Encode, Decode, PartialEq, Debug)]
+pub struct Dct {
+    pub id: u8,
+    /// Kept coefficients, sorted by position
+    coefficients: Vec<Quantized<i32>>,
+    pub max_value: f32,
+    pub min_value: f32,
+    /// Exponent of the power of two the coefficients are multiples of
+    pub exponent: i8,
+}
+
+/// DCT-II of `data`, X_k = sum of x_n * cos(PI * k * (2n + 1) / 2N). Computed with the FFT of the data followed by
+/// its mirror.
+fn forward(data: &[f64]) -> Vec<f64> {
+    let len = data.len();
+    let mut buffer: Vec<Complex<f32>> = data
+        .iter()
+        .chain(data.iter().rev())
+        .map(|&x| Complex {
+            re: x as f32,
+            im: 0.0,
+        })
+        .collect();
+    FftCache::global().forward(&mut buffer);
+    buffer
+        .iter()
+        .take(len)
+        .enumerate()
+        .map(|(k, y)| {
+            let phase = PI * k as f64 / (2 * len) as f64;
+            (y.re as f64 * phase.cos() + y.im as f64 * phase.sin()) / 2.0
+        })
+        .collect()
+}
+
+/// Inverse of `forward` (a scaled DCT-III) for the `coefficients`, positions and values, of a frame of `len`
+/// samples
+fn inverse(coefficients: impl Iterator<Item = (usize, f64)>, len: usize) -> Vec<f64> {
+    let size = 2 * len;
+    let mut buffer = vec![Complex::<f32>::default(); size];
+    for (k, value) in coefficients {
+        let phase = PI * k as f64 / size as f64;
+        let y = Complex {
+            re: (2.0 * value * phase.cos()) as f32,
+            im: (2.0 * value * phase.sin()) as f32,
+        };
+        buffer[k] = y;
+        // Spectrum of a real signal, the upper half mirrors the lower one
+        if k > 0 {
+            buffer[size - k] = y.conj();
+        }
+    }
+    FftCache::global().inverse(&mut buffer);
+    buffer[..len]
+        .iter()
+        .map(|y| y.re as f64 / size as f64)
+        .collect()
+}
+
+/// Coefficients of a frame, and their positions from the biggest to the smallest without the empty ones
+struct Analysis {
+    coefficients: Vec<f64>,
+    ranked: Vec<usize>,
+}
+
+impl Analysis {
+    fn new(data: &[f64]) -> Self {
+        let coefficients = forward(data);
+        let mut ranked: Vec<usize> = (0..coefficients.len())
+            .filter(|&pos| coefficients[pos] != 0.0 && coefficients[pos].is_finite())
+            .collect();
+        ranked.sort_by(|&a, &b| coefficients[b].abs().total_cmp(&coefficients[a].abs()));
+        Analysis {
+            coefficients,
+            ranked,
+        }
+    }
+
+    /// Positions and values of the first `count` ranked coefficients
+    fn kept(&self, count: usize) -> impl Iterator<Item = (usize, f64)> + '_ {
+        self.ranked[..count.min(self.ranked.len())]
+            .iter()
+            .map(|&pos| (pos, self.coefficients[pos]))
+    }
+
+    /// Smallest exponent of a power of two that is bigger than every one of the first `count` ranked coefficients
+    fn top_exponent(&self, count: usize) -> i32 {
+        top_exponent(self.kept(count).map(|(_, value)| value))
+    }
+}
+
+impl Dct {
+    pub fn new(min: f64, max: f64) -> Self {
+        debug!("DCT compressor: min:{} max:{}", min, max);
+        Dct {
+            id: DCT_COMPRESSOR_ID,
+            coefficients: Vec::new(),
+            max_value: max as f32,
+            min_value: min as f32,
+            exponent: 0,
+        }
+    }
+
+    /// Stores the first `count` ranked coefficients as multiples of 2^`exponent`, the ones that round to 0 are
+    /// dropped
+    fn set_coefficients(&mut self, analysis: &Analysis, count: usize, exponent: i8) {
+        self.coefficients = quantize(analysis.kept(count), exponent);
+        self.exponent = exponent;
+    }
+
+    /// Positions and values of the kept coefficients
+    fn coefficients(&self) -> impl Iterator<Item = (usize, f64)> + '_ {
+        dequantize(&self.coefficients, self.exponent)
+    }
+
+    /// Number of kept coefficients
+    pub fn coefficient_count(&self) -> usize {
+        self.coefficients.len()
+    }
+
+    /// Compresses the data keeping `count` coefficients, finely quantized
+    pub fn compress_hinted(&mut self, data: &[f64], count: usize) {
+        let analysis = Analysis::new(data);
+        let exponent = fine_exponent(analysis.top_exponent(count));
+        self.set_coefficients(&analysis, count, exponent);
+    }
+
+    /// Compresses the data keeping `count` coefficients as multiples of 2^`exponent`
+    pub fn compress_quantized(&mut self, data: &[f64], count: usize, exponent: i8) {
+        self.set_coefficients(&Analysis::new(data), count, exponent);
+    }
+
+    /// Compresses the data keeping the fewest coefficients that meet a share of the error, quantized as coarsely as
+    /// the whole error allows, and keeps the smallest of the shares in `COUNT_ERROR_SHARES`. The transform is
+    /// computed once, each step of the search only runs the inverse of the kept coefficients. Returns the error, if
+    /// no number of coefficients meets it, the default number of coefficients is kept.
+    pub fn compress_bounded(&mut self, data: &[f64], max_err: f64) -> f64 {
+        let analysis = Analysis::new(data);
+        let ranked_len = analysis.ranked.len();
+        let default_count = Dct::default_count(data.len());
+        let (min, max) = (self.min_value.into(), self.max_value.into());
+        let mut iterations = 0;
+        let mut error_of = |count: usize, exponent: i8| -> f64 {
+            iterations += 1;
+            let mut candidate = Dct::new(min, max);
+            candidate.set_coefficients(&analysis, count, exponent);
+            calculate_error(data, &candidate.to_data(data.len()))
+        };
+        if ranked_len == 0 {
+            let error = error_of(0, 0);
+            self.set_coefficients(&analysis, 0, 0);
+            return error;
+        }
+        // Finely quantized errors by count, shared by the searches of every share
+        let mut errors: Vec<Option<f64>> = vec![None; ranked_len + 1];
+        // Count, exponent, error and size of the smallest so far
+        let mut best: Option<(usize, i8, f64, usize)> = None;
+        for share in COUNT_ERROR_SHARES {
+            // Finely quantized, the error drops as coefficients are added
+            let mut error_with = |count: usize| -> f64 {
+                *errors[count].get_or_insert_with(|| {
+                    let err = error_of(count, fine_exponent(analysis.top_exponent(count)));
+                    trace!("Coefficients: {} Current Err: {}", count, err);
+                    err
+                })
+            };
+            let mut count = smallest_passing(1, ranked_len, default_count, |count| {
+                error_with(count) <= max_err * share
+            });
+            // The error can't be computed for some data (e.g. zeros), then there is nothing to search for
+            if !error_with(count).is_finite() {
+                count = default_count.min(ranked_len);
+            }
+            let allowed_err = max_err.max(error_with(count));
+            if best.is_some_and(|(best_count, ..)| best_count == count) {
+                continue;
+            }
+
+            // Search the coarsest step still in the bound
+            let (exponent, quantized_err) = coarsest_exponent(
+                analysis.top_exponent(count),
+                |exponent| error_of(count, exponent),
+                |err| err <= allowed_err,
+            );
+            self.set_coefficients(&analysis, count, exponent);
+            let size = self.to_bytes().len();
+            trace!(
+                "Error share: {} Coefficients: {} Size: {}",
+                share,
+                count,
+                size
+            );
+            if best.map_or(true, |(.., best_size)| size < best_size) {
+                best = Some((count, exponent, quantized_err, size));
+            }
+        }
+        let (count, exponent, error, _) = best.unwrap();
+        self.set_coefficients(&analysis, count, exponent);
+        debug!(
+            "Iterations to convergence: {}, Coefficients P:{} S:{}, Exponent: {}, Error: {}",
+            iterations,
+            default_count,
+            self.coefficients.len(),
+            self.exponent,
+            error
+        );
+        error
+    }
+
+    /// Coefficients kept without an error bound, 1/100 of the data length OR 3, which is bigger
+    fn default_count(len: usize) -> usize {
+        (len / 100).max(3)
+    }
+
+    pub fn decompress(data: &[u8]) -> Self {
+        let config = BinConfig::get();
+        let (dct, _) = bincode::decode_from_slice(data, config).unwrap();
+        dct
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let config = BinConfig::get();
+        bincode::encode_to_vec(self, config).unwrap()
+    }
+
+    /// Returns the `frame_size` samples of the frame
+    pub fn to_data(&self, frame_size: usize) -> Vec<f64> {
+        inverse(self.coefficients(), frame_size)
+            .into_iter()
+            .map(|x| {
+                round_and_limit_f64(
+                    x,
+                    self.min_value.into(),
+                    self.max_value.into(),
+                    DECIMAL_PRECISION,
+                )
+            })
+            .collect()
+    }
+}
+
+/// Compresses via DCT, keeping the default number of coefficients
+pub fn dct(data: &[f64]) -> Vec<u8> {
+    info!("Initializing DCT Compressor");
+    dct_set(data, Dct::default_count(data.len()))
+}
+
+/// Compresses via DCT, keeping `count` coefficients
+pub fn dct_set(data: &[f64], count: usize) -> Vec<u8> {
+    let stats = DataStats::new(data);
+    let mut c = Dct::new(stats.min, stats.max);
+    c.compress_hinted(data, count);
+    c.to_bytes()
+}
+
+/// Compresses via DCT, keeping `count` coefficients stored as multiples of 2^`exponent`
+pub fn dct_set_quantized(data: &[f64], count: usize, exponent: i8) -> Vec<u8> {
+    let stats = DataStats::new(data);
+    let mut c = Dct::new(stats.min, stats.max);
+    c.compress_quantized(data, count, exponent);
+    c.to_bytes()
+}
+
+/// Compress targeting a specific max error allowed
+pub fn dct_compressor(data: &[f64], allowed_error: f64, stats: DataStats) -> CompressorResult {
+    debug!("Initializing DCT Compressor. Error and Stats provided");
+    let mut c = Dct::new(stats.min, stats.max);
+    let error = c.compress_bounded(data, allowed_error);
+    CompressorResult::new(c.to_bytes(), error)
+}
+
+/// Uncompress a DCT frame
+pub fn dct_to_data(sample_number: usize, compressed_data: &[u8]) -> Vec<f64> {
+    Dct::decompress(compressed_data).to_data(sample_number)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compressor::fft::fft_compressor;
+    use crate::optimizer::MAX_FRAME_SIZE;
+
+    /// Smooth and not periodic, the end of the frame is far from its start
+    fn trend(len: usize) -> Vec<f64> {
+        (0..len)
+            .map(|x| {
+                let x = x as f64;
+                100.0 + x / 4.0 + (x / 90.0).sin() * 15.0
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_transform() {
+        let data = [1.0, 2.0, 3.0, 5.0, 4.0];
+        let coefficients = forward(&data);
+        // DC component is the sum of the data
+        assert!((coefficients[0] - 15.0).abs() < 1e-4);
+        let expected: f64 = data
+            .iter()
+            .enumerate()
+            .map(|(n, x)| x * (PI * (2 * n + 1) as f64 / 10.0).cos())
+            .sum();
+        assert!((coefficients[1] - expected).abs() < 1e-4);
+        let out = inverse(coefficients.into_iter().enumerate(), data.len());
+        for (x, y) in data.iter().zip(&out) {
+            assert!((x - y).abs() < 1e-4, "{} {}", x, y);
+        }
+    }
+
+    #[test]
+    fn test_dct() {
+        let data = trend(229);
+        let out = dct_to_data(data.len(), &dct_set(&data, usize::MAX));
+        assert!(calculate_error(&data, &out) < 1e-5);
+    }
+
+    #[test]
+    fn test_to_allowed_error() {
+        let data = trend(1000);
+        for max_error in [0.001, 0.01, 0.03] {
+            let result = dct_compressor(&data, max_error, DataStats::new(&data));
+            let out = dct_to_data(data.len(), &result.compressed_data);
+            assert_eq!(calculate_error(&data, &out), result.error);
+            assert!(result.error <= max_error);
+        }
+    }
+
+    #[test]
+    fn test_smaller_than_fft_on_trends() {
+        let data = trend(1000);
+        let dct = dct_compressor(&data, 0.001, DataStats::new(&data));
+        let fft = fft_compressor(&data, 0.001, DataStats::new(&data));
+        assert!(
+            dct.compressed_data.len() < fft.compressed_data.len(),
+            "{} {}",
+            dct.compressed_data.len(),
+            fft.compressed_data.len()
+        );
+    }
+
+    #[test]
+    fn test_quantized() {
+        let data = trend(256);
+        let dct = Dct::decompress(&dct_set_quantized(&data, 5, 3));
+        assert_eq!(dct.exponent, 3);
+        assert_eq!(dct.coefficient_count(), 5);
+        assert!(dct.coefficients().all(|(_, value)| value % 8.0 == 0.0));
+    }
+
+    #[test]
+    fn test_positions_past_u16() {
+        // A full size frame alternating around its mean, all the energy is in the last coefficients
+        let data: Vec<f64> = (0..MAX_FRAME_SIZE)
+            .map(|x| if x % 2 == 0 { 110.0 } else { 90.0 })
+            .collect();
+        let result = dct_compressor(&data, 0.01, DataStats::new(&data));
+        let dct = Dct::decompress(&result.compressed_data);
+        assert!(dct.coefficients().any(|(pos, _)| pos > u16::MAX as usize));
+        assert!(dct.coefficient_count() < 16, "{}", dct.coefficient_count());
+        let out = dct_to_data(data.len(), &result.compressed_data);
+        assert!(calculate_error(&data, &out) <= 0.01);
+    }
+}
@@ -0,0 +1,248 @@
+/*
+Copyright 2024 NetApp, Inc.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    https://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use crate::compressor::CompressorResult;
+
+use super::bits::{bits_for, rice_bits, BitReader, BitWriter};
+use super::BinConfig;
+use bincode::{Decode, Encode};
+use log::{debug, trace};
+
+const DICTIONARY_COMPRESSOR_ID: u8 = 90;
+
+/// Distinct values of a frame, in ascending order
+#[derive(Encode, Decode, PartialEq, Debug)]
+enum Table {
+    /// Values that are integers, the first one as it is and the others as the difference to the previous one
+    Integers(Vec<i64>),
+    Floats(Vec<f64>),
+}
+
+impl Table {
+    fn new(values: &[f64]) -> Self {
+        // Only values that come back with the same bits, -0.0 is not an integer
+        let integer = |x: &f64| (*x as i64 as f64).to_bits() == x.to_bits();
+        if values.iter().all(integer) {
+            let mut previous = 0;
+            Table::Integers(
+                values
+                    .iter()
+                    .map(|&x| {
+                        let delta = (x as i64).wrapping_sub(previous);
+                        previous = x as i64;
+                        delta
+                    })
+                    .collect(),
+            )
+        } else {
+            Table::Floats(values.to_vec())
+        }
+    }
+
+    fn values(&self) -> Vec<f64> {
+        match self {
+            Table::Integers(deltas) => {
+                let mut previous = 0i64;
+                deltas
+                    .iter()
+                    .map(|&delta| {
+                        previous = previous.wrapping_add(delta);
+                        previous as f64
+                    })
+                    .collect()
+            }
+            Table::Floats(values) => values.clone(),
+        }
+    }
+}
+
+/// Dictionary Compressor. The distinct values of the frame go in a table, and each sample is stored as the position
+/// of its value in the table, packed in the fewest bits that fit every position (ceil(log2(k)) for k values).
+/// When the values come in runs the codes are stored once per run followed by its Rice coded length. Suits series
+/// with few states that change often (e.g. health states), where Index RLE stores a position per change.
+/// Always LOSSLESS
+#[derive(Encode, Decode, PartialEq, Debug)]
+pub struct Dictionary {
+    pub id: u8,
+    table: Table,
+    /// Codes are stored once per run, followed by the length of the run
+    pub runs: bool,
+    /// Rice parameter of the run lengths
+    run_bits: u8,
+    stream: Vec<u8>,
+}
+
+impl Dictionary {
+    pub fn new(data: &[f64]) -> Self {
+        debug!("Dictionary compressor");
+        let mut values = data.to_vec();
+        values.sort_by(f64::total_cmp);
+        values.dedup_by(|a, b| a.to_bits() == b.to_bits());
+        let code_bits = bits_for(values.len());
+        let code = |x: f64| values.binary_search_by(|v| v.total_cmp(&x)).unwrap() as u64;
+
+        // A code per sample
+        let mut writer = BitWriter::default();
+        for &x in data {
+            writer.fixed(code(x), code_bits);
+        }
+        let packed = writer.into_bytes();
+
+        // A code per run
+        let mut runs: Vec<(u64, u64)> = Vec::new();
+        for &x in data {
+            match runs.last_mut() {
+                Some((last, length)) if *last == code(x) => *length += 1,
+                _ => runs.push((code(x), 1)),
+            }
+        }
+        let lengths: Vec<u64> = runs.iter().map(|(_, length)| length - 1).collect();
+        let run_bits = rice_bits(&lengths);
+        let mut writer = BitWriter::default();
+        for (&(code, _), &length) in runs.iter().zip(&lengths) {
+            writer.fixed(code, code_bits);
+            writer.rice(length, run_bits);
+        }
+        let run_coded = writer.into_bytes();
+        trace!(
+            "Values: {} Runs: {} Packed: {} Run coded: {}",
+            values.len(),
+            runs.len(),
+            packed.len(),
+            run_coded.len()
+        );
+
+        let table = Table::new(&values);
+        if run_coded.len() < packed.len() {
+            Dictionary {
+                id: DICTIONARY_COMPRESSOR_ID,
+                table,
+                runs: true,
+                run_bits,
+                stream: run_coded,
+            }
+        } else {
+            Dictionary {
+                id: DICTIONARY_COMPRESSOR_ID,
+                table,
+                runs: false,
+                run_bits: 0,
+                stream: packed,
+            }
+        }
+    }
+
+    pub fn decompress(data: &[u8]) -> Self {
+        let config = BinConfig::get();
+        let (dictionary, _) = bincode::decode_from_slice(data, config).unwrap();
+        dictionary
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let config = BinConfig::get();
+        bincode::encode_to_vec(self, config).unwrap()
+    }
+
+    pub fn to_data(&self, frame_size: usize) -> Vec<f64> {
+        let values = self.table.values();
+        let code_bits = bits_for(values.len());
+        let value = |code: u64| values.get(code as usize).copied().unwrap_or_default();
+        let mut reader = BitReader::new(&self.stream);
+        let mut data = Vec::with_capacity(frame_size);
+        while data.len() < frame_size {
+            let x = value(reader.fixed(code_bits));
+            let length = if self.runs {
+                reader.rice(self.run_bits) as usize + 1
+            } else {
+                1
+            };
+            data.extend(std::iter::repeat(x).take(length.min(frame_size - data.len())));
+        }
+        data
+    }
+}
+
+pub fn dictionary_compressor(data: &[f64]) -> CompressorResult {
+    debug!("Initializing Dictionary Compressor");
+    let c = Dictionary::new(data);
+    CompressorResult::new(c.to_bytes(), 0.0)
+}
+
+pub fn dictionary_to_data(sample_number: usize, compressed_data: &[u8]) -> Vec<f64> {
+    let c = Dictionary::decompress(compressed_data);
+    c.to_data(sample_number)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compressor::rle::rle_compressor;
+    use crate::optimizer::utils::DataStats;
+
+    fn assert_roundtrip(data: &[f64]) -> Dictionary {
+        let result = dictionary_compressor(data);
+        let out = dictionary_to_data(data.len(), &result.compressed_data);
+        assert_eq!(out.len(), data.len());
+        for (x, y) in data.iter().zip(&out) {
+            assert_eq!(x.to_bits(), y.to_bits(), "{} {}", x, y);
+        }
+        Dictionary::decompress(&result.compressed_data)
+    }
+
+    #[test]
+    fn test_alternating_states() {
+        // Health states that change every few samples
+        let data: Vec<f64> = (0..1024).map(|x| ((x * 7 / 3) % 3) as f64).collect();
+        let dictionary = assert_roundtrip(&data);
+        assert!(!dictionary.runs);
+        let rle = rle_compressor(&data, DataStats::new(&data));
+        let size = dictionary.to_bytes().len();
+        // 2 bits per sample
+        assert!(size < 1024 / 4 + 16, "{}", size);
+        // RLE takes about a byte per run
+        assert!(
+            size * 2 < rle.compressed_data.len(),
+            "{} {}",
+            size,
+            rle.compressed_data.len()
+        );
+    }
+
+    #[test]
+    fn test_runs() {
+        let data: Vec<f64> = (0..1024).map(|x| ((x / 50) % 4) as f64 * 10.5).collect();
+        let dictionary = assert_roundtrip(&data);
+        assert!(dictionary.runs);
+        // The table takes 32 bytes, the 21 runs a few more
+        let size = dictionary.to_bytes().len();
+        assert!(size < 32 + 21 * 2, "{}", size);
+    }
+
+    #[test]
+    fn test_values() {
+        assert_roundtrip(&[-0.0, 0.0, 3.5, f64::NAN, -7.0, 3.5, f64::INFINITY]);
+        assert_roundtrip(&[-5.0, 1e15, -5.0, i64::MIN as f64]);
+        assert_roundtrip(&[2.0; 300]);
+        assert_roundtrip(&[]);
+    }
+
+    #[test]
+    fn test_integer_table() {
+        let dictionary = Dictionary::new(&[300.0, -2.0, 300.0, 7.0]);
+        assert_eq!(dictionary.table, Table::Integers(vec![-2, 9, 293]));
+        assert_eq!(dictionary.table.values(), [-2.0, 7.0, 300.0]);
+    }
+}
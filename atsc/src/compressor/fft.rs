@@ -16,17 +16,21 @@ limitations under the License.
 
 use crate::{
     optimizer::utils::DataStats,
-    utils::{error::calculate_error, next_size},
+    utils::{error::calculate_error, next_size, smallest_passing, DECIMAL_PRECISION},
 };
 use bincode::{Decode, Encode};
-use rustfft::{num_complex::Complex, FftPlanner};
+use rustfft::num_complex::Complex;
 use std::{cmp::Ordering, collections::BinaryHeap};
 
-use super::{BinConfig, CompressorResult};
-use log::{debug, error, info, trace, warn};
+use super::quantization::{coarsest_exponent, dequantize, quantize, top_exponent, Quantized};
+use super::{fft_cache::FftCache, BinConfig, CompressorResult};
+use log::{debug, error, info, trace};
 
 const FFT_COMPRESSOR_ID: u8 = 15;
-const DECIMAL_PRECISION: u8 = 5;
+/// FFT with the padding of the frame recorded after the compressor fields
+const FFT_PADDED_COMPRESSOR_ID: u8 = 16;
+/// Padded FFT with the frequencies stored as multiples of a power of two, see `Quantized`
+const FFT_QUANTIZED_COMPRESSOR_ID: u8 = 17;
 
 /// Struct to store frequencies, since bincode can't encode num_complex Complex format, this one is compatible
 // This could be a Generic to support f64, integers, etc...
@@ -60,6 +64,22 @@ impl FrequencyPoint {
             im: self.freq_img * -1.0,
         }
     }
+
+    /// Position and parts, to be quantized
+    fn parts(&self) -> (usize, (f64, f64)) {
+        (
+            self.pos.into(),
+            (self.freq_real.into(), self.freq_img.into()),
+        )
+    }
+
+    fn from_parts((pos, (real, img)): (usize, (f64, f64))) -> Self {
+        FrequencyPoint {
+            pos: pos as u16,
+            freq_real: real as f32,
+            freq_img: img as f32,
+        }
+    }
 }
 
 // This is VERY specific for this use case, DO NOT RE-USE! This NORM comparison is false for complex numbers
@@ -105,6 +125,26 @@ impl Ord for FrequencyPoint {
     }
 }
 
+/// Samples added around a frame before the FFT, so decompression can remove them
+#[derive(Encode, Decode, PartialEq, Debug, Default, Clone, Copy)]
+pub struct FramePadding {
+    /// Samples added before the frame
+    pub prefix: u32,
+    /// Length of the padded frame, the length the FFT runs on
+    pub size: u32,
+}
+
+/// How the padding continues the frame
+#[derive(PartialEq, Debug, Default, Clone, Copy)]
+pub enum PadWindow {
+    /// Repeats the first and last values
+    #[default]
+    Edge,
+    /// Goes from the last value to the first one with a raised cosine (half a Hann window), so the frame repeats
+    /// itself without a jump
+    Cosine,
+}
+
 /// FFT Compressor. Applies FFT to a signal, picks the N best frequencies, discards the rest. Always LOSSY
 #[derive(PartialEq, Debug)]
 pub struct FFT {
@@ -112,6 +152,12 @@ pub struct FFT {
     pub frequencies: Vec<FrequencyPoint>,
     pub max_value: f32,
     pub min_value: f32,
+    /// Padding of the frame, None for frames written before it was recorded
+    pub padding: Option<FramePadding>,
+    /// Exponent of the power of two the frequencies are multiples of, None for frequencies stored as they are
+    pub quantization: Option<i8>,
+    /// Padding used on compression, not stored
+    pub window: PadWindow,
     pub error: Option<f64>,
 }
 
@@ -122,9 +168,21 @@ impl Encode for FFT {
         encoder: &mut __E,
     ) -> Result<(), ::bincode::error::EncodeError> {
         Encode::encode(&self.id, encoder)?;
-        Encode::encode(&self.frequencies, encoder)?;
+        match self.quantization {
+            Some(exponent) if self.id == FFT_QUANTIZED_COMPRESSOR_ID => Encode::encode(
+                &quantize(self.frequencies.iter().map(FrequencyPoint::parts), exponent),
+                encoder,
+            )?,
+            _ => Encode::encode(&self.frequencies, encoder)?,
+        }
         Encode::encode(&self.max_value, encoder)?;
         Encode::encode(&self.min_value, encoder)?;
+        if self.id == FFT_PADDED_COMPRESSOR_ID || self.id == FFT_QUANTIZED_COMPRESSOR_ID {
+            Encode::encode(&self.padding.unwrap_or_default(), encoder)?;
+        }
+        if self.id == FFT_QUANTIZED_COMPRESSOR_ID {
+            Encode::encode(&self.quantization.unwrap_or_default(), encoder)?;
+        }
         Ok(())
     }
 }
@@ -133,11 +191,39 @@ impl Decode for FFT {
     fn decode<__D: ::bincode::de::Decoder>(
         decoder: &mut __D,
     ) -> Result<Self, ::bincode::error::DecodeError> {
+        let id = Decode::decode(decoder)?;
+        let (frequencies, points): (Vec<FrequencyPoint>, Vec<Quantized<(i32, i32)>>) =
+            if id == FFT_QUANTIZED_COMPRESSOR_ID {
+                (Vec::new(), Decode::decode(decoder)?)
+            } else {
+                (Decode::decode(decoder)?, Vec::new())
+            };
+        let max_value = Decode::decode(decoder)?;
+        let min_value = Decode::decode(decoder)?;
+        let padding = if id == FFT_PADDED_COMPRESSOR_ID || id == FFT_QUANTIZED_COMPRESSOR_ID {
+            Some(Decode::decode(decoder)?)
+        } else {
+            None
+        };
+        let (frequencies, quantization) = if id == FFT_QUANTIZED_COMPRESSOR_ID {
+            let exponent = Decode::decode(decoder)?;
+            (
+                dequantize(&points, exponent)
+                    .map(FrequencyPoint::from_parts)
+                    .collect(),
+                Some(exponent),
+            )
+        } else {
+            (frequencies, None)
+        };
         Ok(Self {
-            id: Decode::decode(decoder)?,
-            frequencies: Decode::decode(decoder)?,
-            max_value: Decode::decode(decoder)?,
-            min_value: Decode::decode(decoder)?,
+            id,
+            frequencies,
+            max_value,
+            min_value,
+            padding,
+            quantization,
+            window: PadWindow::default(),
             error: None,
         })
     }
@@ -147,13 +233,7 @@ impl<'__de> ::bincode::BorrowDecode<'__de> for FFT {
     fn borrow_decode<__D: ::bincode::de::BorrowDecoder<'__de>>(
         decoder: &mut __D,
     ) -> Result<Self, ::bincode::error::DecodeError> {
-        Ok(Self {
-            id: ::bincode::BorrowDecode::borrow_decode(decoder)?,
-            frequencies: ::bincode::BorrowDecode::borrow_decode(decoder)?,
-            max_value: ::bincode::BorrowDecode::borrow_decode(decoder)?,
-            min_value: ::bincode::BorrowDecode::borrow_decode(decoder)?,
-            error: None,
-        })
+        Decode::decode(decoder)
     }
 }
 
@@ -162,14 +242,23 @@ impl FFT {
     pub fn new(sample_count: usize, min: f64, max: f64) -> Self {
         debug!("FFT compressor: min:{} max:{}", min, max);
         FFT {
-            id: FFT_COMPRESSOR_ID,
+            id: FFT_PADDED_COMPRESSOR_ID,
             frequencies: Vec::with_capacity(sample_count),
             max_value: FFT::f64_to_f32(max),
             min_value: FFT::f64_to_f32(min),
+            padding: None,
+            quantization: None,
+            window: PadWindow::default(),
             error: None,
         }
     }
 
+    /// Sets how the frame is padded on compression
+    pub fn with_window(mut self, window: PadWindow) -> Self {
+        self.window = window;
+        self
+    }
+
     fn f64_to_f32(x: f64) -> f32 {
         let y = x as f32;
         if !(x.is_finite() && y.is_finite()) {
@@ -182,25 +271,41 @@ impl FFT {
     /// Given an array of size N, it returns the next best FFT size with the
     /// begining and the ended padded to improve Gibbs on the edges of the frame
     pub fn gibbs_sizing(data: &[f64]) -> Vec<f64> {
-        let data_len = data.len();
-        let next_size = next_size(data_len);
-        let added_len = next_size - data_len;
-        debug!("Gibbs sizing, padding with {}", added_len);
+        FFT::pad(data, PadWindow::Edge).0
+    }
+
+    /// Pads the frame to the next FFT size, half of the padding before the frame and half after it, shaped by
+    /// `window`. Returns the padded frame and the padding to remove after decompression.
+    pub fn pad(data: &[f64], window: PadWindow) -> (Vec<f64>, FramePadding) {
+        let size = next_size(data.len());
+        let added_len = size - data.len();
         let prefix_len = added_len / 2;
         let suffix_len = added_len - prefix_len;
-        // Extend the beginning and the end with the first and last value
-        let mut result = Vec::with_capacity(next_size);
-        if let Some(first) = data.first() {
-            result.resize(prefix_len, *first);
-            result.extend_from_slice(data);
-
-            if let Some(last) = data.last() {
-                for _ in 0..suffix_len {
-                    result.push(*last);
-                }
+        debug!("Padding with {} samples, {:?}", added_len, window);
+        let (first, last) = match (data.first(), data.last()) {
+            (Some(&first), Some(&last)) => (first, last),
+            _ => return (Vec::new(), FramePadding::default()),
+        };
+        // Value at position `k` of the gap between the end of the frame and its start on the next repetition
+        let gap = |k: usize| match window {
+            PadWindow::Edge if k <= suffix_len => last,
+            PadWindow::Edge => first,
+            PadWindow::Cosine => {
+                let phase = std::f64::consts::PI * k as f64 / (added_len + 1) as f64;
+                last + (first - last) * (1.0 - phase.cos()) / 2.0
             }
-        }
-        result
+        };
+        let mut result = Vec::with_capacity(size);
+        result.extend((suffix_len + 1..=added_len).map(gap));
+        result.extend_from_slice(data);
+        result.extend((1..=suffix_len).map(gap));
+        (
+            result,
+            FramePadding {
+                prefix: prefix_len as u32,
+                size: size as u32,
+            },
+        )
     }
 
     /// Rounds a number to the specified number of decimal places
@@ -227,6 +332,18 @@ impl FFT {
             .collect()
     }
 
+    /// Sorts the frequencies of `buffer` from the biggest to the smallest, dropping the empty ones
+    fn fft_rank(buffer: &[Complex<f32>]) -> Vec<FrequencyPoint> {
+        let mut ranked: Vec<FrequencyPoint> = buffer
+            .iter()
+            .enumerate()
+            .map(|(pos, &f)| FrequencyPoint::from_complex_with_position(f, pos as u16))
+            .filter(|f| f.freq_img != 0.0 || f.freq_real != 0.0)
+            .collect();
+        ranked.sort_by(|a, b| b.cmp(a));
+        ranked
+    }
+
     /// Removes the smallest frequencies from `buffer` until `max_freq` remain
     fn fft_trim(buffer: &mut [Complex<f32>], max_freq: usize) -> Vec<FrequencyPoint> {
         let mut freq_vec = Vec::with_capacity(max_freq);
@@ -264,16 +381,12 @@ impl FFT {
             debug!("Same max and min, we're done here!");
             return;
         }
-        // First thing, always try to get the data len as a power of 2.
-        let v = data.len();
-        if !v.is_power_of_two() {
-            warn!("Slow FFT, data segment is not a power of 2!");
-        }
-        let mut planner = FftPlanner::new();
-        let fft = planner.plan_fft_forward(v);
-        let mut buffer = FFT::optimize(data);
+        // Pad to a size the FFT handles fast, and to reduce the Gibbs phenomenon on the edges
+        let (g_data, padding) = FFT::pad(data, self.window);
+        self.padding = Some(padding);
+        let mut buffer = FFT::optimize(&g_data);
         // The data is processed in place, it gets back to the buffer
-        fft.process(&mut buffer);
+        FftCache::global().forward(&mut buffer);
         // We need half + 1 frequencies at most, due to the mirrored nature of FFT (signal is always real!)
         // and the first one being the dc component
         let size = (buffer.len() / 2) + 1;
@@ -282,18 +395,16 @@ impl FFT {
     }
 
     /// Compress data via FFT - EXPENSIVE
-    /// This picks a set of data, computes the FFT, and optimizes the number of frequencies to store to match
-    /// the max allowed error.
-    /// NOTE: This does not otimize for smallest possible error, just being smaller than the error.
+    /// This picks a set of data, computes the FFT, and searches the smallest number of frequencies to store that
+    /// matches the max allowed error. The spectrum is computed once, each step of the search only runs the inverse
+    /// FFT of the biggest frequencies. The error drops as frequencies are added, so the count is bisected.
+    /// If no count meets the error, all the frequencies are stored, unless the error is undefined for the data.
     pub fn compress_bounded(&mut self, data: &[f64], max_err: f64) {
         if self.max_value == self.min_value {
             debug!("Same max and min, we're done here!");
             return;
         }
 
-        if !data.len().is_power_of_two() {
-            warn!("Slow FFT, data segment is not a power of 2!");
-        }
         // Let's start from the defaults values for frequencies
         let max_freq = if 3 >= (data.len() / 100) {
             3
@@ -301,91 +412,122 @@ impl FFT {
             data.len() / 100
         };
 
-        // Should we apply a Gibbs sizing?
-        let g_data: &[f64] = if data.len() >= 128 {
-            &FFT::gibbs_sizing(data)
-        } else {
-            data
-        };
+        // Pad to a size the FFT handles fast, and to reduce the Gibbs phenomenon on the edges
+        let (g_data, padding) = FFT::pad(data, self.window);
+        self.padding = Some(padding);
+        let prefix_len = padding.prefix as usize;
 
         let len = g_data.len();
         let len_f32 = len as f32;
 
         // Clean the data
-        let mut buffer = FFT::optimize(g_data);
-
-        // Create the FFT planners
-        let mut planner = FftPlanner::new();
-        let fft = planner.plan_fft_forward(len);
-        let ifft = planner.plan_fft_inverse(len);
+        let mut buffer = FFT::optimize(&g_data);
 
         // FFT calculations
-        fft.process(&mut buffer);
-        let mut buff_clone = buffer.clone();
+        let cache = FftCache::global();
+        cache.forward(&mut buffer);
         // We need half + 1 frequencies at most, due to the mirrored nature of FFT (signal is always real!)
         // and the first one being the dc component
-        let size = (buff_clone.len() / 2) + 1;
-        buff_clone.truncate(size);
-        // To make sure we run the first cycle
-        let mut current_err = max_err + 1.0;
-        let mut jump: usize = 0;
+        buffer.truncate((len / 2) + 1);
+        // Biggest frequencies first, storing N frequencies is storing the first N
+        let ranked = FFT::fft_rank(&buffer);
+        if ranked.is_empty() {
+            self.frequencies = ranked;
+            self.error = Some(0.0);
+            return;
+        }
+        // Every step runs the inverse FFT on the same buffer
+        let mut idata = vec![Complex::default(); len];
         let mut iterations = 0;
-        // Aproximation. Faster convergence
-        while ((max_err * 1000.0) as i32) < ((current_err * 1000.0) as i32) {
+        let compressor = &*self;
+        let mut error_of = |frequencies: &[FrequencyPoint]| -> f64 {
             iterations += 1;
-            self.frequencies = FFT::fft_trim(&mut buff_clone, max_freq + jump);
             // Inverse FFT and error check
-            let mut idata = self.get_mirrored_freqs(len);
+            FFT::fill_mirrored_freqs(frequencies, &mut idata);
             // run the ifft
-            ifft.process(&mut idata);
-            let out_data: Vec<f64> = idata
+            cache.inverse(&mut idata);
+            // The error is over the samples that are decompressed, not the Gibbs padding
+            let out_data: Vec<f64> = idata[prefix_len..prefix_len + data.len()]
                 .iter()
-                .map(|&f| self.round(f.re / len_f32, DECIMAL_PRECISION.into()))
+                .map(|&f| compressor.round(f.re / len_f32, DECIMAL_PRECISION))
                 .collect();
-            current_err = calculate_error(g_data, &out_data);
-            trace!("Current Err: {}", current_err);
-            // Max iterations is 22 (We start at 10%, we can go to 95% and 1% at a time)
-            match iterations {
-                1..=17 => jump += (max_freq / 2).max(1),
-                18..=22 => jump += (max_freq / 10).max(1),
-                _ => break,
-            }
+            calculate_error(data, &out_data)
+        };
+        let mut errors: Vec<Option<f64>> = vec![None; ranked.len() + 1];
+        let mut error_with = |freqs: usize| -> f64 {
+            *errors[freqs].get_or_insert_with(|| {
+                let err = error_of(&ranked[..freqs]);
+                trace!("Freqs: {} Current Err: {}", freqs, err);
+                err
+            })
+        };
+        let mut freqs = smallest_passing(1, ranked.len(), max_freq, |freqs| {
+            error_with(freqs) <= max_err
+        });
+        // The error can't be computed for some data (e.g. zeros), then there is nothing to search for
+        if !error_with(freqs).is_finite() {
+            freqs = max_freq.min(ranked.len());
         }
+        let current_err = error_with(freqs);
+        let kept = &ranked[..freqs];
+
+        // Quantization makes the error grow as the step gets coarser, search the coarsest step still in the bound.
+        // If the bound can't be met, the quantization can't make the error worse.
+        let allowed_err = max_err.max(current_err);
+        let (exponent, quantized_err) = coarsest_exponent(
+            top_exponent(kept.iter().map(|f| f.parts().1)),
+            |exponent| error_of(&FFT::quantize(kept, exponent)),
+            |err| err <= allowed_err,
+        );
+
+        self.frequencies = kept.to_vec();
         self.error = Some(current_err);
+        if quantized_err <= allowed_err {
+            let plain_size = self.to_bytes().len();
+            self.quantize_frequencies(exponent);
+            if self.to_bytes().len() < plain_size {
+                self.error = Some(quantized_err);
+            } else {
+                // Few frequencies with big multiples, they are smaller as they are
+                self.id = FFT_PADDED_COMPRESSOR_ID;
+                self.quantization = None;
+                self.frequencies = kept.to_vec();
+            }
+        }
         debug!(
-            "Iterations to convergence: {}, Freqs P:{} S:{}, Error: {}",
+            "Iterations to convergence: {}, Freqs P:{} S:{}, Quantization: {:?}, Error: {:?}",
             iterations,
-            jump + max_freq,
+            max_freq,
             self.frequencies.len(),
-            current_err
+            self.quantization,
+            self.error
         );
     }
 
+    /// Stores the frequencies as multiples of 2^`exponent`
+    pub fn quantize_frequencies(&mut self, exponent: i8) {
+        self.frequencies = FFT::quantize(&self.frequencies, exponent);
+        self.id = FFT_QUANTIZED_COMPRESSOR_ID;
+        self.quantization = Some(exponent);
+    }
+
+    /// Rounds the frequencies to multiples of 2^`exponent`, sorted by position. The ones that round to 0 are dropped.
+    fn quantize(frequencies: &[FrequencyPoint], exponent: i8) -> Vec<FrequencyPoint> {
+        let quantized = quantize(frequencies.iter().map(FrequencyPoint::parts), exponent);
+        dequantize(&quantized, exponent)
+            .map(FrequencyPoint::from_parts)
+            .collect()
+    }
+
     /// Compresses data via FFT
     /// The set of frequencies to store is 1/100 of the data length OR 3, which is bigger.
     pub fn compress(&mut self, data: &[f64]) {
-        if self.max_value == self.min_value {
-            debug!("Same max and min, we're done here!");
-            return;
-        }
-        // First thing, always try to get the data len as a power of 2.
         let v = data.len();
         let max_freq = if 3 >= (v / 100) { 3 } else { v / 100 };
         debug!("Setting max_freq count to: {}", max_freq);
-        if !v.is_power_of_two() {
-            warn!("Slow FFT, data segment is not a power of 2!");
-        }
-        let mut planner = FftPlanner::new();
-        let fft = planner.plan_fft_forward(v);
-        let mut buffer = FFT::optimize(data);
-        // The data is processed in place, it gets back to the buffer
-        fft.process(&mut buffer);
-        // We need half + 1 frequencies at most, due to the mirrored nature of FFT (signal is always real!)
-        // and the first one being the dc component
-        let size = (buffer.len() / 2) + 1;
-        buffer.truncate(size);
-        self.frequencies = FFT::fft_trim(&mut buffer, max_freq);
+        self.compress_hinted(data, max_freq);
     }
+
     pub fn decompress(data: &[u8]) -> Self {
         let config = BinConfig::get();
         let (fft, _) = bincode::decode_from_slice(data, config).unwrap();
@@ -399,16 +541,18 @@ impl FFT {
 
     /// Gets the full sized array with the frequencies mirrored
     fn get_mirrored_freqs(&self, len: usize) -> Vec<Complex<f32>> {
+        let mut data = vec![Complex::default(); len];
+        FFT::fill_mirrored_freqs(&self.frequencies, &mut data);
+        data
+    }
+
+    /// Overwrites `data` with the frequencies mirrored, its length is the length of the ifft
+    fn fill_mirrored_freqs(frequencies: &[FrequencyPoint], data: &mut [Complex<f32>]) {
         // Because we are dealing with Real inputs, we only store half the frequencies, but
         // we need all for the ifft
-        let mut data = vec![
-            Complex {
-                re: 0.0f32,
-                im: 0.0f32
-            };
-            len
-        ];
-        for f in &self.frequencies {
+        let len = data.len();
+        data.fill(Complex::default());
+        for f in frequencies {
             let pos = f.pos as usize;
             data[pos] = f.to_complex();
             // fo doesn't mirror
@@ -416,9 +560,8 @@ impl FFT {
                 continue;
             }
             // Mirror and invert the imaginary part
-            data[len - pos] = f.to_inv_complex()
+            data[len - pos] = f.to_inv_complex();
         }
-        data
     }
 
     /// Returns an array of data
@@ -429,7 +572,14 @@ impl FFT {
             return vec![self.max_value as f64; frame_size];
         }
         // Was this processed to reduce the Gibbs phenomeon?
-        let trim_sizes = if frame_size >= 128 {
+        let trim_sizes = if let Some(padding) = self.padding {
+            let prefix_len = padding.prefix as usize;
+            (
+                prefix_len,
+                (padding.size as usize).saturating_sub(prefix_len + frame_size),
+            )
+        } else if frame_size >= 128 {
+            // Frames from before the padding was recorded, padded to the next size from 128 samples
             let added_len = next_size(frame_size) - frame_size;
             let prefix_len = added_len / 2;
             let suffix_len = added_len - prefix_len;
@@ -444,11 +594,8 @@ impl FFT {
         let gibbs_frame_size = frame_size + trim_sizes.0 + trim_sizes.1;
         // Vec to process the ifft
         let mut data = self.get_mirrored_freqs(gibbs_frame_size);
-        // Plan the ifft
-        let mut planner = FftPlanner::new();
-        let fft = planner.plan_fft_inverse(gibbs_frame_size);
         // run the ifft
-        fft.process(&mut data);
+        FftCache::global().inverse(&mut data);
         // We need this for normalization
         let len = gibbs_frame_size as f32;
         // We only need the real part
@@ -457,7 +604,7 @@ impl FFT {
             .skip(trim_sizes.0)
             .take(data.len() - trim_sizes.0 - trim_sizes.1)
             // We only need the real part
-            .map(|&f| self.round(f.re / len, DECIMAL_PRECISION.into()))
+            .map(|&f| self.round(f.re / len, DECIMAL_PRECISION))
             .collect()
     }
 }
@@ -543,6 +690,17 @@ pub fn fft_set(data: &[f64], freqs: usize) -> Vec<u8> {
     c.to_bytes()
 }
 
+/// Compresses with the hinted number of frequencies, stored as multiples of 2^`exponent`
+pub fn fft_set_quantized(data: &[f64], freqs: usize, exponent: i8) -> Vec<u8> {
+    let stats = DataStats::new(data);
+    let mut c = FFT::new(data.len(), stats.min, stats.max);
+    c.compress_hinted(data, freqs);
+    if c.max_value != c.min_value {
+        c.quantize_frequencies(exponent);
+    }
+    c.to_bytes()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -553,12 +711,73 @@ mod tests {
         assert_eq!(
             fft_set(&vector1, 2),
             [
-                15, 2, 0, 0, 0, 152, 65, 0, 0, 0, 0, 4, 0, 0, 96, 192, 102, 144, 138, 64, 0, 0,
-                160, 64, 0, 0, 128, 63
+                16, 2, 0, 0, 0, 248, 65, 0, 0, 0, 0, 1, 18, 222, 189, 64, 70, 48, 12, 65, 0, 0,
+                160, 64, 0, 0, 128, 63, 2, 16
             ]
         );
     }
 
+    #[test]
+    fn test_unpadded_frame() {
+        // Written before the padding was recorded, frames under 128 samples were not padded
+        let compressed_data = [
+            15, 2, 0, 0, 0, 152, 65, 0, 0, 0, 0, 4, 0, 0, 96, 192, 102, 144, 138, 64, 0, 0, 160,
+            64, 0, 0, 128, 63,
+        ];
+        let fft = FFT::decompress(&compressed_data);
+        assert_eq!(fft.padding, None);
+        assert_eq!(
+            fft.to_data(12),
+            [1.0, 1.25, 2.5, 1.0, 1.25, 2.5, 1.0, 1.25, 2.5, 1.0, 1.25, 2.5]
+        );
+    }
+
+    #[test]
+    fn test_pad() {
+        let data = [1.0, 2.0, 3.0, 5.0, 4.0];
+        // 5 samples go to 6, the sample added goes after the frame
+        let (padded, padding) = FFT::pad(&data, PadWindow::Edge);
+        assert_eq!(padded, [1.0, 2.0, 3.0, 5.0, 4.0, 4.0]);
+        assert_eq!(padding, FramePadding { prefix: 0, size: 6 });
+        // 229 samples go to 243, the cosine goes from the last value to the first across the padding
+        let data: Vec<f64> = (0..229).map(|x| x as f64).collect();
+        let (padded, padding) = FFT::pad(&data, PadWindow::Cosine);
+        assert_eq!(
+            padding,
+            FramePadding {
+                prefix: 7,
+                size: 243
+            }
+        );
+        assert_eq!(&padded[7..236], &data[..]);
+        assert!(padded[236..].windows(2).all(|w| w[0] > w[1]));
+        assert!(padded[..8].windows(2).all(|w| w[0] > w[1]));
+        assert!(padded[242] > padded[0]);
+    }
+
+    #[test]
+    fn test_tail_frame() {
+        // The size of a frame left at the end of the data
+        let data: Vec<f64> = (0..229)
+            .map(|x| 50.0 + (x as f64 / 15.0).sin() * 20.0 + x as f64 / 10.0)
+            .collect();
+        let compressed_data = fft_allowed_error(&data, 0.01).compressed_data;
+        let fft = FFT::decompress(&compressed_data);
+        assert_eq!(
+            fft.padding,
+            Some(FramePadding {
+                prefix: 7,
+                size: 243
+            })
+        );
+        let out = fft.to_data(data.len());
+        assert!(calculate_error(&data, &out) <= 0.01);
+        // Padding towards the start of the frame needs less frequencies than repeating the edges
+        let mut cosine = FFT::new(data.len(), 30.0, 93.0).with_window(PadWindow::Cosine);
+        cosine.compress_bounded(&data, 0.01);
+        assert!(cosine.frequencies.len() < fft.frequencies.len());
+    }
+
     #[test]
     fn test_to_lossless_data() {
         let vector1 = vec![1.0, 1.0, 1.0, 1.0, 2.0, 1.0, 1.0, 1.0, 3.0, 1.0, 1.0, 5.0];
@@ -571,7 +790,8 @@ mod tests {
     fn test_to_lossy_data() {
         let vector1 = vec![1.0, 1.0, 1.0, 1.0, 2.0, 1.0, 1.0, 1.0, 3.0, 1.0, 1.0, 5.0];
         let lossy_vec = vec![
-            1.0, 1.87201, 2.25, 1.0, 1.82735, 1.689, 1.82735, 1.0, 2.75, 1.189, 1.0, 3.311,
+            1.0, 1.0, 1.0, 1.40699, 1.72073, 1.59833, 1.19583, 1.0, 1.10539, 1.90036, 3.03272,
+            3.99834,
         ];
         let compressed_data = fft(&vector1);
         let out = fft_to_data(vector1.len(), &compressed_data);
@@ -588,6 +808,67 @@ mod tests {
         assert!(e <= 0.01);
     }
 
+    #[test]
+    fn test_bounded_search_is_minimal() {
+        // Long enough for the Gibbs padding, the error has to hold on the decompressed samples
+        let data: Vec<f64> = (0..1000)
+            .map(|x| {
+                let x = x as f64;
+                100.0 + (x / 20.0).sin() * 10.0 + (x / 3.0).cos() * 2.0 + (x % 7.0)
+            })
+            .collect();
+        for max_error in [0.005, 0.01, 0.03] {
+            let compressed = fft_allowed_error(&data, max_error).compressed_data;
+            let fft = FFT::decompress(&compressed);
+            let out = fft.to_data(data.len());
+            assert!(calculate_error(&data, &out) <= max_error);
+            // One frequency less misses the error
+            let freqs = fft.frequencies.len();
+            let fewer = FFT::decompress(&fft_set(&data, freqs - 1)).to_data(data.len());
+            assert!(calculate_error(&data, &fewer) > max_error);
+        }
+    }
+
+    #[test]
+    fn test_quantized_frequencies() {
+        let data: Vec<f64> = (0..1000)
+            .map(|x| {
+                let x = x as f64;
+                100.0 + (x / 20.0).sin() * 10.0 + (x / 3.0).cos() * 2.0 + (x % 7.0)
+            })
+            .collect();
+        let compressed = fft_allowed_error(&data, 0.01);
+        let fft = FFT::decompress(&compressed.compressed_data);
+        assert_eq!(fft.id, FFT_QUANTIZED_COMPRESSOR_ID);
+        assert_eq!(fft.to_bytes(), compressed.compressed_data);
+        // The reported error accounts for the quantization
+        let out = fft.to_data(data.len());
+        assert_eq!(calculate_error(&data, &out), compressed.error);
+        assert!(compressed.error <= 0.01);
+        // Same frequencies stored as they are take more space
+        let exponent = fft.quantization.unwrap();
+        let plain = FFT {
+            id: FFT_PADDED_COMPRESSOR_ID,
+            quantization: None,
+            ..fft
+        };
+        assert!(compressed.compressed_data.len() < plain.to_bytes().len());
+        let step = 2f32.powi(exponent.into());
+        assert!(plain.frequencies.windows(2).all(|w| w[0].pos < w[1].pos));
+        assert!(plain
+            .frequencies
+            .iter()
+            .all(|f| (f.freq_real / step).fract() == 0.0 && (f.freq_img / step).fract() == 0.0));
+    }
+
+    #[test]
+    fn test_hinted_large_frame() {
+        let vector1: Vec<f64> = (0..256).map(|x| (x as f64 / 8.0).sin() + 2.0).collect();
+        let compressed_data = fft_set(&vector1, 256);
+        let out = fft_to_data(vector1.len(), &compressed_data);
+        assert!(calculate_error(&vector1, &out) < 0.001);
+    }
+
     #[test]
     fn test_gibbs_sizing() {
         let mut vector1 = vec![2.0; 2048];
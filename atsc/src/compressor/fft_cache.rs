@@ -0,0 +1,131 @@
+/*
+Copyright 2024 NetApp, Inc.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    https://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use log::trace;
+use rustfft::{num_complex::Complex, FftDirection, FftPlanner};
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard, OnceLock, PoisonError};
+
+/// Scratch buffers are kept per FFT length and direction, `true` being the inverse
+type ScratchKey = (usize, bool);
+
+/// FFT plans and scratch buffers shared by every compression and decompression, so frames of the same size only
+/// plan once. Safe to use from several threads, the locks are only held to get or return a plan or buffer.
+pub struct FftCache {
+    /// The planner keeps the plans by length and direction
+    planner: Mutex<FftPlanner<f32>>,
+    /// Scratch buffers not in use, at most one per thread that ran an FFT of that length and direction
+    scratch: Mutex<HashMap<ScratchKey, Vec<Vec<Complex<f32>>>>>,
+}
+
+static GLOBAL_CACHE: OnceLock<FftCache> = OnceLock::new();
+
+/// Gets the lock even if another thread panicked with it, the cache is still valid
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+impl FftCache {
+    pub fn new() -> Self {
+        FftCache {
+            planner: Mutex::new(FftPlanner::new()),
+            scratch: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Cache used by the FFT compressor
+    pub fn global() -> &'static FftCache {
+        GLOBAL_CACHE.get_or_init(FftCache::new)
+    }
+
+    /// Runs the FFT of `buffer` in place, in the given direction. The output is not normalized.
+    pub fn process(&self, buffer: &mut [Complex<f32>], direction: FftDirection) {
+        let len = buffer.len();
+        let fft = lock(&self.planner).plan_fft(len, direction);
+        let key = (len, direction == FftDirection::Inverse);
+        let pooled = lock(&self.scratch)
+            .get_mut(&key)
+            .and_then(|pool| pool.pop());
+        let mut scratch = pooled.unwrap_or_else(|| {
+            trace!("New FFT scratch buffer for {:?}", key);
+            vec![Complex::default(); fft.get_inplace_scratch_len()]
+        });
+        fft.process_with_scratch(buffer, &mut scratch);
+        lock(&self.scratch).entry(key).or_default().push(scratch);
+    }
+
+    pub fn forward(&self, buffer: &mut [Complex<f32>]) {
+        self.process(buffer, FftDirection::Forward)
+    }
+
+    pub fn inverse(&self, buffer: &mut [Complex<f32>]) {
+        self.process(buffer, FftDirection::Inverse)
+    }
+}
+
+impl Default for FftCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_same_as_planner() {
+        let input: Vec<Complex<f32>> = (0..288)
+            .map(|x| Complex::new((x as f32 / 10.0).sin() * 5.0 + 7.0, 0.0))
+            .collect();
+        let mut planner = FftPlanner::new();
+        let mut expected = input.clone();
+        planner.plan_fft_forward(input.len()).process(&mut expected);
+        let cache = FftCache::new();
+        // Second run reuses the plan and the scratch buffer
+        for _ in 0..2 {
+            let mut buffer = input.clone();
+            cache.forward(&mut buffer);
+            assert_eq!(buffer, expected);
+        }
+        assert_eq!(lock(&cache.scratch)[&(288, false)].len(), 1);
+        // Back to the input, once normalized
+        cache.inverse(&mut expected);
+        for (out, original) in expected.iter().zip(&input) {
+            assert!((out.re / 288.0 - original.re).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn test_shared_between_threads() {
+        let cache = FftCache::new();
+        let input: Vec<Complex<f32>> = (0..256).map(|x| Complex::new(x as f32, 0.0)).collect();
+        let mut expected = input.clone();
+        cache.forward(&mut expected);
+        std::thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..10 {
+                        let mut buffer = input.clone();
+                        cache.forward(&mut buffer);
+                        assert_eq!(buffer, expected);
+                    }
+                });
+            }
+        });
+        assert!(lock(&cache.scratch)[&(256, false)].len() <= 4);
+    }
+}
@@ -0,0 +1,236 @@
+/*
+Copyright 2024 NetApp, Inc.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    https://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+//! Interpolations of the points stored by the Polynomial compressor. Each one gets the positions of the points,
+//! sorted and without repetitions, and their values, and returns the `frame_size` samples of the frame. Samples
+//! past the last point take its value.
+
+/// Straight lines between the points
+pub fn linear(positions: &[usize], values: &[f64], frame_size: usize) -> Vec<f64> {
+    let slopes = slopes(positions, values);
+    evaluate(positions, values, frame_size, |k, x| {
+        values[k] + slopes[k] * (x - positions[k]) as f64
+    })
+}
+
+/// Natural cubic spline, the curve with continuous first and second derivatives that is straight at both ends.
+/// Smoothest of the interpolations, but it overshoots next to steps.
+pub fn natural_spline(positions: &[usize], values: &[f64], frame_size: usize) -> Vec<f64> {
+    let n = positions.len();
+    if n < 3 {
+        return linear(positions, values, frame_size);
+    }
+    let widths = widths(positions);
+    let slopes = slopes(positions, values);
+    // Second derivatives at the points, zero at the ends. Tridiagonal system solved with the Thomas algorithm.
+    let mut second = vec![0.0; n];
+    let mut diagonal = vec![0.0; n];
+    let mut rhs = vec![0.0; n];
+    for i in 1..n - 1 {
+        diagonal[i] = 2.0 * (widths[i - 1] + widths[i]);
+        rhs[i] = 6.0 * (slopes[i] - slopes[i - 1]);
+        if i > 1 {
+            let factor = widths[i - 1] / diagonal[i - 1];
+            diagonal[i] -= factor * widths[i - 1];
+            rhs[i] -= factor * rhs[i - 1];
+        }
+    }
+    for i in (1..n - 1).rev() {
+        second[i] = (rhs[i] - widths[i] * second[i + 1]) / diagonal[i];
+    }
+    evaluate(positions, values, frame_size, |k, x| {
+        let h = widths[k];
+        let (left, right) = ((x - positions[k]) as f64, (positions[k + 1] - x) as f64);
+        second[k] * right.powi(3) / (6.0 * h)
+            + second[k + 1] * left.powi(3) / (6.0 * h)
+            + (values[k] / h - second[k] * h / 6.0) * right
+            + (values[k + 1] / h - second[k + 1] * h / 6.0) * left
+    })
+}
+
+/// Monotone piecewise cubic (PCHIP, Fritsch-Carlson). Between two points the curve never goes past them, so
+/// monotonic data stays monotonic and there is no overshoot.
+pub fn pchip(positions: &[usize], values: &[f64], frame_size: usize) -> Vec<f64> {
+    let n = positions.len();
+    if n < 3 {
+        return linear(positions, values, frame_size);
+    }
+    let widths = widths(positions);
+    let slopes = slopes(positions, values);
+    let mut derivatives = vec![0.0; n];
+    for k in 1..n - 1 {
+        let (before, after) = (slopes[k - 1], slopes[k]);
+        // Flat at local extremes
+        if before * after > 0.0 {
+            // Weighted harmonic mean of the slopes around the point
+            let w1 = 2.0 * widths[k] + widths[k - 1];
+            let w2 = widths[k] + 2.0 * widths[k - 1];
+            derivatives[k] = (w1 + w2) / (w1 / before + w2 / after);
+        }
+    }
+    derivatives[0] = pchip_end(widths[0], widths[1], slopes[0], slopes[1]);
+    derivatives[n - 1] = pchip_end(widths[n - 2], widths[n - 3], slopes[n - 2], slopes[n - 3]);
+    hermite(positions, values, frame_size, &derivatives)
+}
+
+/// Derivative at an end point from the first two slopes, shape preserving
+fn pchip_end(h0: f64, h1: f64, m0: f64, m1: f64) -> f64 {
+    let derivative = ((2.0 * h0 + h1) * m0 - h0 * m1) / (h0 + h1);
+    if derivative.signum() != m0.signum() || m0 == 0.0 {
+        0.0
+    } else if m0.signum() != m1.signum() && derivative.abs() > 3.0 * m0.abs() {
+        3.0 * m0
+    } else {
+        derivative
+    }
+}
+
+/// Akima spline, the derivative at each point follows the side where the slopes change the least. Overshoots
+/// less than the natural spline and doesn't wiggle around isolated jumps.
+pub fn akima(positions: &[usize], values: &[f64], frame_size: usize) -> Vec<f64> {
+    let n = positions.len();
+    if n < 3 {
+        return linear(positions, values, frame_size);
+    }
+    let slopes = slopes(positions, values);
+    // Two slopes extrapolated at each end
+    let mut extended = Vec::with_capacity(n + 3);
+    let (first, second) = (slopes[0], slopes[1]);
+    extended.push(3.0 * first - 2.0 * second);
+    extended.push(2.0 * first - second);
+    extended.extend_from_slice(&slopes);
+    let (last, before_last) = (slopes[n - 2], slopes[n - 3]);
+    extended.push(2.0 * last - before_last);
+    extended.push(3.0 * last - 2.0 * before_last);
+    // Point k sits between slopes k + 1 and k + 2 of the extended ones
+    let derivatives: Vec<f64> = (0..n)
+        .map(|k| {
+            let m = &extended[k..k + 4];
+            let (w1, w2) = ((m[3] - m[2]).abs(), (m[1] - m[0]).abs());
+            if w1 + w2 == 0.0 {
+                (m[1] + m[2]) / 2.0
+            } else {
+                (w1 * m[1] + w2 * m[2]) / (w1 + w2)
+            }
+        })
+        .collect();
+    hermite(positions, values, frame_size, &derivatives)
+}
+
+/// Cubic Hermite curve through the points with the provided derivatives
+fn hermite(
+    positions: &[usize],
+    values: &[f64],
+    frame_size: usize,
+    derivatives: &[f64],
+) -> Vec<f64> {
+    evaluate(positions, values, frame_size, |k, x| {
+        let h = (positions[k + 1] - positions[k]) as f64;
+        let t = (x - positions[k]) as f64 / h;
+        let (t2, t3) = (t * t, t * t * t);
+        (2.0 * t3 - 3.0 * t2 + 1.0) * values[k]
+            + (t3 - 2.0 * t2 + t) * h * derivatives[k]
+            + (-2.0 * t3 + 3.0 * t2) * values[k + 1]
+            + (t3 - t2) * h * derivatives[k + 1]
+    })
+}
+
+fn widths(positions: &[usize]) -> Vec<f64> {
+    positions.windows(2).map(|w| (w[1] - w[0]) as f64).collect()
+}
+
+fn slopes(positions: &[usize], values: &[f64]) -> Vec<f64> {
+    positions
+        .windows(2)
+        .zip(values.windows(2))
+        .map(|(p, v)| (v[1] - v[0]) / (p[1] - p[0]) as f64)
+        .collect()
+}
+
+/// Samples of the frame, `segment(k, x)` gives the value at `x` between point `k` and the next one
+fn evaluate(
+    positions: &[usize],
+    values: &[f64],
+    frame_size: usize,
+    segment: impl Fn(usize, usize) -> f64,
+) -> Vec<f64> {
+    let mut out = Vec::with_capacity(frame_size);
+    let mut k = 0;
+    for x in 0..frame_size {
+        while k + 1 < positions.len() && positions[k + 1] <= x {
+            k += 1;
+        }
+        let value = match values.get(k) {
+            Some(&value) if x == positions[k] || k + 1 == positions.len() => value,
+            Some(_) => segment(k, x),
+            None => 0.0,
+        };
+        out.push(value);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POSITIONS: [usize; 5] = [0, 2, 3, 7, 9];
+    const STEP: [f64; 5] = [1.0, 1.0, 1.0, 5.0, 5.0];
+
+    #[test]
+    fn test_goes_through_points() {
+        let values = [3.0, -1.0, 2.0, 8.0, 4.0];
+        for interpolation in [linear, natural_spline, pchip, akima] {
+            let out = interpolation(&POSITIONS, &values, 12);
+            assert_eq!(out.len(), 12);
+            for (&pos, &value) in POSITIONS.iter().zip(&values) {
+                assert!((out[pos] - value).abs() < 1e-9, "{} {}", out[pos], value);
+            }
+            // Past the last point
+            assert_eq!(out[11], 4.0);
+        }
+    }
+
+    #[test]
+    fn test_reproduces_lines() {
+        let values: Vec<f64> = POSITIONS.iter().map(|&x| 2.0 * x as f64 - 3.0).collect();
+        for interpolation in [linear, natural_spline, pchip, akima] {
+            let out = interpolation(&POSITIONS, &values, 10);
+            for (x, y) in out.iter().enumerate() {
+                assert!((y - (2.0 * x as f64 - 3.0)).abs() < 1e-9, "{} {}", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn test_pchip_is_monotonic() {
+        let out = pchip(&POSITIONS, &STEP, 10);
+        assert!(out.windows(2).all(|w| w[0] <= w[1]), "{:?}", out);
+        assert!(out.iter().all(|&y| (1.0..=5.0).contains(&y)));
+        // The natural spline overshoots the step
+        let out = natural_spline(&POSITIONS, &STEP, 10);
+        assert!(out.iter().any(|&y| !(1.0..=5.0).contains(&y)), "{:?}", out);
+    }
+
+    #[test]
+    fn test_few_points() {
+        for interpolation in [linear, natural_spline, pchip, akima] {
+            assert_eq!(interpolation(&[0], &[2.0], 3), [2.0, 2.0, 2.0]);
+            assert_eq!(interpolation(&[0, 2], &[2.0, 4.0], 3), [2.0, 3.0, 4.0]);
+            assert!(interpolation(&[], &[], 2).iter().all(|&y| y == 0.0));
+        }
+    }
+}
@@ -0,0 +1,593 @@
+/*
+Copyright 2024 NetApp, Inc.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    https://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use crate::{
+    compressor::CompressorResult,
+    optimizer::utils::DataStats,
+    utils::{error::calculate_error, round_and_limit_f64, smallest_passing, DECIMAL_PRECISION},
+};
+
+use super::quantization::{
+    coarsest_exponent, dequantize, fine_exponent, quantize, top_exponent, Quantized,
+};
+use super::BinConfig;
+use bincode::{Decode, Encode};
+use log::{debug, info, trace};
+use std::sync::OnceLock;
+
+const MDCT_COMPRESSOR_ID: u8 = 20;
+/// Samples between the start of two blocks, each block spans twice as many
+pub const BLOCK_SIZE: usize = 64;
+/// Share of the error bound of a frame left to the first block of the next frame, that overlaps its last samples
+const OVERLAP_ERROR_SHARE: f64 = 0.25;
+
+static TRANSFORM: OnceLock<Transform> = OnceLock::new();
+
+/// Samples of the frames around the one being compressed, so its blocks overlap them
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Neighbours<'a> {
+    /// Samples of the previous frame, if it is MDCT
+    pub before: &'a [f64],
+    /// Samples of the next frame, if it is MDCT. Without them the frame doesn't overlap the next one.
+    pub after: &'a [f64],
+}
+
+/// Window and cosines of the transform of a block size
+struct Transform {
+    block_size: usize,
+    /// Sine window over the samples of a block, the windows of two overlapping blocks add up to 1 when squared
+    window: Vec<f64>,
+    /// cos(PI / M * (n + 1/2 + M/2) * (k + 1/2)) of coefficient k and sample n, at k * 2M + n
+    cosines: Vec<f64>,
+}
+
+impl Transform {
+    fn new(block_size: usize) -> Self {
+        let span = 2 * block_size;
+        let m = block_size as f64;
+        let window = (0..span)
+            .map(|n| (std::f64::consts::PI * (n as f64 + 0.5) / (2.0 * m)).sin())
+            .collect();
+        let cosines = (0..block_size)
+            .flat_map(|k| {
+                (0..span).map(move |n| {
+                    (std::f64::consts::PI / m * (n as f64 + 0.5 + m / 2.0) * (k as f64 + 0.5)).cos()
+                })
+            })
+            .collect();
+        Transform {
+            block_size,
+            window,
+            cosines,
+        }
+    }
+
+    /// Runs `f` with the transform of `block_size`, the one of the default size is only built once
+    fn with<R>(block_size: usize, f: impl FnOnce(&Transform) -> R) -> R {
+        if block_size == BLOCK_SIZE {
+            f(TRANSFORM.get_or_init(|| Transform::new(BLOCK_SIZE)))
+        } else {
+            f(&Transform::new(block_size))
+        }
+    }
+
+    /// Coefficients of a block of 2 * block_size samples
+    fn forward(&self, block: &[f64], coefficients: &mut [f64]) {
+        let span = 2 * self.block_size;
+        for (k, coefficient) in coefficients.iter_mut().enumerate() {
+            let cosines = &self.cosines[k * span..(k + 1) * span];
+            *coefficient = block
+                .iter()
+                .zip(&self.window)
+                .zip(cosines)
+                .map(|((x, w), c)| x * w * c)
+                .sum();
+        }
+    }
+
+    /// Adds the samples of coefficient `k` to the 2 * block_size samples of its block
+    fn add_inverse(&self, k: usize, value: f64, block: &mut [f64]) {
+        let span = 2 * self.block_size;
+        let scale = 2.0 * value / self.block_size as f64;
+        let cosines = &self.cosines[k * span..(k + 1) * span];
+        for ((x, w), c) in block.iter_mut().zip(&self.window).zip(cosines) {
+            *x += scale * w * c;
+        }
+    }
+}
+
+/// MDCT (Modified Discrete Cosine Transform) compressor. Splits the frame in blocks that overlap half of the next
+/// one, windowed so the overlapping halves add up to the data, and keeps the biggest coefficients. The first block
+/// starts before the frame and overlaps the previous frame, the last one overlaps the next frame unless the frame
+/// is trailing. Frames decompressed together join without the jumps of frames transformed on their own.
+/// Always LOSSY
+#[derive(Encode, Decode, PartialEq, Debug)]
+pub struct Mdct {
+    pub id: u8,
+    /// Samples between the start of two blocks
+    pub block_size: u16,
+    /// The blocks go past the end of the frame, it doesn't need the next frame to be decompressed
+    pub trailing: bool,
+    /// Exponent of the power of two the coefficients are multiples of
+    pub exponent: i8,
+    /// Kept coefficients, sorted by position. The position is the block times the block size plus the coefficient.
+    coefficients: Vec<Quantized<i32>>,
+    pub max_value: f32,
+    pub min_value: f32,
+}
+
+/// MDCT frame decompressed on its own, to be joined with its neighbours
+#[derive(Debug, Clone, PartialEq)]
+pub struct LappedSamples {
+    /// Added to the last samples of the previous frame, if that one is MDCT and not trailing
+    pub overlap: Vec<f64>,
+    /// Samples of the frame. If it is not trailing, the last block size samples are missing the overlap of the
+    /// next frame.
+    pub samples: Vec<f64>,
+    pub trailing: bool,
+    min_value: f64,
+    max_value: f64,
+}
+
+impl LappedSamples {
+    /// Rounds and limits the samples of the frame, once the overlaps are added
+    pub fn finish(&self, samples: &mut [f64]) {
+        for x in samples.iter_mut() {
+            *x = round_and_limit_f64(*x, self.min_value, self.max_value, DECIMAL_PRECISION);
+        }
+    }
+}
+
+/// Coefficients of a frame, and what is needed to check the error of keeping some of them
+struct Analysis {
+    block_size: usize,
+    blocks: usize,
+    trailing: bool,
+    coefficients: Vec<f64>,
+    /// Samples the next frame adds to the last block of this one, without losses. Empty if trailing.
+    next_overlap: Vec<f64>,
+    /// Last samples of the previous frame, if it is not trailing. The first block of this frame adds to them.
+    previous_tail: Vec<f64>,
+    /// Samples the first block adds to the previous frame, without losses. Empty if the previous frame is trailing.
+    previous_overlap: Vec<f64>,
+    previous_len: usize,
+}
+
+impl Analysis {
+    fn new(data: &[f64], neighbours: Neighbours, block_size: usize) -> Self {
+        let len = data.len();
+        let trailing = neighbours.after.is_empty() || len % block_size != 0;
+        let blocks = Mdct::block_count(len, block_size, trailing);
+        // From one block before the frame to the end of the last block, and of the first block of the next frame
+        let needed = (blocks + 1 + usize::from(!trailing)) * block_size;
+        let before = &neighbours.before[neighbours.before.len().saturating_sub(block_size)..];
+        let mut samples = Vec::with_capacity(needed);
+        // Missing samples repeat the edges, the overlaps that have them are not used
+        samples.resize(
+            block_size - before.len(),
+            *before.first().unwrap_or(&data[0]),
+        );
+        samples.extend_from_slice(before);
+        samples.extend_from_slice(data);
+        samples.extend(neighbours.after.iter().take(needed - samples.len()));
+        samples.resize(needed, *samples.last().unwrap_or(&0.0));
+
+        Transform::with(block_size, |transform| {
+            let mut coefficients = vec![0.0; blocks * block_size];
+            for (j, block) in coefficients.chunks_exact_mut(block_size).enumerate() {
+                transform.forward(&samples[j * block_size..(j + 2) * block_size], block);
+            }
+            // The first half of a block on its own, as it is added to the block before
+            let overlap_of = |coefficients: &[f64]| {
+                let mut samples = vec![0.0; 2 * block_size];
+                for (k, &value) in coefficients.iter().enumerate() {
+                    transform.add_inverse(k, value, &mut samples);
+                }
+                samples.truncate(block_size);
+                samples
+            };
+            let mut next_overlap = Vec::new();
+            if !trailing {
+                let mut next = vec![0.0; block_size];
+                transform.forward(&samples[blocks * block_size..], &mut next);
+                next_overlap = overlap_of(&next);
+            }
+            // The previous frame only waits for this one if it is not trailing
+            let (mut previous_tail, mut previous_overlap) = (Vec::new(), Vec::new());
+            if !neighbours.before.is_empty() && neighbours.before.len() % block_size == 0 {
+                previous_tail = before.to_vec();
+                previous_overlap = overlap_of(&coefficients[..block_size]);
+            }
+            Analysis {
+                block_size,
+                blocks,
+                trailing,
+                coefficients,
+                next_overlap,
+                previous_tail,
+                previous_overlap,
+                previous_len: neighbours.before.len(),
+            }
+        })
+    }
+
+    /// Positions of the coefficients from the biggest to the smallest, without the empty ones and the ones of
+    /// blocks over values that are not finite
+    fn ranked(&self) -> Vec<usize> {
+        let mut ranked: Vec<usize> = (0..self.coefficients.len())
+            .filter(|&pos| self.coefficients[pos] != 0.0 && self.coefficients[pos].is_finite())
+            .collect();
+        ranked.sort_by(|&a, &b| {
+            self.coefficients[b]
+                .abs()
+                .total_cmp(&self.coefficients[a].abs())
+        });
+        ranked
+    }
+
+    /// Bounds of the error of the frame and of the error its first block adds to the previous frame, so both
+    /// frames stay within `max_err` once joined
+    fn error_bounds(&self, max_err: f64) -> (f64, f64) {
+        let own = if self.trailing {
+            max_err
+        } else {
+            max_err * (1.0 - OVERLAP_ERROR_SHARE)
+        };
+        (own, max_err * OVERLAP_ERROR_SHARE)
+    }
+}
+
+impl Mdct {
+    pub fn new(min: f64, max: f64) -> Self {
+        debug!("MDCT compressor: min:{} max:{}", min, max);
+        Mdct {
+            id: MDCT_COMPRESSOR_ID,
+            block_size: BLOCK_SIZE as u16,
+            trailing: true,
+            exponent: 0,
+            coefficients: Vec::new(),
+            max_value: max as f32,
+            min_value: min as f32,
+        }
+    }
+
+    /// Blocks of a frame of `len` samples. Trailing frames have blocks up to their end, the others leave the last
+    /// block size samples to the first block of the next frame.
+    fn block_count(len: usize, block_size: usize, trailing: bool) -> usize {
+        if trailing {
+            len.div_ceil(block_size) + 1
+        } else {
+            len / block_size
+        }
+    }
+
+    /// Smallest exponent of a power of two that is bigger than every coefficient
+    fn top_exponent(analysis: &Analysis, kept: &[usize]) -> i32 {
+        top_exponent(kept.iter().map(|&pos| analysis.coefficients[pos]))
+    }
+
+    /// Stores the `kept` coefficients as multiples of 2^`exponent`, the ones that round to 0 are dropped
+    fn set_coefficients(&mut self, analysis: &Analysis, kept: &[usize], exponent: i8) {
+        self.coefficients = quantize(
+            kept.iter().map(|&pos| (pos, analysis.coefficients[pos])),
+            exponent,
+        );
+        self.exponent = exponent;
+        self.block_size = analysis.block_size as u16;
+        self.trailing = analysis.trailing;
+    }
+
+    /// Positions and values of the kept coefficients
+    fn coefficients(&self) -> impl Iterator<Item = (usize, f64)> + '_ {
+        dequantize(&self.coefficients, self.exponent)
+    }
+
+    /// Adds the samples of the kept coefficients to `samples`, that start one block before the frame
+    fn synthesize(&self, samples: &mut [f64]) {
+        let block_size = self.block_size as usize;
+        Transform::with(block_size, |transform| {
+            for (pos, value) in self.coefficients() {
+                let start = (pos / block_size) * block_size;
+                transform.add_inverse(
+                    pos % block_size,
+                    value,
+                    &mut samples[start..start + 2 * block_size],
+                );
+            }
+        });
+    }
+
+    /// Error of the frame, with the overlap of the next frame as if it had no losses, and the error the first block
+    /// adds to the previous frame, with the rest of the previous frame as if it had no losses
+    fn frame_error(&self, data: &[f64], analysis: &Analysis, samples: &mut [f64]) -> (f64, f64) {
+        let block_size = analysis.block_size;
+        samples.fill(0.0);
+        let last = analysis.blocks * block_size;
+        for (x, overlap) in samples[last..].iter_mut().zip(&analysis.next_overlap) {
+            *x += overlap;
+        }
+        self.synthesize(samples);
+        let out: Vec<f64> = samples[block_size..block_size + data.len()]
+            .iter()
+            .map(|&x| {
+                round_and_limit_f64(
+                    x,
+                    self.min_value.into(),
+                    self.max_value.into(),
+                    DECIMAL_PRECISION,
+                )
+            })
+            .collect();
+        let mut previous_err = 0.0;
+        if !analysis.previous_tail.is_empty() {
+            let previous: Vec<f64> = analysis
+                .previous_tail
+                .iter()
+                .zip(&analysis.previous_overlap)
+                .zip(&samples[..block_size])
+                .map(|((x, exact), lossy)| x + lossy - exact)
+                .collect();
+            previous_err = calculate_error(&analysis.previous_tail, &previous) * block_size as f64
+                / analysis.previous_len as f64;
+            // The error of the previous frame can't be computed either (e.g. zeros), it has no bound to keep
+            if !previous_err.is_finite() {
+                previous_err = 0.0;
+            }
+        }
+        (calculate_error(data, &out), previous_err)
+    }
+
+    /// Compresses the data keeping `count` coefficients, finely quantized
+    pub fn compress_hinted(&mut self, data: &[f64], neighbours: Neighbours, count: usize) {
+        let analysis = Analysis::new(data, neighbours, BLOCK_SIZE);
+        let ranked = analysis.ranked();
+        let kept = &ranked[..count.min(ranked.len())];
+        let exponent = fine_exponent(Mdct::top_exponent(&analysis, kept));
+        self.set_coefficients(&analysis, kept, exponent);
+    }
+
+    /// Compresses the data keeping the fewest coefficients that meet the error, quantized as coarsely as the error
+    /// allows. Frames that overlap share the bound, part of it is left to the first block of the next frame, so the
+    /// error of the frames joined is within the bound too. Returns the error of the frame on its own part of the bound,
+    /// if no number of coefficients meets it, the default number of coefficients is kept.
+    pub fn compress_bounded(&mut self, data: &[f64], neighbours: Neighbours, max_err: f64) -> f64 {
+        let analysis = Analysis::new(data, neighbours, BLOCK_SIZE);
+        let ranked = analysis.ranked();
+        let default_count = Mdct::default_count(data.len());
+        if ranked.is_empty() {
+            self.set_coefficients(&analysis, &ranked, 0);
+            let mut samples = vec![0.0; (analysis.blocks + 1) * BLOCK_SIZE];
+            return self.frame_error(data, &analysis, &mut samples).0;
+        }
+        let (own_bound, previous_bound) = analysis.error_bounds(max_err);
+        let mut samples = vec![0.0; (analysis.blocks + 1) * BLOCK_SIZE];
+        let mut iterations = 0;
+        let mut error_of = |count: usize, exponent: i8| -> (f64, f64) {
+            iterations += 1;
+            let mut candidate = Mdct::new(self.min_value.into(), self.max_value.into());
+            candidate.set_coefficients(&analysis, &ranked[..count], exponent);
+            candidate.frame_error(data, &analysis, &mut samples)
+        };
+        // Finely quantized, the error drops as coefficients are added
+        let fine = |count: usize| fine_exponent(Mdct::top_exponent(&analysis, &ranked[..count]));
+        let mut errors: Vec<Option<(f64, f64)>> = vec![None; ranked.len() + 1];
+        let mut error_with = |count: usize| -> (f64, f64) {
+            *errors[count].get_or_insert_with(|| {
+                let err = error_of(count, fine(count));
+                trace!("Coefficients: {} Current Err: {:?}", count, err);
+                err
+            })
+        };
+        let mut count = smallest_passing(1, ranked.len(), default_count, |count| {
+            let (own, previous) = error_with(count);
+            own <= own_bound && previous <= previous_bound
+        });
+        // The error can't be computed for some data (e.g. zeros), then there is nothing to search for
+        if !error_with(count).0.is_finite() {
+            count = default_count.min(ranked.len());
+        }
+        let (own, previous) = error_with(count);
+        let (own_bound, previous_bound) = (own_bound.max(own), previous_bound.max(previous));
+
+        // Search the coarsest step still in the bound
+        let (exponent, (error, _)) = coarsest_exponent(
+            Mdct::top_exponent(&analysis, &ranked[..count]),
+            |exponent| error_of(count, exponent),
+            |(own, previous)| own <= own_bound && previous <= previous_bound,
+        );
+        self.set_coefficients(&analysis, &ranked[..count], exponent);
+        debug!(
+            "Iterations to convergence: {}, Coefficients P:{} S:{}, Exponent: {}, Error: {}",
+            iterations,
+            default_count,
+            self.coefficients.len(),
+            self.exponent,
+            error
+        );
+        error
+    }
+
+    /// Coefficients kept without an error bound, 1/100 of the data length OR 3, which is bigger
+    fn default_count(len: usize) -> usize {
+        (len / 100).max(3)
+    }
+
+    pub fn decompress(data: &[u8]) -> Self {
+        let config = BinConfig::get();
+        let (mdct, _) = bincode::decode_from_slice(data, config).unwrap();
+        mdct
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let config = BinConfig::get();
+        bincode::encode_to_vec(self, config).unwrap()
+    }
+
+    /// Decompresses the `frame_size` samples of the frame, with the overlap of the previous frame apart
+    pub fn to_lapped(&self, frame_size: usize) -> LappedSamples {
+        let block_size = self.block_size as usize;
+        let blocks = Mdct::block_count(frame_size, block_size, self.trailing);
+        let mut samples = vec![0.0; (blocks + 1) * block_size];
+        self.synthesize(&mut samples);
+        samples.truncate(block_size + frame_size);
+        let frame = samples.split_off(block_size);
+        LappedSamples {
+            overlap: samples,
+            samples: frame,
+            trailing: self.trailing,
+            min_value: self.min_value.into(),
+            max_value: self.max_value.into(),
+        }
+    }
+
+    /// Decompresses the frame on its own
+    pub fn to_data(&self, frame_size: usize) -> Vec<f64> {
+        let mut lapped = self.to_lapped(frame_size);
+        let mut samples = std::mem::take(&mut lapped.samples);
+        lapped.finish(&mut samples);
+        samples
+    }
+}
+
+/// Compresses a frame on its own via MDCT, keeping the default number of coefficients
+pub fn mdct(data: &[f64]) -> Vec<u8> {
+    mdct_lapped(data, Neighbours::default())
+}
+
+/// Compresses via MDCT overlapping the `neighbours` frames, keeping the default number of coefficients
+pub fn mdct_lapped(data: &[f64], neighbours: Neighbours) -> Vec<u8> {
+    info!("Initializing MDCT Compressor");
+    let stats = DataStats::new(data);
+    let mut c = Mdct::new(stats.min, stats.max);
+    c.compress_hinted(data, neighbours, Mdct::default_count(data.len()));
+    c.to_bytes()
+}
+
+/// Compresses a frame on its own via MDCT, keeping `count` coefficients
+pub fn mdct_set(data: &[f64], count: usize) -> Vec<u8> {
+    info!("Initializing MDCT Compressor");
+    let stats = DataStats::new(data);
+    let mut c = Mdct::new(stats.min, stats.max);
+    c.compress_hinted(data, Neighbours::default(), count);
+    c.to_bytes()
+}
+
+/// Compress targeting a specific max error allowed, overlapping the `neighbours` frames
+pub fn mdct_compressor(
+    data: &[f64],
+    neighbours: Neighbours,
+    allowed_error: f64,
+    stats: DataStats,
+) -> CompressorResult {
+    debug!("Initializing MDCT Compressor. Error and Stats provided");
+    let mut c = Mdct::new(stats.min, stats.max);
+    let error = c.compress_bounded(data, neighbours, allowed_error);
+    CompressorResult::new(c.to_bytes(), error)
+}
+
+/// Uncompress a MDCT frame on its own
+pub fn mdct_to_data(sample_number: usize, compressed_data: &[u8]) -> Vec<f64> {
+    Mdct::decompress(compressed_data).to_data(sample_number)
+}
+
+/// Uncompress a MDCT frame, to be joined with its neighbours
+pub fn mdct_to_lapped(sample_number: usize, compressed_data: &[u8]) -> LappedSamples {
+    Mdct::decompress(compressed_data).to_lapped(sample_number)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signal(len: usize) -> Vec<f64> {
+        (0..len)
+            .map(|x| {
+                let x = x as f64;
+                100.0 + (x / 30.0).sin() * 20.0 + (x / 7.0).cos() * 3.0 + x / 50.0
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_perfect_reconstruction() {
+        let data = signal(229);
+        let out = mdct_to_data(data.len(), &mdct_set(&data, usize::MAX));
+        for (x, y) in data.iter().zip(&out) {
+            assert!((x - y).abs() < 1e-3, "{} {}", x, y);
+        }
+    }
+
+    #[test]
+    fn test_to_allowed_error() {
+        let data = signal(1000);
+        for max_error in [0.001, 0.01, 0.03] {
+            let result = mdct_compressor(
+                &data,
+                Neighbours::default(),
+                max_error,
+                DataStats::new(&data),
+            );
+            let out = mdct_to_data(data.len(), &result.compressed_data);
+            assert_eq!(calculate_error(&data, &out), result.error);
+            assert!(result.error <= max_error);
+        }
+    }
+
+    #[test]
+    fn test_overlapping_frames() {
+        let data = signal(1024);
+        let (first, second) = data.split_at(512);
+        let first_frame = mdct_compressor(
+            first,
+            Neighbours {
+                before: &[],
+                after: second,
+            },
+            0.01,
+            DataStats::new(first),
+        );
+        let second_frame = mdct_compressor(
+            second,
+            Neighbours {
+                before: first,
+                after: &[],
+            },
+            0.01,
+            DataStats::new(second),
+        );
+        let first_lapped = mdct_to_lapped(first.len(), &first_frame.compressed_data);
+        let second_lapped = mdct_to_lapped(second.len(), &second_frame.compressed_data);
+        assert!(!first_lapped.trailing);
+        assert!(second_lapped.trailing);
+        assert_eq!(first_lapped.overlap.len(), BLOCK_SIZE);
+        // The frames are only complete once the overlap is added
+        let mut out = first_lapped.samples.clone();
+        for (x, overlap) in out[512 - BLOCK_SIZE..]
+            .iter_mut()
+            .zip(&second_lapped.overlap)
+        {
+            *x += overlap;
+        }
+        first_lapped.finish(&mut out);
+        let mut second_out = second_lapped.samples.clone();
+        second_lapped.finish(&mut second_out);
+        assert!(calculate_error(first, &out) <= 0.011);
+        assert!(calculate_error(second, &second_out) <= 0.01);
+        // No jump at the frame boundary
+        let step = (out[511] - second_out[0]).abs();
+        assert!(step < (data[511] - data[512]).abs() + 2.0, "{}", step);
+    }
+}
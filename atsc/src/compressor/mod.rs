@@ -16,22 +16,50 @@ limitations under the License.
 
 use bincode::config::{self, Configuration};
 use bincode::{Decode, Encode};
+use serde::{Deserialize, Serialize};
 
 use crate::optimizer::utils::DataStats;
 
-use self::constant::{constant_compressor, constant_to_data};
-use self::fft::{fft, fft_compressor, fft_to_data};
+use self::constant::{
+    constant_allowed_error, constant_compressor, constant_to_data, trend_compressor, trend_to_data,
+};
+use self::dct::{dct, dct_compressor, dct_set, dct_to_data};
+use self::dictionary::{dictionary_compressor, dictionary_to_data};
+use self::fft::{fft, fft_compressor, fft_set, fft_to_data};
+use self::mdct::{mdct, mdct_compressor, mdct_set, mdct_to_data, Neighbours};
 use self::noop::{noop, noop_to_data};
-use self::polynomial::{polynomial, polynomial_allowed_error, to_data, PolynomialType};
+use self::pla::{pla, pla_compressor, pla_to_data};
+use self::polynomial::{
+    polynomial, polynomial_allowed_error, polynomial_set, to_data, PolynomialType,
+};
+use self::residual::{residual, residual_compressor, residual_to_data};
 use self::rle::{rle_compressor, rle_to_data};
+use self::spike::{spike, spike_compressor, spike_to_data};
+use self::wavelet::{wavelet, wavelet_compressor, wavelet_set, wavelet_to_data};
 
+pub mod bits;
 pub mod constant;
+pub mod dct;
+pub mod dictionary;
 pub mod fft;
+pub mod fft_cache;
+pub mod interpolation;
+pub mod mdct;
 pub mod noop;
+pub mod pla;
 pub mod polynomial;
+pub mod quantization;
+pub mod residual;
 pub mod rle;
+pub mod spike;
+pub mod wavelet;
 
-#[derive(Encode, Decode, Default, Debug, Clone, Copy, Eq, Hash, PartialEq)]
+/// Error allowed on each sample when no bound is provided, same as the default error of the CLI
+const DEFAULT_MAX_ERROR: f64 = 0.03;
+
+#[derive(
+    Encode, Decode, Serialize, Deserialize, Default, Debug, Clone, Copy, Eq, Hash, PartialEq,
+)]
 pub enum Compressor {
     #[default]
     Noop,
@@ -41,6 +69,18 @@ pub enum Compressor {
     Polynomial,
     Auto,
     RLE,
+    Mdct,
+    Wavelet,
+    Dct,
+    Pla,
+    Linear,
+    Spline,
+    Pchip,
+    Akima,
+    Residual,
+    Spike,
+    Dictionary,
+    Trend,
 }
 
 /// Struct to store the results of a compression round. Will be used to pick the best compressor.
@@ -66,19 +106,51 @@ impl Compressor {
             Compressor::Noop => noop(data),
             Compressor::FFT => fft(data),
             Compressor::Constant => constant_compressor(data, stats).compressed_data,
+            Compressor::Trend => trend_compressor(data).compressed_data,
             Compressor::Polynomial => polynomial(data, PolynomialType::Polynomial),
             Compressor::Idw => polynomial(data, PolynomialType::Idw),
+            Compressor::Linear => polynomial(data, PolynomialType::Linear),
+            Compressor::Spline => polynomial(data, PolynomialType::Spline),
+            Compressor::Pchip => polynomial(data, PolynomialType::Pchip),
+            Compressor::Akima => polynomial(data, PolynomialType::Akima),
+            Compressor::Residual => residual(data),
+            Compressor::Spike => spike(data),
             Compressor::RLE => rle_compressor(data, stats).compressed_data,
+            Compressor::Dictionary => dictionary_compressor(data).compressed_data,
+            Compressor::Mdct => mdct(data),
+            Compressor::Wavelet => wavelet(data),
+            Compressor::Dct => dct(data),
+            Compressor::Pla => pla(data),
             _ => todo!(),
         }
     }
 
+    /// Compresses with a parameter hint, number of frequencies for FFT, number of coefficients for DCT, MDCT and
+    /// Wavelet and number of points for Polynomial, IDW and the other interpolations. Compressors without parameters
+    /// ignore the hint.
+    pub fn compress_hinted(&self, data: &[f64], hint: usize) -> Vec<u8> {
+        match self {
+            Compressor::FFT => fft_set(data, hint),
+            Compressor::Mdct => mdct_set(data, hint),
+            Compressor::Wavelet => wavelet_set(data, hint),
+            Compressor::Dct => dct_set(data, hint),
+            Compressor::Polynomial => polynomial_set(data, hint, PolynomialType::Polynomial),
+            Compressor::Idw => polynomial_set(data, hint, PolynomialType::Idw),
+            Compressor::Linear => polynomial_set(data, hint, PolynomialType::Linear),
+            Compressor::Spline => polynomial_set(data, hint, PolynomialType::Spline),
+            Compressor::Pchip => polynomial_set(data, hint, PolynomialType::Pchip),
+            Compressor::Akima => polynomial_set(data, hint, PolynomialType::Akima),
+            _ => self.compress(data),
+        }
+    }
+
     pub fn compress_bounded(&self, data: &[f64], max_error: f64) -> Vec<u8> {
         let stats = DataStats::new(data);
         match self {
             Compressor::Noop => noop(data),
             Compressor::FFT => fft_compressor(data, max_error, stats).compressed_data,
-            Compressor::Constant => constant_compressor(data, stats).compressed_data,
+            Compressor::Constant => constant_allowed_error(data, stats).compressed_data,
+            Compressor::Trend => trend_compressor(data).compressed_data,
             Compressor::Polynomial => {
                 polynomial_allowed_error(data, max_error, PolynomialType::Polynomial)
                     .compressed_data
@@ -86,7 +158,28 @@ impl Compressor {
             Compressor::Idw => {
                 polynomial_allowed_error(data, max_error, PolynomialType::Idw).compressed_data
             }
+            Compressor::Linear => {
+                polynomial_allowed_error(data, max_error, PolynomialType::Linear).compressed_data
+            }
+            Compressor::Spline => {
+                polynomial_allowed_error(data, max_error, PolynomialType::Spline).compressed_data
+            }
+            Compressor::Pchip => {
+                polynomial_allowed_error(data, max_error, PolynomialType::Pchip).compressed_data
+            }
+            Compressor::Akima => {
+                polynomial_allowed_error(data, max_error, PolynomialType::Akima).compressed_data
+            }
+            Compressor::Residual => residual_compressor(data, max_error).compressed_data,
+            Compressor::Spike => spike_compressor(data, max_error).compressed_data,
             Compressor::RLE => rle_compressor(data, stats).compressed_data,
+            Compressor::Dictionary => dictionary_compressor(data).compressed_data,
+            Compressor::Mdct => {
+                mdct_compressor(data, Neighbours::default(), max_error, stats).compressed_data
+            }
+            Compressor::Wavelet => wavelet_compressor(data, max_error, stats).compressed_data,
+            Compressor::Dct => dct_compressor(data, max_error, stats).compressed_data,
+            Compressor::Pla => pla_compressor(data, max_error).compressed_data,
             _ => todo!(),
         }
     }
@@ -96,12 +189,24 @@ impl Compressor {
         match self {
             Compressor::Noop => CompressorResult::new(noop(data), 0.0),
             Compressor::FFT => fft_compressor(data, max_error, stats),
-            Compressor::Constant => constant_compressor(data, stats),
+            Compressor::Constant => constant_allowed_error(data, stats),
+            Compressor::Trend => trend_compressor(data),
             Compressor::RLE => rle_compressor(data, stats),
+            Compressor::Dictionary => dictionary_compressor(data),
             Compressor::Polynomial => {
                 polynomial_allowed_error(data, max_error, PolynomialType::Polynomial)
             }
             Compressor::Idw => polynomial_allowed_error(data, max_error, PolynomialType::Idw),
+            Compressor::Linear => polynomial_allowed_error(data, max_error, PolynomialType::Linear),
+            Compressor::Spline => polynomial_allowed_error(data, max_error, PolynomialType::Spline),
+            Compressor::Pchip => polynomial_allowed_error(data, max_error, PolynomialType::Pchip),
+            Compressor::Akima => polynomial_allowed_error(data, max_error, PolynomialType::Akima),
+            Compressor::Residual => residual_compressor(data, max_error),
+            Compressor::Spike => spike_compressor(data, max_error),
+            Compressor::Mdct => mdct_compressor(data, Neighbours::default(), max_error, stats),
+            Compressor::Wavelet => wavelet_compressor(data, max_error, stats),
+            Compressor::Dct => dct_compressor(data, max_error, stats),
+            Compressor::Pla => pla_compressor(data, max_error),
             _ => todo!(),
         }
     }
@@ -111,9 +216,20 @@ impl Compressor {
             Compressor::Noop => noop_to_data(samples, data),
             Compressor::FFT => fft_to_data(samples, data),
             Compressor::Constant => constant_to_data(samples, data),
+            Compressor::Trend => trend_to_data(samples, data),
             Compressor::Polynomial => to_data(samples, data),
             Compressor::Idw => to_data(samples, data),
+            Compressor::Linear | Compressor::Spline | Compressor::Pchip | Compressor::Akima => {
+                to_data(samples, data)
+            }
+            Compressor::Residual => residual_to_data(samples, data),
+            Compressor::Spike => spike_to_data(samples, data),
             Compressor::RLE => rle_to_data(samples, data),
+            Compressor::Dictionary => dictionary_to_data(samples, data),
+            Compressor::Mdct => mdct_to_data(samples, data),
+            Compressor::Wavelet => wavelet_to_data(samples, data),
+            Compressor::Dct => dct_to_data(samples, data),
+            Compressor::Pla => pla_to_data(samples, data),
             _ => todo!(),
         }
     }
@@ -129,3 +245,33 @@ impl BinConfig {
         config::standard()
     }
 }
+
+/// Helpers shared by the tests of the compressors
+#[cfg(test)]
+mod test_utils {
+    /// Every sample of `out` is within `max_error` of the same sample in `data`, relative to it
+    pub(crate) fn assert_within(data: &[f64], out: &[f64], max_error: f64) {
+        assert_eq!(data.len(), out.len());
+        for (x, y) in data.iter().zip(out) {
+            assert!(
+                (x - y).abs() <= (x * max_error).abs() * (1.0 + 1e-9),
+                "{} {}",
+                x,
+                y
+            );
+        }
+    }
+
+    /// A slow trend with a few isolated spikes
+    pub(crate) fn spiky(len: usize) -> Vec<f64> {
+        (0..len)
+            .map(|x| {
+                if x % 113 == 5 {
+                    1000.0 + x as f64 * 0.7
+                } else {
+                    200.0 + x as f64 * 0.05
+                }
+            })
+            .collect()
+    }
+}
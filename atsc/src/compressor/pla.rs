@@ -0,0 +1,319 @@
+/*
+Copyright 2024 NetApp, Inc.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    https://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use crate::{compressor::CompressorResult, utils::error::calculate_error};
+
+use super::{BinConfig, DEFAULT_MAX_ERROR};
+use bincode::{Decode, Encode};
+use log::{debug, info, trace};
+
+const PLA_COMPRESSOR_ID: u8 = 50;
+/// Bits an endpoint value can take as a multiple of the quantization step, keeps the step coarse enough for the
+/// biggest values of the frame to fit
+const VALUE_BITS: i32 = 62;
+
+/// Piecewise Linear Approximation (PLA) Compressor. Describes the frame as connected line segments of any length,
+/// built with a swing filter: a segment grows while a line from its start can still pass within the error of every
+/// sample. Every sample is within the error of its original value (relative to it), not only the average.
+/// Endpoint values are stored as multiples of a power of two that fits the error. Always LOSSY
+#[derive(Encode, Decode, PartialEq, Debug)]
+pub struct Pla {
+    pub id: u8,
+    /// Exponent of the power of two the endpoint values are multiples of
+    pub exponent: i8,
+    /// Samples each segment moves forward, the first endpoint is the first sample
+    lengths: Vec<u32>,
+    /// Endpoint values as multiples of the step, each one as the difference to the previous one
+    values: Vec<i64>,
+}
+
+/// Slopes from the start of a segment that keep every sample of it within the error
+struct Swing {
+    start: usize,
+    value: f64,
+    lower: f64,
+    upper: f64,
+}
+
+impl Swing {
+    fn new(start: usize, value: f64) -> Self {
+        Swing {
+            start,
+            value,
+            lower: f64::NEG_INFINITY,
+            upper: f64::INFINITY,
+        }
+    }
+
+    fn slope_to(&self, pos: usize, value: f64) -> f64 {
+        (value - self.value) / (pos - self.start) as f64
+    }
+
+    /// Narrows the slopes to the ones that pass within `tolerance` of `value` at `pos`. Returns false, leaving the
+    /// slopes as they were, if none is left. Samples that are not finite don't narrow anything.
+    fn narrow(&mut self, pos: usize, value: f64, tolerance: f64) -> bool {
+        let lower = self.lower.max(self.slope_to(pos, value - tolerance));
+        let upper = self.upper.min(self.slope_to(pos, value + tolerance));
+        if lower > upper {
+            return false;
+        }
+        self.lower = lower;
+        self.upper = upper;
+        true
+    }
+
+    /// Value at `pos` of the line in the middle of the slopes left
+    fn value_at(&self, pos: usize) -> f64 {
+        let slope = match (self.lower.is_finite(), self.upper.is_finite()) {
+            (true, true) => (self.lower + self.upper) / 2.0,
+            (true, false) => self.lower,
+            (false, true) => self.upper,
+            (false, false) => 0.0,
+        };
+        self.value + slope * (pos - self.start) as f64
+    }
+
+    fn allows(&self, pos: usize, value: f64) -> bool {
+        let slope = self.slope_to(pos, value);
+        self.lower <= slope && slope <= self.upper
+    }
+}
+
+impl Pla {
+    pub fn new() -> Self {
+        debug!("PLA compressor");
+        Pla {
+            id: PLA_COMPRESSOR_ID,
+            exponent: 0,
+            lengths: Vec::new(),
+            values: Vec::new(),
+        }
+    }
+
+    /// Number of segments
+    pub fn segment_count(&self) -> usize {
+        self.lengths.len()
+    }
+
+    /// Exponent of the step, the biggest power of two that is not bigger than the smallest error allowed on a
+    /// sample, so rounding an endpoint uses at most half of it. Zeros allow no error, they don't count. Without any
+    /// error allowed, the finest step the values fit in.
+    fn step_exponent(data: &[f64], max_err: f64) -> i32 {
+        let finite = data.iter().filter(|x| x.is_finite()).map(|x| x.abs());
+        let smallest = finite
+            .clone()
+            .filter(|&x| x > 0.0)
+            .fold(f64::INFINITY, f64::min)
+            * max_err;
+        let biggest = finite.fold(0.0, f64::max);
+        let exponent = if smallest > 0.0 && smallest.is_finite() {
+            smallest.log2().floor() as i32
+        } else {
+            i32::MIN
+        };
+        // Biggest values still fit as multiples of the step, and so does the exponent in the frame
+        let lowest = (biggest.log2().ceil() as i32).saturating_sub(VALUE_BITS);
+        exponent.max(lowest).clamp(i8::MIN.into(), i8::MAX.into())
+    }
+
+    /// Compresses the data keeping every sample within `max_err` of its value, relative to it
+    pub fn compress_bounded(&mut self, data: &[f64], max_err: f64) {
+        self.lengths.clear();
+        self.values.clear();
+        if data.is_empty() {
+            return;
+        }
+        let exponent = Pla::step_exponent(data, max_err);
+        let step = 2f64.powi(exponent);
+        self.exponent = exponent as i8;
+        let quantize = |x: f64| (x / step).round() as i64;
+        // Rounding the end of a segment moves the line up to half a step, keep it out of the tolerance
+        let tolerance = |x: f64| (x * max_err).abs() - step / 2.0;
+
+        let mut previous = quantize(data[0]);
+        self.values.push(previous);
+        let mut swing = Swing::new(0, previous as f64 * step);
+        // Ends the segment at `end`, on `value` or on the middle line if there is none
+        let mut end_segment = |swing: &mut Swing, end: usize, value: Option<i64>| {
+            let value = value.unwrap_or_else(|| quantize(swing.value_at(end)));
+            self.lengths.push((end - swing.start) as u32);
+            self.values.push(value - previous);
+            previous = value;
+            *swing = Swing::new(end, value as f64 * step);
+        };
+        let mut pos = 1;
+        while pos < data.len() {
+            let value = data[pos];
+            if tolerance(value) <= 0.0 {
+                // No room for rounding (e.g. zeros), the sample can only be an endpoint on its own value
+                let exact = quantize(value);
+                if !swing.allows(pos, exact as f64 * step) {
+                    let end = pos - 1;
+                    end_segment(&mut swing, end, None);
+                }
+                end_segment(&mut swing, pos, Some(exact));
+            } else if swing.narrow(pos, value, tolerance(value)) {
+                // Always the case for the first sample after the start
+                if pos == data.len() - 1 {
+                    end_segment(&mut swing, pos, None);
+                }
+            } else {
+                let end = pos - 1;
+                end_segment(&mut swing, end, None);
+                // The sample starts narrowing the next segment
+                continue;
+            }
+            pos += 1;
+        }
+        trace!(
+            "Samples: {} Segments: {} Exponent: {}",
+            data.len(),
+            self.lengths.len(),
+            self.exponent
+        );
+    }
+
+    pub fn decompress(data: &[u8]) -> Self {
+        let config = BinConfig::get();
+        let (pla, _) = bincode::decode_from_slice(data, config).unwrap();
+        pla
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let config = BinConfig::get();
+        bincode::encode_to_vec(self, config).unwrap()
+    }
+
+    /// Returns the `frame_size` samples of the frame, the last endpoint is repeated if the segments are shorter
+    pub fn to_data(&self, frame_size: usize) -> Vec<f64> {
+        let step = 2f64.powi(self.exponent.into());
+        let mut data = Vec::with_capacity(frame_size);
+        let mut values = self.values.iter();
+        let mut start = values.next().copied().unwrap_or_default();
+        data.push(start as f64 * step);
+        for (&length, &delta) in self.lengths.iter().zip(values) {
+            let end = start + delta;
+            let (from, to) = (start as f64 * step, end as f64 * step);
+            data.extend((1..length).map(|pos| from + (to - from) * pos as f64 / length as f64));
+            data.push(to);
+            start = end;
+        }
+        let last = data.last().copied().unwrap_or_default();
+        data.resize(frame_size, last);
+        data
+    }
+}
+
+impl Default for Pla {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Compresses via PLA, keeping every sample within the default error
+pub fn pla(data: &[f64]) -> Vec<u8> {
+    info!("Initializing PLA Compressor");
+    let mut c = Pla::new();
+    c.compress_bounded(data, DEFAULT_MAX_ERROR);
+    c.to_bytes()
+}
+
+/// Compress keeping every sample within the max error allowed
+pub fn pla_compressor(data: &[f64], allowed_error: f64) -> CompressorResult {
+    debug!("Initializing PLA Compressor. Error provided");
+    let mut c = Pla::new();
+    c.compress_bounded(data, allowed_error);
+    let error = calculate_error(data, &c.to_data(data.len()));
+    debug!("Segments: {}, Error: {}", c.segment_count(), error);
+    CompressorResult::new(c.to_bytes(), error)
+}
+
+/// Uncompress a PLA frame
+pub fn pla_to_data(sample_number: usize, compressed_data: &[u8]) -> Vec<f64> {
+    Pla::decompress(compressed_data).to_data(sample_number)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compressor::polynomial::{polynomial_allowed_error, PolynomialType};
+    use crate::compressor::test_utils::assert_within;
+
+    /// Ramps up and resets, like a counter that wraps
+    fn ramps(len: usize) -> Vec<f64> {
+        (0..len).map(|x| 50.0 + (x % 200) as f64 * 1.5).collect()
+    }
+
+    #[test]
+    fn test_every_sample_within_error() {
+        let data: Vec<f64> = (0..1000)
+            .map(|x| {
+                let x = x as f64;
+                100.0 + x / 3.0 + (x / 20.0).sin() * 10.0 + (x * 7.0).cos()
+            })
+            .collect();
+        for max_error in [0.001, 0.01, 0.05] {
+            let result = pla_compressor(&data, max_error);
+            let out = pla_to_data(data.len(), &result.compressed_data);
+            assert_within(&data, &out, max_error);
+            assert_eq!(calculate_error(&data, &out), result.error);
+        }
+    }
+
+    #[test]
+    fn test_ramps() {
+        let data = ramps(1000);
+        let pla = Pla::decompress(&pla_compressor(&data, 0.01).compressed_data);
+        // A segment per ramp, and one for each reset
+        assert_eq!(pla.segment_count(), 9);
+        assert_within(&data, &pla.to_data(data.len()), 0.01);
+    }
+
+    #[test]
+    fn test_smaller_than_polynomial_on_ramps() {
+        let data = ramps(1000);
+        let pla = pla_compressor(&data, 0.01);
+        let polynomial = polynomial_allowed_error(&data, 0.01, PolynomialType::Polynomial);
+        assert!(
+            pla.compressed_data.len() < polynomial.compressed_data.len(),
+            "{} {}",
+            pla.compressed_data.len(),
+            polynomial.compressed_data.len()
+        );
+    }
+
+    #[test]
+    fn test_zeros_are_exact() {
+        let data = [0.0, 1.0, 2.0, 0.0, 0.0, 3.0, -2.0, 0.0, 5.0];
+        let out = pla_to_data(data.len(), &pla_compressor(&data, 0.1).compressed_data);
+        assert_within(&data, &out, 0.1);
+    }
+
+    #[test]
+    fn test_without_error() {
+        let data = [0.25, 1.1, 2.5, 1000.3, -7.01];
+        let out = pla_to_data(data.len(), &pla_compressor(&data, 0.0).compressed_data);
+        assert_within(&data, &out, 1e-15);
+    }
+
+    #[test]
+    fn test_short_frames() {
+        for data in [vec![], vec![7.0], vec![7.0, 8.0]] {
+            let out = pla_to_data(data.len(), &pla(&data));
+            assert_within(&data, &out, DEFAULT_MAX_ERROR);
+        }
+    }
+}
@@ -15,9 +15,11 @@ limitations under the License.
 */
 
 use crate::optimizer::utils::{Bitdepth, DataStats};
-use crate::utils::{error::calculate_error, round_and_limit_f64, round_f64, DECIMAL_PRECISION};
+use crate::utils::{
+    error::calculate_error, round_and_limit_f64, smallest_passing, DECIMAL_PRECISION,
+};
 
-use super::{BinConfig, CompressorResult};
+use super::{interpolation, BinConfig, CompressorResult};
 use bincode::{Decode, Encode};
 use inverse_distance_weight::IDW;
 use log::{debug, info, trace};
@@ -25,12 +27,19 @@ use splines::{Interpolation, Key, Spline};
 
 const POLYNOMIAL_COMPRESSOR_ID: u8 = 0;
 const IDW_COMPRESSOR_ID: u8 = 1;
+/// Step of the frames that store the positions of their points, instead of placing them on a grid
+pub const ADAPTIVE_STEP: u8 = 0;
 
+/// Interpolation of the frame, stored first in the encoded frame
 #[derive(Encode, Decode, Default, Debug, Clone, PartialEq)]
 pub enum PolynomialType {
     #[default]
     Polynomial = 0,
     Idw = 1,
+    Linear = 2,
+    Spline = 3,
+    Pchip = 4,
+    Akima = 5,
 }
 
 #[derive(Encode, Decode, Default, Debug, Clone)]
@@ -38,6 +47,12 @@ pub enum Method {
     #[default]
     CatmullRom,
     Idw,
+    Linear,
+    /// Natural cubic spline
+    NaturalSpline,
+    /// Monotone piecewise cubic, never overshoots the points
+    Pchip,
+    Akima,
 }
 
 #[derive(PartialEq, Debug, Clone)]
@@ -47,10 +62,28 @@ pub struct Polynomial {
     pub min: f64,
     pub max: f64,
     pub point_step: u8,
+    /// Positions of the points when they are not on a grid (`point_step` is `ADAPTIVE_STEP`), stored as the gaps
+    /// between them
+    pub positions: Vec<usize>,
     pub error: Option<f64>,
     pub bitdepth: Bitdepth,
 }
 
+/// Distances between consecutive positions, the first position is always 0
+fn positions_to_gaps(positions: &[usize]) -> Vec<u32> {
+    positions.windows(2).map(|w| (w[1] - w[0]) as u32).collect()
+}
+
+fn gaps_to_positions(gaps: &[u32]) -> Vec<usize> {
+    let mut position = 0;
+    std::iter::once(0)
+        .chain(gaps.iter().map(|&gap| {
+            position += gap as usize;
+            position
+        }))
+        .collect()
+}
+
 impl Encode for Polynomial {
     fn encode<__E: ::bincode::enc::Encoder>(
         &self,
@@ -82,6 +115,9 @@ impl Encode for Polynomial {
         Encode::encode(&self.min, encoder)?;
         Encode::encode(&self.max, encoder)?;
         Encode::encode(&self.point_step, encoder)?;
+        if self.point_step == ADAPTIVE_STEP {
+            Encode::encode(&positions_to_gaps(&self.positions), encoder)?;
+        }
         Ok(())
     }
 }
@@ -117,6 +153,12 @@ impl Decode for Polynomial {
         let min = Decode::decode(decoder)?;
         let max = Decode::decode(decoder)?;
         let point_step = Decode::decode(decoder)?;
+        let positions = if point_step == ADAPTIVE_STEP {
+            let gaps: Vec<u32> = Decode::decode(decoder)?;
+            gaps_to_positions(&gaps)
+        } else {
+            Vec::new()
+        };
 
         Ok(Self {
             id,
@@ -125,6 +167,7 @@ impl Decode for Polynomial {
             min,
             max,
             point_step,
+            positions,
             error: None,
         })
     }
@@ -162,6 +205,12 @@ impl<'__de> ::bincode::BorrowDecode<'__de> for Polynomial {
         let min = Decode::decode(decoder)?;
         let max = Decode::decode(decoder)?;
         let point_step = Decode::decode(decoder)?;
+        let positions = if point_step == ADAPTIVE_STEP {
+            let gaps: Vec<u32> = Decode::decode(decoder)?;
+            gaps_to_positions(&gaps)
+        } else {
+            Vec::new()
+        };
 
         Ok(Self {
             id,
@@ -170,6 +219,7 @@ impl<'__de> ::bincode::BorrowDecode<'__de> for Polynomial {
             min,
             max,
             point_step,
+            positions,
             error: None,
         })
     }
@@ -194,6 +244,7 @@ impl Polynomial {
             data_points: Vec::with_capacity(sample_count),
             // Minimum step is always 1
             point_step: 1,
+            positions: Vec::new(),
             error: None,
             bitdepth,
         }
@@ -203,10 +254,43 @@ impl Polynomial {
         match self.id {
             PolynomialType::Idw => Method::Idw,
             PolynomialType::Polynomial => Method::CatmullRom,
+            PolynomialType::Linear => Method::Linear,
+            PolynomialType::Spline => Method::NaturalSpline,
+            PolynomialType::Pchip => Method::Pchip,
+            PolynomialType::Akima => Method::Akima,
         }
     }
 
+    /// Compresses with the fewest points that match the max allowed error, either on a grid or placed where the
+    /// error is highest, whichever is smaller. Placed points only pay off when they are fewer, the ones on the grid
+    /// don't store their positions.
     pub fn compress_bounded(&mut self, data: &[f64], max_err: f64) {
+        if self.max == self.min {
+            debug!("Same max and min, we're done here!");
+            return;
+        }
+        self.compress_uniform(data, max_err);
+        let uniform = self.clone();
+        let uniform_met = uniform.error.is_some_and(|err| err <= max_err);
+        match self.compress_adaptive(data, max_err, uniform.data_points.len()) {
+            Some(err) if !uniform_met || self.to_bytes().len() < uniform.to_bytes().len() => {
+                debug!(
+                    "Adaptive points: {} Grid points: {}",
+                    self.data_points.len(),
+                    uniform.data_points.len()
+                );
+                self.error = Some(err);
+            }
+            _ => *self = uniform,
+        }
+    }
+
+    /// Searches a big step between the stored points, so few points, that matches the max allowed error. The error
+    /// mostly grows with the step, so the step is bisected, then the steps right above it are tried while they still
+    /// match. Resampling periodic data can alias, a bigger step can still match past one that doesn't, so the step
+    /// found matches the error and the next one doesn't, but it is not always the biggest that matches.
+    /// If the error is undefined for the data (e.g. zeros), the default number of points is stored.
+    fn compress_uniform(&mut self, data: &[f64], max_err: f64) {
         if self.max == self.min {
             debug!("Same max and min, we're done here!");
             return;
@@ -222,51 +306,41 @@ impl Polynomial {
         } else {
             data_len / 100
         };
-        // Variables for the error control loop
-        let mut current_err = max_err + 1.0;
-        let mut jump: usize = 0;
+        // Biggest step that still stores a point in between the first and the last
+        let max_step = (data_len / 2).clamp(1, u8::MAX as usize);
+        let baseline_step = (data_len / baseline_points).clamp(1, max_step);
+        let mut errors: Vec<Option<f64>> = vec![None; max_step + 1];
         let mut iterations = 0;
-        // Locking max target error precision to 0.1%
-        let target_error = round_f64(max_err, 3);
-        while target_error < round_f64(current_err, 4) {
-            trace!(
-                "Method: {:?} Iterations: {} Error: {} Target: {}",
-                method,
-                iterations,
-                current_err,
-                target_error
-            );
-            iterations += 1;
-            self.compress_hinted(data, baseline_points + jump);
-            let out_data = match method {
-                Method::CatmullRom => self.polynomial_to_data(data_len),
-                Method::Idw => self.idw_to_data(data_len),
-            };
-            trace!("Calculated Values: {:?}", out_data);
-            trace!("Data Values: {:?}", data);
-            current_err = calculate_error(data, &out_data);
-            trace!("Current Err: {}", current_err);
-            // Max iterations is 18 (We start at 10%, we can go to 95% and 1% at a time)
-            match iterations {
-                // We should always increase by 1 in worst case
-                1..=17 => jump += (data_len / 10).max(1),
-                18..=22 => jump += (data_len / 100).max(1),
-                // No more jumping, but we landed right in the end
-                _ if target_error > round_f64(current_err, 4) => break,
-                // We can't hit the target, store everything
-                _ => {
-                    self.compress_hinted(data, data_len);
-                    // if we store everything, there is no error
-                    current_err = 0.0;
-                    break;
-                }
-            }
-            if self.data_points.len() == data_len {
-                // Storing the whole thing anyway...
-                // if we store everything, there is no error
-                current_err = 0.0;
-                break;
+        let mut error_with = |step: usize| -> f64 {
+            if let Some(err) = errors[step] {
+                return err;
             }
+            iterations += 1;
+            self.compress_step(data, step);
+            let out_data = self.method_to_data(&method, data_len);
+            let err = calculate_error(data, &out_data);
+            trace!("Method: {:?} Step: {} Error: {}", method, step, err);
+            errors[step] = Some(err);
+            err
+        };
+        // Searching the smallest distance from the biggest step is searching the biggest step
+        let distance = smallest_passing(0, max_step - 1, max_step - baseline_step, |distance| {
+            error_with(max_step - distance) <= max_err
+        });
+        let mut step = max_step - distance;
+        while step < max_step && error_with(step + 1) <= max_err {
+            step += 1;
+        }
+        let mut current_err = error_with(step);
+        if !current_err.is_finite() {
+            step = baseline_step;
+            current_err = error_with(step);
+        }
+        self.compress_step(data, step);
+        if self.data_points.len() == data_len {
+            // Storing the whole thing anyway...
+            // if we store everything, there is no error
+            current_err = 0.0;
         }
         self.error = Some(current_err);
         debug!(
@@ -276,6 +350,79 @@ impl Polynomial {
         );
     }
 
+    /// Places the points where the reconstruction error is highest, starting with the first and the last samples.
+    /// Each round adds a point at the worst sample of the segments between points that add the most error, an
+    /// eighth more points each round, so the rounds are few even for many points. Stops once the error is met, or
+    /// at `max_points`. Returns the error, None if it wasn't met.
+    fn compress_adaptive(&mut self, data: &[f64], max_err: f64, max_points: usize) -> Option<f64> {
+        let data_len = data.len();
+        let max_points = max_points.min(data_len);
+        let mut positions: Vec<usize> = vec![0, data_len - 1];
+        positions.dedup();
+        let magnitude = (data
+            .iter()
+            .filter(|x| x.is_finite())
+            .map(|x| x.abs())
+            .sum::<f64>()
+            / data_len as f64)
+            .max(f64::MIN_POSITIVE);
+        let mut rounds = 0;
+        loop {
+            rounds += 1;
+            self.compress_positions(data, &positions);
+            let out_data = self.to_data(data_len);
+            let err = calculate_error(data, &out_data);
+            trace!("Points: {} Error: {}", positions.len(), err);
+            if err <= max_err {
+                debug!("Adaptive points: {} Rounds: {}", positions.len(), rounds);
+                return Some(err);
+            }
+            if positions.len() >= max_points {
+                return None;
+            }
+            // Samples are ranked by their relative error, as the bound is. It is infinite on zeros and every new point
+            // would go to them, so zeros are ranked by their error relative to the mean magnitude of the frame.
+            // Samples that are not finite can't be points, their error doesn't count
+            let sample_err = |i: usize| -> f64 {
+                match data[i] {
+                    x if !x.is_finite() => 0.0,
+                    0.0 => out_data[i].abs() / magnitude,
+                    x => ((out_data[i] - x) / x).abs(),
+                }
+            };
+            // Error added by each segment, and its worst sample
+            let mut segments: Vec<(f64, usize)> = positions
+                .windows(2)
+                .filter(|w| w[1] - w[0] > 1)
+                .map(|w| {
+                    let samples = w[0] + 1..w[1];
+                    let total: f64 = samples.clone().map(sample_err).sum();
+                    let worst = samples
+                        .max_by(|&a, &b| sample_err(a).total_cmp(&sample_err(b)))
+                        .unwrap();
+                    (total, worst)
+                })
+                .filter(|&(total, _)| total > 0.0)
+                .collect();
+            if segments.is_empty() {
+                return None;
+            }
+            segments.sort_by(|a, b| b.0.total_cmp(&a.0));
+            let added = (positions.len() / 8)
+                .max(1)
+                .min(max_points - positions.len());
+            positions.extend(segments.iter().take(added).map(|&(_, worst)| worst));
+            positions.sort_unstable();
+        }
+    }
+
+    /// Stores the points at `positions`, sorted and starting at the first sample
+    fn compress_positions(&mut self, data: &[f64], positions: &[usize]) {
+        self.data_points = positions.iter().map(|&pos| data[pos]).collect();
+        self.positions = positions.to_vec();
+        self.point_step = ADAPTIVE_STEP;
+    }
+
     pub fn compress_hinted(&mut self, data: &[f64], points: usize) {
         if self.max == self.min {
             debug!("Same max and min, we're done here!");
@@ -283,11 +430,16 @@ impl Polynomial {
         }
         // The algorithm is simple, Select 10% of the data points, calculate the Polynomial based on those data points
         // Plus the max and min
-        let data_len = data.len();
         // Instead of calculation, we use the provided count
-        let point_count = points;
-        // Step size
-        let step = (data_len / point_count).max(1);
+        let point_count = points.max(1);
+        // Step size, it is stored as an u8
+        let step = (data.len() / point_count).clamp(1, u8::MAX as usize);
+        self.compress_step(data, step);
+    }
+
+    /// Stores a point every `step` samples, and the last one
+    fn compress_step(&mut self, data: &[f64], step: usize) {
+        let data_len = data.len();
         // I can calculate the positions from here
         let mut points: Vec<f64> = (0..data_len).step_by(step).map(|f| f as f64).collect();
         // Pushing the last value if needed (and if data is not empty)
@@ -301,6 +453,7 @@ impl Polynomial {
         debug!("Compressed Hinted Values: {:?}", values);
 
         self.data_points = values;
+        self.positions.clear();
         self.point_step = step as u8;
     }
 
@@ -327,6 +480,9 @@ impl Polynomial {
     /// Since IDW and Polynomial are the same code everywhere, this function prepares the data
     /// to be used by one of the polynomial decompression methods
     fn get_positions(&self, frame_size: usize) -> Vec<usize> {
+        if self.point_step == ADAPTIVE_STEP {
+            return self.positions.clone();
+        }
         let mut points = Vec::with_capacity(frame_size);
         for position_value in (0..frame_size).step_by(self.point_step as usize) {
             points.push(position_value);
@@ -392,15 +548,38 @@ impl Polynomial {
             .collect()
     }
 
+    /// Same as `polynomial_to_data` with one of the interpolations of the `interpolation` module
+    fn interpolated_to_data(
+        &self,
+        frame_size: usize,
+        interpolation: fn(&[usize], &[f64], usize) -> Vec<f64>,
+    ) -> Vec<f64> {
+        let points = self.get_positions(frame_size);
+        interpolation(&points, &self.data_points, frame_size)
+            .into_iter()
+            .map(|value| round_and_limit_f64(value, self.min, self.max, DECIMAL_PRECISION))
+            .collect()
+    }
+
+    fn method_to_data(&self, method: &Method, frame_size: usize) -> Vec<f64> {
+        match method {
+            Method::CatmullRom => self.polynomial_to_data(frame_size),
+            Method::Idw => self.idw_to_data(frame_size),
+            Method::Linear => self.interpolated_to_data(frame_size, interpolation::linear),
+            Method::NaturalSpline => {
+                self.interpolated_to_data(frame_size, interpolation::natural_spline)
+            }
+            Method::Pchip => self.interpolated_to_data(frame_size, interpolation::pchip),
+            Method::Akima => self.interpolated_to_data(frame_size, interpolation::akima),
+        }
+    }
+
     pub fn to_data(&self, frame_size: usize) -> Vec<f64> {
         if self.max == self.min {
             debug!("Same max and min, faster decompression!");
             return vec![self.max; frame_size];
         }
-        match self.id {
-            PolynomialType::Idw => self.idw_to_data(frame_size),
-            PolynomialType::Polynomial => self.polynomial_to_data(frame_size),
-        }
+        self.method_to_data(&self.get_method(), frame_size)
     }
 }
 
@@ -424,6 +603,15 @@ pub fn polynomial_allowed_error(
     CompressorResult::new(c.to_bytes(), c.error.unwrap_or(0.0))
 }
 
+/// Compresses the data storing the provided number of points
+pub fn polynomial_set(data: &[f64], points: usize, p_type: PolynomialType) -> Vec<u8> {
+    info!("Initializing Polynomial Compressor");
+    let stats = DataStats::new(data);
+    let mut c = Polynomial::new(data.len(), stats.min, stats.max, p_type, stats.bitdepth);
+    c.compress_hinted(data, points);
+    c.to_bytes()
+}
+
 pub fn to_data(sample_number: usize, compressed_data: &[u8]) -> Vec<f64> {
     let c = Polynomial::decompress(compressed_data);
     c.to_data(sample_number)
@@ -432,6 +620,7 @@ pub fn to_data(sample_number: usize, compressed_data: &[u8]) -> Vec<f64> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::utils::error::error_mae;
 
     #[test]
     fn test_polynomial_u8() {
@@ -525,6 +714,176 @@ mod tests {
         assert!(e <= 0.05);
     }
 
+    #[test]
+    fn test_bounded_search_is_minimal() {
+        let wave: Vec<f64> = (0..2000)
+            .map(|x| {
+                let x = x as f64;
+                100.0 + (x / 40.0).sin() * 10.0 + (x % 5.0)
+            })
+            .collect();
+        // Periodic input, the error is not monotonic in the step
+        let triangle: Vec<f64> = (0..2000)
+            .map(|x| 100.0 + (x % 24).min(24 - x % 24) as f64 * 2.0)
+            .collect();
+        for (data, p_type, max_error) in [
+            (&wave, PolynomialType::Polynomial, 0.01),
+            (&wave, PolynomialType::Polynomial, 0.03),
+            (&wave, PolynomialType::Idw, 0.01),
+            (&wave, PolynomialType::Idw, 0.03),
+            (&triangle, PolynomialType::Polynomial, 0.01),
+        ] {
+            let stats = DataStats::new(data);
+            let mut poly = Polynomial::new(
+                data.len(),
+                stats.min,
+                stats.max,
+                p_type.clone(),
+                stats.bitdepth,
+            );
+            poly.compress_uniform(data, max_error);
+            let out = poly.to_data(data.len());
+            assert!(calculate_error(data, &out) <= max_error);
+            // A bigger step misses the error
+            let mut bigger = Polynomial::new(
+                data.len(),
+                stats.min,
+                stats.max,
+                p_type.clone(),
+                stats.bitdepth,
+            );
+            bigger.compress_step(data, poly.point_step as usize + 1);
+            let out = bigger.to_data(data.len());
+            assert!(calculate_error(data, &out) > max_error);
+        }
+    }
+
+    #[test]
+    fn test_adaptive_points() {
+        // Flat levels joined by steep ramps, a grid wastes points on the levels and misses the corners
+        let data: Vec<f64> = (0..1000)
+            .map(|x| match x {
+                0..=399 => 100.0 + (x % 2) as f64 * 0.1,
+                400..=449 => 100.0 + (x - 400) as f64 * 4.0,
+                450..=799 => 300.0,
+                800..=819 => 300.0 - (x - 800) as f64 * 7.5,
+                _ => 150.0,
+            })
+            .collect();
+        let stats = DataStats::new(&data);
+        for p_type in [PolynomialType::Polynomial, PolynomialType::Idw] {
+            let mut uniform = Polynomial::new(
+                data.len(),
+                stats.min,
+                stats.max,
+                p_type.clone(),
+                stats.bitdepth,
+            );
+            uniform.compress_uniform(&data, 0.01);
+            let cr = polynomial_allowed_error(&data, 0.01, p_type.clone());
+            let out = to_data(data.len(), &cr.compressed_data);
+            assert_eq!(calculate_error(&data, &out), cr.error);
+            assert!(cr.error <= 0.01);
+            // Never bigger than the grid
+            assert!(cr.compressed_data.len() <= uniform.to_bytes().len());
+            if p_type == PolynomialType::Polynomial {
+                let poly = Polynomial::decompress(&cr.compressed_data);
+                assert_eq!(poly.point_step, ADAPTIVE_STEP);
+                assert!(
+                    poly.data_points.len() * 4 < uniform.data_points.len(),
+                    "{} {}",
+                    poly.data_points.len(),
+                    uniform.data_points.len()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_adaptive_points_with_zeros() {
+        // Integer wave that rests on zero between its bumps, the error is undefined and the points run out
+        let data: Vec<f64> = (0..1000)
+            .map(|x| ((x as f64 / 40.0).sin() * 50.0).round().max(0.0))
+            .collect();
+        let stats = DataStats::new(&data);
+        let mut poly = Polynomial::new(
+            data.len(),
+            stats.min,
+            stats.max,
+            PolynomialType::Polynomial,
+            stats.bitdepth,
+        );
+        assert_eq!(poly.compress_adaptive(&data, 0.01, 60), None);
+        // The points follow the bumps instead of going to the zeros
+        let zeros = poly.data_points.iter().filter(|&&x| x == 0.0).count();
+        assert!(
+            zeros * 3 < poly.data_points.len(),
+            "{} {}",
+            zeros,
+            poly.data_points.len()
+        );
+        let out = poly.to_data(data.len());
+        assert!(error_mae(&data, &out) < 1.0, "{}", error_mae(&data, &out));
+    }
+
+    #[test]
+    fn test_adaptive_positions() {
+        let positions = vec![0, 1, 7, 300, 301];
+        assert_eq!(positions_to_gaps(&positions), [1, 6, 293, 1]);
+        assert_eq!(gaps_to_positions(&positions_to_gaps(&positions)), positions);
+        let data: Vec<f64> = (0..302).map(|x| x as f64).collect();
+        let stats = DataStats::new(&data);
+        let mut poly = Polynomial::new(
+            data.len(),
+            stats.min,
+            stats.max,
+            PolynomialType::Polynomial,
+            stats.bitdepth,
+        );
+        poly.compress_positions(&data, &positions);
+        let decoded = Polynomial::decompress(&poly.to_bytes());
+        assert_eq!(decoded.positions, positions);
+        assert_eq!(decoded.data_points, [0.0, 1.0, 7.0, 300.0, 301.0]);
+    }
+
+    #[test]
+    fn test_methods_to_allowed_error() {
+        let data: Vec<f64> = (0..1000)
+            .map(|x| {
+                let x = x as f64;
+                200.0 + (x / 50.0).sin() * 40.0 + if x > 500.0 { 60.0 } else { 0.0 }
+            })
+            .collect();
+        for p_type in [
+            PolynomialType::Linear,
+            PolynomialType::Spline,
+            PolynomialType::Pchip,
+            PolynomialType::Akima,
+        ] {
+            let cr = polynomial_allowed_error(&data, 0.01, p_type.clone());
+            let poly = Polynomial::decompress(&cr.compressed_data);
+            assert_eq!(poly.id, p_type);
+            let out = poly.to_data(data.len());
+            assert_eq!(calculate_error(&data, &out), cr.error);
+            assert!(cr.error <= 0.01);
+        }
+    }
+
+    #[test]
+    fn test_pchip_counter_stays_monotonic() {
+        // Counter with bursts of increments
+        let data: Vec<f64> = (0..1000)
+            .scan(1000.0, |counter, x| {
+                *counter += if x % 100 < 10 { 50.0 } else { (x % 3) as f64 };
+                Some(*counter)
+            })
+            .collect();
+        let cr = polynomial_allowed_error(&data, 0.01, PolynomialType::Pchip);
+        let out = to_data(data.len(), &cr.compressed_data);
+        assert!(cr.error <= 0.01);
+        assert!(out.windows(2).all(|w| w[0] <= w[1]));
+    }
+
     #[test]
     fn test_idw() {
         let vector1 = vec![1.0, 0.0, 1.0, 1.0, 2.0, 1.0, 1.0, 1.0, 3.0, 1.0, 1.0, 5.0];
@@ -580,6 +939,14 @@ mod tests {
         assert!(e <= 0.02);
     }
 
+    #[test]
+    fn test_polynomial_large_step() {
+        let vector1: Vec<f64> = (0..2048).map(|x| x as f64).collect();
+        let compressed_data = polynomial_set(&vector1, 3, PolynomialType::Polynomial);
+        let out = to_data(vector1.len(), &compressed_data);
+        assert_eq!(vector1, out);
+    }
+
     #[test]
     fn test_line_polynomial() {
         let vector1 = vec![1.0, 1.0, 1.0, 1.0];
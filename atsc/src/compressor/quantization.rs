@@ -0,0 +1,228 @@
+/*
+Copyright 2024 NetApp, Inc.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    https://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+//! Coefficients of the transform compressors (FFT, DCT, MDCT and Wavelet) stored as multiples of a power of two
+
+use crate::utils::smallest_passing;
+use bincode::{Decode, Encode};
+use log::trace;
+use std::fmt::Debug;
+
+/// Bits kept of the biggest coefficient at the finest quantization, the precision of an f32. FFT and DCT compute in
+/// f32, finer steps only keep rounding noise. With fewer bits the fine quantization uses up part of the error bound,
+/// on the test data MDCT, that leaves part of its bound to the next frame, needed more coefficients with 20 bits.
+pub const QUANTIZATION_BITS: i32 = 24;
+
+/// Coefficient stored as a multiple of the quantization step, with its position as the distance to the previous one.
+/// Small multiples and distances take a single byte each with the variable int encoding.
+#[derive(Encode, Decode, Debug, Copy, Clone, PartialEq)]
+pub struct Quantized<M> {
+    pos_delta: u32,
+    multiple: M,
+}
+
+/// Value that can be stored as a multiple of the quantization step, a coefficient or the parts of a frequency
+pub trait Quantize: Copy {
+    type Multiple: Copy;
+
+    fn to_multiple(self, step: f64) -> Self::Multiple;
+
+    fn from_multiple(multiple: Self::Multiple, step: f64) -> Self;
+
+    fn is_zero(multiple: Self::Multiple) -> bool;
+
+    /// Biggest absolute value of its parts
+    fn magnitude(self) -> f64;
+}
+
+impl Quantize for f64 {
+    type Multiple = i32;
+
+    fn to_multiple(self, step: f64) -> i32 {
+        (self / step).round() as i32
+    }
+
+    fn from_multiple(multiple: i32, step: f64) -> Self {
+        multiple as f64 * step
+    }
+
+    fn is_zero(multiple: i32) -> bool {
+        multiple == 0
+    }
+
+    fn magnitude(self) -> f64 {
+        self.abs()
+    }
+}
+
+/// Real and imaginary parts, encoded as two multiples one after the other
+impl Quantize for (f64, f64) {
+    type Multiple = (i32, i32);
+
+    fn to_multiple(self, step: f64) -> (i32, i32) {
+        (self.0.to_multiple(step), self.1.to_multiple(step))
+    }
+
+    fn from_multiple(multiple: (i32, i32), step: f64) -> Self {
+        (
+            f64::from_multiple(multiple.0, step),
+            f64::from_multiple(multiple.1, step),
+        )
+    }
+
+    fn is_zero(multiple: (i32, i32)) -> bool {
+        multiple == (0, 0)
+    }
+
+    fn magnitude(self) -> f64 {
+        self.0.abs().max(self.1.abs())
+    }
+}
+
+/// Stores the values, with their positions, as multiples of 2^`exponent` sorted by position. The ones that round to
+/// 0 are dropped.
+pub fn quantize<V: Quantize>(
+    values: impl IntoIterator<Item = (usize, V)>,
+    exponent: i8,
+) -> Vec<Quantized<V::Multiple>> {
+    let step = 2f64.powi(exponent.into());
+    let mut multiples: Vec<(usize, V::Multiple)> = values
+        .into_iter()
+        .map(|(pos, value)| (pos, value.to_multiple(step)))
+        .filter(|&(_, multiple)| !V::is_zero(multiple))
+        .collect();
+    multiples.sort_by_key(|&(pos, _)| pos);
+    let mut previous = 0;
+    multiples
+        .into_iter()
+        .map(|(pos, multiple)| {
+            let quantized = Quantized {
+                pos_delta: (pos - previous) as u32,
+                multiple,
+            };
+            previous = pos;
+            quantized
+        })
+        .collect()
+}
+
+/// Positions and values of the values stored by `quantize` with `exponent`
+pub fn dequantize<V: Quantize>(
+    quantized: &[Quantized<V::Multiple>],
+    exponent: i8,
+) -> impl Iterator<Item = (usize, V)> + '_ {
+    let step = 2f64.powi(exponent.into());
+    let mut pos = 0;
+    quantized.iter().map(move |q| {
+        pos += q.pos_delta as usize;
+        (pos, V::from_multiple(q.multiple, step))
+    })
+}
+
+/// Smallest exponent of a power of two that is bigger than every one of the values
+pub fn top_exponent<V: Quantize>(values: impl IntoIterator<Item = V>) -> i32 {
+    let biggest = values.into_iter().map(V::magnitude).fold(0.0, f64::max);
+    biggest.log2().floor().max(i8::MIN.into()) as i32 + 1
+}
+
+/// Exponent that keeps `QUANTIZATION_BITS` of the values whose top exponent is `top`
+pub fn fine_exponent(top: i32) -> i8 {
+    clamp_exponent(top - QUANTIZATION_BITS)
+}
+
+pub fn clamp_exponent(exponent: i32) -> i8 {
+    exponent.clamp(i8::MIN.into(), i8::MAX.into()) as i8
+}
+
+/// Searches the coarsest exponent, from `top` to `QUANTIZATION_BITS` under it, whose error `passes`. Quantization
+/// makes the error grow as the step gets coarser, so the exponents are bisected. Returns the exponent and its error.
+pub fn coarsest_exponent<E: Copy + Debug>(
+    top: i32,
+    mut error_of: impl FnMut(i8) -> E,
+    passes: impl Fn(E) -> bool,
+) -> (i8, E) {
+    let mut errors: Vec<Option<E>> = vec![None; QUANTIZATION_BITS as usize + 1];
+    let mut error_with = |finer: usize| -> E {
+        *errors[finer].get_or_insert_with(|| {
+            let exponent = clamp_exponent(top - finer as i32);
+            let err = error_of(exponent);
+            trace!("Quantization exponent: {} Current Err: {:?}", exponent, err);
+            err
+        })
+    };
+    let finer = smallest_passing(
+        0,
+        QUANTIZATION_BITS as usize,
+        QUANTIZATION_BITS as usize / 2,
+        |finer| passes(error_with(finer)),
+    );
+    (clamp_exponent(top - finer as i32), error_with(finer))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compressor::BinConfig;
+
+    #[test]
+    fn test_round_trip() {
+        let values = [(7, 12.5), (2, -3.0), (70000, 0.1), (9, 40.0)];
+        let quantized = quantize(values, -1);
+        let out: Vec<(usize, f64)> = dequantize(&quantized, -1).collect();
+        // Sorted by position, 0.1 rounds to 0 and is dropped
+        assert_eq!(out, [(2, -3.0), (7, 12.5), (9, 40.0)]);
+    }
+
+    #[test]
+    fn test_parts() {
+        let values = [(1, (4.0, -8.0)), (3, (0.4, 0.0)), (5, (0.0, 2.0))];
+        let quantized = quantize(values, 1);
+        let out: Vec<(usize, (f64, f64))> = dequantize(&quantized, 1).collect();
+        assert_eq!(out, [(1, (4.0, -8.0)), (5, (0.0, 2.0))]);
+        assert_eq!(top_exponent(values.map(|(_, v)| v)), 4);
+    }
+
+    #[test]
+    fn test_encoding() {
+        // A position delta and two multiples, one byte each
+        let quantized = quantize([(3, (1.0, -1.0))], 0);
+        let bytes = bincode::encode_to_vec(&quantized, BinConfig::get()).unwrap();
+        assert_eq!(bytes, [1, 3, 2, 1]);
+    }
+
+    #[test]
+    fn test_top_exponent() {
+        assert_eq!(top_exponent([3.0, -4.0]), 3);
+        assert_eq!(top_exponent([4.5]), 3);
+        // No values, the exponent stays in range
+        assert_eq!(top_exponent::<f64>([]), i8::MIN as i32 + 1);
+    }
+
+    #[test]
+    fn test_coarsest_exponent() {
+        let value = 1001.3;
+        let (exponent, error) = coarsest_exponent(
+            top_exponent([value]),
+            |exponent| {
+                let step = 2f64.powi(exponent.into());
+                (value / step).round() * step - value
+            },
+            |error: f64| error.abs() <= 0.5,
+        );
+        assert_eq!(exponent, 0);
+        assert!((error + 0.3).abs() < 1e-9);
+    }
+}
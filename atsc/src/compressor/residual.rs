@@ -0,0 +1,313 @@
+/*
+Copyright 2024 NetApp, Inc.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    https://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use crate::{
+    compressor::{Compressor, CompressorResult},
+    optimizer::budget::geometric_range,
+    utils::error::calculate_error,
+};
+
+use super::bits::{rice_bits, BitReader, BitWriter};
+use super::{BinConfig, DEFAULT_MAX_ERROR};
+use bincode::{Decode, Encode};
+use log::{debug, info, trace};
+
+const RESIDUAL_COMPRESSOR_ID: u8 = 70;
+/// Bits a sample can take as a multiple of the quantization step, keeps the step coarse enough for the biggest
+/// values of the frame to fit
+const VALUE_BITS: i32 = 62;
+/// Compressors the coarse model is picked from
+const MODELS: [Compressor; 2] = [Compressor::FFT, Compressor::Polynomial];
+
+/// Residual Compressor. A coarse model (FFT or Polynomial) plus corrections for the samples the model misses by
+/// more than the error. A corrected sample is its value rounded to a power of two that fits its error, stored as
+/// the difference to the model rounded the same way, so corrections are small numbers when the model is close.
+/// Positions and values of the corrections are Rice coded. Every sample ends up within the error of its value,
+/// which makes tight errors possible without storing the whole frame. Always LOSSY
+#[derive(Encode, Decode, PartialEq, Debug)]
+pub struct Residual {
+    pub id: u8,
+    /// Compressor of the model
+    pub model: Compressor,
+    model_data: Vec<u8>,
+    /// Exponent of the power of two the corrected samples are multiples of
+    pub exponent: i8,
+    /// Number of corrected samples
+    pub corrections: u32,
+    /// Rice parameters of the gaps between corrected samples and of the corrections
+    gap_bits: u8,
+    value_bits: u8,
+    /// Gaps and corrections, each gap followed by its correction
+    stream: Vec<u8>,
+}
+
+/// Exponent of the biggest power of two `x` is a multiple of
+fn exact_exponent(x: f64) -> i32 {
+    let bits = x.to_bits();
+    let exponent = ((bits >> 52) & 0x7ff) as i32;
+    let mantissa = bits & ((1 << 52) - 1);
+    // Subnormals have no implicit bit
+    let (mantissa, exponent) = match exponent {
+        0 => (mantissa, -1074),
+        _ => (mantissa | 1 << 52, exponent - 1075),
+    };
+    exponent + mantissa.trailing_zeros() as i32
+}
+
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+fn unzigzag(value: u64) -> i64 {
+    (value >> 1) as i64 ^ -((value & 1) as i64)
+}
+
+impl Residual {
+    pub fn new(model: Compressor) -> Self {
+        debug!("Residual compressor: model:{:?}", model);
+        Residual {
+            id: RESIDUAL_COMPRESSOR_ID,
+            model,
+            model_data: Vec::new(),
+            exponent: 0,
+            corrections: 0,
+            gap_bits: 0,
+            value_bits: 0,
+            stream: Vec::new(),
+        }
+    }
+
+    /// Exponent of the step, the biggest power of two each sample to correct either is a multiple of or allows as
+    /// error. Integers are multiples of 1, so without any error allowed they are still corrected exactly. Zeros are
+    /// multiples of every step, they don't count.
+    fn step_exponent(data: &[f64], corrected: &[usize], max_err: f64) -> i32 {
+        let exponent = corrected
+            .iter()
+            .map(|&pos| data[pos])
+            .filter(|&x| x != 0.0)
+            .map(|x| {
+                let allowed = (x * max_err).abs().log2().floor();
+                let allowed = if allowed.is_finite() {
+                    allowed as i32
+                } else {
+                    i32::MIN
+                };
+                allowed.max(exact_exponent(x))
+            })
+            .min()
+            .unwrap_or(i32::MIN);
+        let biggest = data
+            .iter()
+            .filter(|x| x.is_finite())
+            .fold(0.0, |biggest: f64, x| biggest.max(x.abs()));
+        let lowest = (biggest.log2().ceil() as i32).saturating_sub(VALUE_BITS);
+        exponent.max(lowest).clamp(i8::MIN.into(), i8::MAX.into())
+    }
+
+    /// Keeps `model_data`, the output of the model, and corrects the samples of `data` it misses by more than
+    /// `max_err`
+    fn set_model(&mut self, data: &[f64], model_data: Vec<u8>, max_err: f64) {
+        let modeled = self.model.decompress(data.len(), &model_data);
+        let corrected: Vec<usize> = (0..data.len())
+            .filter(|&pos| {
+                data[pos].is_finite()
+                    && (data[pos] - modeled[pos]).abs() > (data[pos] * max_err).abs()
+            })
+            .collect();
+        let exponent = Residual::step_exponent(data, &corrected, max_err);
+        let step = 2f64.powi(exponent);
+        let mut previous = 0;
+        let gaps: Vec<u64> = corrected
+            .iter()
+            .map(|&pos| {
+                let gap = (pos - previous) as u64;
+                previous = pos + 1;
+                gap
+            })
+            .collect();
+        let values: Vec<u64> = corrected
+            .iter()
+            .map(|&pos| {
+                zigzag((data[pos] / step).round() as i64 - (modeled[pos] / step).round() as i64)
+            })
+            .collect();
+        self.gap_bits = rice_bits(&gaps);
+        self.value_bits = rice_bits(&values);
+        let mut writer = BitWriter::default();
+        for (&gap, &value) in gaps.iter().zip(&values) {
+            writer.rice(gap, self.gap_bits);
+            writer.rice(value, self.value_bits);
+        }
+        self.model_data = model_data;
+        self.exponent = exponent as i8;
+        self.corrections = corrected.len() as u32;
+        self.stream = writer.into_bytes();
+    }
+
+    /// Compresses the data with the model size that, with its corrections, takes the fewest bytes. Model sizes
+    /// grow geometrically, from a single frequency or point to half the frame.
+    pub fn compress_bounded(&mut self, data: &[f64], max_err: f64) {
+        let mut best: Option<(usize, usize)> = None;
+        for hint in geometric_range(1, (data.len() / 2).max(1)) {
+            self.set_model(data, self.model.compress_hinted(data, hint), max_err);
+            let size = self.to_bytes().len();
+            trace!(
+                "Model: {:?} Hint: {} Corrections: {} Size: {}",
+                self.model,
+                hint,
+                self.corrections,
+                size
+            );
+            if best.map_or(true, |(_, best_size)| size < best_size) {
+                best = Some((hint, size));
+            }
+        }
+        let (hint, _) = best.unwrap();
+        self.set_model(data, self.model.compress_hinted(data, hint), max_err);
+        debug!(
+            "Model: {:?} Hint: {} Corrections: {}",
+            self.model, hint, self.corrections
+        );
+    }
+
+    pub fn decompress(data: &[u8]) -> Self {
+        let config = BinConfig::get();
+        let (residual, _) = bincode::decode_from_slice(data, config).unwrap();
+        residual
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let config = BinConfig::get();
+        bincode::encode_to_vec(self, config).unwrap()
+    }
+
+    /// Returns the `frame_size` samples of the frame
+    pub fn to_data(&self, frame_size: usize) -> Vec<f64> {
+        let mut data = self.model.decompress(frame_size, &self.model_data);
+        let step = 2f64.powi(self.exponent.into());
+        let mut reader = BitReader::new(&self.stream);
+        let mut pos = 0;
+        for _ in 0..self.corrections {
+            pos += reader.rice(self.gap_bits) as usize;
+            let correction = unzigzag(reader.rice(self.value_bits));
+            data[pos] = ((data[pos] / step).round() as i64 + correction) as f64 * step;
+            pos += 1;
+        }
+        data
+    }
+}
+
+/// Compresses with a model and corrections, keeping every sample within the default error
+pub fn residual(data: &[f64]) -> Vec<u8> {
+    info!("Initializing Residual Compressor");
+    residual_compressor(data, DEFAULT_MAX_ERROR).compressed_data
+}
+
+/// Compress keeping every sample within the max error allowed, with the model that takes the fewest bytes
+pub fn residual_compressor(data: &[f64], allowed_error: f64) -> CompressorResult {
+    debug!("Initializing Residual Compressor. Error provided");
+    MODELS
+        .iter()
+        .map(|&model| {
+            let mut c = Residual::new(model);
+            c.compress_bounded(data, allowed_error);
+            let error = calculate_error(data, &c.to_data(data.len()));
+            CompressorResult::new(c.to_bytes(), error)
+        })
+        .min_by_key(|result| result.compressed_data.len())
+        .unwrap()
+}
+
+/// Uncompress a Residual frame
+pub fn residual_to_data(sample_number: usize, compressed_data: &[u8]) -> Vec<f64> {
+    Residual::decompress(compressed_data).to_data(sample_number)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compressor::fft::fft_compressor;
+    use crate::compressor::test_utils::{assert_within, spiky};
+    use crate::optimizer::utils::DataStats;
+
+    #[test]
+    fn test_zigzag() {
+        for value in [0, -1, 1, i64::MAX, i64::MIN] {
+            assert_eq!(unzigzag(zigzag(value)), value);
+        }
+    }
+
+    #[test]
+    fn test_every_sample_within_error() {
+        let data = spiky(1000);
+        for max_error in [0.0001, 0.001, 0.01] {
+            let result = residual_compressor(&data, max_error);
+            let out = residual_to_data(data.len(), &result.compressed_data);
+            assert_within(&data, &out, max_error);
+            assert_eq!(calculate_error(&data, &out), result.error);
+        }
+    }
+
+    #[test]
+    fn test_smaller_than_model_alone() {
+        let data = spiky(1000);
+        let residual = residual_compressor(&data, 0.001);
+        let fft = fft_compressor(&data, 0.001, DataStats::new(&data));
+        assert!(
+            residual.compressed_data.len() < fft.compressed_data.len(),
+            "{} {}",
+            residual.compressed_data.len(),
+            fft.compressed_data.len()
+        );
+    }
+
+    #[test]
+    fn test_near_lossless() {
+        let data = spiky(1000);
+        let result = residual_compressor(&data, 1e-7);
+        let out = residual_to_data(data.len(), &result.compressed_data);
+        assert_within(&data, &out, 1e-7);
+        // Raw samples take 8 bytes each
+        assert!(result.compressed_data.len() * 2 < data.len() * 8);
+    }
+
+    #[test]
+    fn test_exact_exponent() {
+        assert_eq!(exact_exponent(1.0), 0);
+        assert_eq!(exact_exponent(100.0), 2);
+        assert_eq!(exact_exponent(-0.375), -3);
+        assert_eq!(exact_exponent(f64::from_bits(1)), -1074);
+    }
+
+    #[test]
+    fn test_integers_without_error() {
+        let data: Vec<f64> = spiky(1000).iter().map(|x| x.round()).collect();
+        let result = residual_compressor(&data, 0.0);
+        let out = residual_to_data(data.len(), &result.compressed_data);
+        assert_eq!(out, data);
+        assert_eq!(Residual::decompress(&result.compressed_data).exponent, 0);
+    }
+
+    #[test]
+    fn test_zeros_are_exact() {
+        let data = [0.0, 3.0, 0.0, 0.0, 7.5, -2.0, 0.0, 1.0];
+        let out = residual_to_data(
+            data.len(),
+            &residual_compressor(&data, 0.01).compressed_data,
+        );
+        assert_within(&data, &out, 0.01);
+    }
+}
@@ -20,12 +20,82 @@ use crate::{
 };
 
 use super::BinConfig;
+use bincode::de::Decoder;
+use bincode::enc::Encoder;
+use bincode::error::{DecodeError, EncodeError};
 use bincode::{Decode, Encode};
 use log::{debug, trace};
 use std::collections::BTreeMap;
 
 const RLE_COMPRESSOR_ID: u8 = 60;
 
+/// How the runs are stored in the frame. The byte after the ID holds the bitdepth and, above it, the layout. The
+/// original format only wrote the bitdepth there, so it reads as the `Indices` layout.
+#[derive(PartialEq, Debug, Clone, Copy)]
+enum Layout {
+    /// Each value with the start of its runs as indices, the original format
+    Indices,
+    /// Each value with the start of its runs, each one as the distance to the previous one
+    Gaps,
+    /// Each run as its value and length, in order
+    Pairs,
+}
+
+/// Byte after the ID, the bitdepth as bincode writes its variant and the layout above it
+fn header(layout: Layout, bitdepth: Bitdepth) -> u8 {
+    let bitdepth = match bitdepth {
+        Bitdepth::F64 => 0,
+        Bitdepth::I32 => 1,
+        Bitdepth::I16 => 2,
+        Bitdepth::U8 => 3,
+    };
+    let layout = match layout {
+        Layout::Indices => 0,
+        Layout::Gaps => 1,
+        Layout::Pairs => 2,
+    };
+    layout * 4 + bitdepth
+}
+
+fn from_header(header: u8) -> Result<(Layout, Bitdepth), DecodeError> {
+    let bitdepth = match header % 4 {
+        0 => Bitdepth::F64,
+        1 => Bitdepth::I32,
+        2 => Bitdepth::I16,
+        _ => Bitdepth::U8,
+    };
+    let layout = match header / 4 {
+        0 => Layout::Indices,
+        1 => Layout::Gaps,
+        2 => Layout::Pairs,
+        _ => return Err(DecodeError::Other("Unknown RLE layout")),
+    };
+    Ok((layout, bitdepth))
+}
+
+/// Writes the value in the bitdepth of the frame
+fn encode_value<E: Encoder>(
+    value: f64,
+    bitdepth: Bitdepth,
+    encoder: &mut E,
+) -> Result<(), EncodeError> {
+    match bitdepth {
+        Bitdepth::U8 => Encode::encode(&(value as u8), encoder),
+        Bitdepth::I16 => Encode::encode(&(value as i16), encoder),
+        Bitdepth::I32 => Encode::encode(&(value as i32), encoder),
+        Bitdepth::F64 => Encode::encode(&value, encoder),
+    }
+}
+
+fn decode_value<D: Decoder>(bitdepth: Bitdepth, decoder: &mut D) -> Result<f64, DecodeError> {
+    Ok(match bitdepth {
+        Bitdepth::U8 => u8::decode(decoder)? as f64,
+        Bitdepth::I16 => i16::decode(decoder)? as f64,
+        Bitdepth::I32 => i32::decode(decoder)? as f64,
+        Bitdepth::F64 => f64::decode(decoder)?,
+    })
+}
+
 /// Run Length Encoding compressor
 #[derive(PartialEq, Debug, Clone)]
 pub struct IndexRLE {
@@ -35,31 +105,44 @@ pub struct IndexRLE {
     /// A series of [1,1,1,1,2,1,1,1,1] would have 1 as a value with indexes at 0 and 5.
     pub rle: Vec<(f64, Vec<usize>)>,
     bitdepth: Bitdepth,
+    layout: Layout,
 }
 
 impl Encode for IndexRLE {
-    fn encode<__E: ::bincode::enc::Encoder>(
-        &self,
-        encoder: &mut __E,
-    ) -> Result<(), ::bincode::error::EncodeError> {
+    fn encode<E: Encoder>(&self, encoder: &mut E) -> Result<(), EncodeError> {
         Encode::encode(&self.id, encoder)?;
-        Encode::encode(&self.bitdepth, encoder)?;
-        match &self.bitdepth {
-            Bitdepth::U8 => {
-                debug!("Encoding as u8");
-                Encode::encode(&self.rle_as_u8(), encoder)?;
-            }
-            Bitdepth::I16 => {
-                debug!("Encoding as i16");
-                Encode::encode(&self.rle_as_i16(), encoder)?;
+        Encode::encode(&header(self.layout, self.bitdepth), encoder)?;
+        debug!("Encoding as {:?} {:?}", self.layout, self.bitdepth);
+        match self.layout {
+            Layout::Indices => {
+                Encode::encode(&(self.rle.len() as u64), encoder)?;
+                for (value, indices) in &self.rle {
+                    encode_value(*value, self.bitdepth, encoder)?;
+                    Encode::encode(indices, encoder)?;
+                }
             }
-            Bitdepth::I32 => {
-                debug!("Encoding as i32");
-                Encode::encode(&self.rle_as_i32(), encoder)?;
+            Layout::Gaps => {
+                Encode::encode(&(self.rle.len() as u64), encoder)?;
+                for (value, indices) in &self.rle {
+                    encode_value(*value, self.bitdepth, encoder)?;
+                    Encode::encode(&(indices.len() as u64), encoder)?;
+                    let mut previous = 0;
+                    for &index in indices {
+                        Encode::encode(&((index - previous) as u64), encoder)?;
+                        previous = index;
+                    }
+                }
             }
-            Bitdepth::F64 => {
-                debug!("Encoding as f64");
-                Encode::encode(&self.rle, encoder)?;
+            Layout::Pairs => {
+                let runs = self.runs();
+                Encode::encode(&(runs.len() as u64), encoder)?;
+                for (i, &(start, value)) in runs.iter().enumerate() {
+                    encode_value(value, self.bitdepth, encoder)?;
+                    // The last run goes to the end of the frame, it has no length
+                    if let Some(&(next, _)) = runs.get(i + 1) {
+                        Encode::encode(&((next - start) as u64), encoder)?;
+                    }
+                }
             }
         }
         Ok(())
@@ -67,125 +150,100 @@ impl Encode for IndexRLE {
 }
 
 impl Decode for IndexRLE {
-    fn decode<__D: ::bincode::de::Decoder>(
-        decoder: &mut __D,
-    ) -> Result<Self, ::bincode::error::DecodeError> {
+    fn decode<D: Decoder>(decoder: &mut D) -> Result<Self, DecodeError> {
         let id = Decode::decode(decoder)?;
-        let bitdepth = Decode::decode(decoder)?;
-        let rle: Vec<(f64, Vec<usize>)> = match bitdepth {
-            Bitdepth::U8 => {
-                debug!("Decoding as u8");
-                let rle_u8: Vec<(u8, Vec<usize>)> = Decode::decode(decoder)?;
-                rle_u8
-                    .into_iter()
-                    .map(|(value, indices)| (value as f64, indices))
-                    .collect()
-            }
-            Bitdepth::I16 => {
-                debug!("Decoding as i16");
-                let rle_i16: Vec<(i16, Vec<usize>)> = Decode::decode(decoder)?;
-                rle_i16
-                    .into_iter()
-                    .map(|(value, indices)| (value as f64, indices))
-                    .collect()
-            }
-            Bitdepth::I32 => {
-                debug!("Decoding as i32");
-                let rle_i32: Vec<(i32, Vec<usize>)> = Decode::decode(decoder)?;
-                rle_i32
+        let (layout, bitdepth) = from_header(Decode::decode(decoder)?)?;
+        debug!("Decoding as {:?} {:?}", layout, bitdepth);
+        let count = u64::decode(decoder)? as usize;
+        let rle = match layout {
+            Layout::Indices => (0..count)
+                .map(|_| Ok((decode_value(bitdepth, decoder)?, Decode::decode(decoder)?)))
+                .collect::<Result<_, DecodeError>>()?,
+            Layout::Gaps => (0..count)
+                .map(|_| {
+                    let value = decode_value(bitdepth, decoder)?;
+                    let starts = u64::decode(decoder)? as usize;
+                    let mut index = 0;
+                    let indices = (0..starts)
+                        .map(|_| {
+                            index += u64::decode(decoder)? as usize;
+                            Ok(index)
+                        })
+                        .collect::<Result<_, DecodeError>>()?;
+                    Ok((value, indices))
+                })
+                .collect::<Result<_, DecodeError>>()?,
+            Layout::Pairs => {
+                let mut grouped: BTreeMap<u64, Vec<usize>> = BTreeMap::new();
+                let mut start = 0;
+                for run in 0..count {
+                    let value = decode_value(bitdepth, decoder)?;
+                    grouped.entry(value.to_bits()).or_default().push(start);
+                    if run + 1 < count {
+                        start += u64::decode(decoder)? as usize;
+                    }
+                }
+                grouped
                     .into_iter()
-                    .map(|(value, indices)| (value as f64, indices))
+                    .map(|(value, indices)| (f64::from_bits(value), indices))
                     .collect()
             }
-            Bitdepth::F64 => {
-                debug!("Decoding as f64");
-                let rle_f64: Vec<(f64, Vec<usize>)> = Decode::decode(decoder)?;
-                rle_f64
-            }
         };
-
-        Ok(Self { id, rle, bitdepth })
+        Ok(Self {
+            id,
+            rle,
+            bitdepth,
+            layout,
+        })
     }
 }
 
 impl IndexRLE {
-    // Helper functions to convert the RLE data to different types
-    fn rle_as_u8(&self) -> Vec<(u8, Vec<usize>)> {
-        let mut result: Vec<(u8, Vec<usize>)> = Vec::with_capacity(self.rle.len());
-        for (value, indices) in &self.rle {
-            result.push((*value as u8, indices.clone()));
-        }
-        result
-    }
-
-    fn rle_as_i16(&self) -> Vec<(i16, Vec<usize>)> {
-        let mut result: Vec<(i16, Vec<usize>)> = Vec::with_capacity(self.rle.len());
-        for (value, indices) in &self.rle {
-            result.push((*value as i16, indices.clone()));
-        }
-        result
-    }
-
-    fn rle_as_i32(&self) -> Vec<(i32, Vec<usize>)> {
-        let mut result: Vec<(i32, Vec<usize>)> = Vec::with_capacity(self.rle.len());
-        for (value, indices) in &self.rle {
-            result.push((*value as i32, indices.clone()));
-        }
-        result
-    }
-
     /// Creates a new instance of the index based RLE compressor with the size needed to handle the worst case
     /// The "trick" here is to use a BTreeMap for faster performance during the encoding process.
     /// and then convert it to a Vec<(f64, Vec<usize>)> for optimal space usage.
     /// NOTE: HashMap is faster, but we need to outcome to be always the same for the same input, which is not guaranteed
     /// with HashMap.
+    /// The runs are stored with the layout that takes the fewest bytes for the frame.
     pub fn new(data: &[f64], bitdepth: Bitdepth) -> Self {
         debug!("RLE compressor");
-        // Capacity is 10% of the date lenght in the worst case scenario
-        let len = data.len();
         let mut encoded: BTreeMap<u64, Vec<usize>> = BTreeMap::new();
-        let mut i = 0;
-        // This is where we found the first value of the current sequence
-        let mut current_index: usize = 0;
-
-        while i < len {
-            let value = data[i];
-
-            if i + 1 >= len || data[i + 1] != value {
+        for (i, value) in data.iter().enumerate() {
+            // A run starts where the value changes
+            if i == 0 || data[i - 1].to_bits() != value.to_bits() {
                 trace!("Value Change! Storing value: {}", value);
-                // Next value is different so store the current index
-                // First check if we have the value in the map
-                match encoded.get(&value.to_bits()) {
-                    Some(indices) => {
-                        trace!("Found value in map: {:?}", indices);
-                        // We have the value in the map, so we need to add the current index
-                        let mut indices = indices.clone();
-                        indices.push(current_index);
-                        encoded.insert(value.to_bits(), indices);
-                    }
-                    None => {
-                        trace!("Not found value in map!");
-                        // We don't have the value in the map, so we need to create a new entry
-                        encoded.insert(value.to_bits(), vec![current_index]);
-                    }
-                }
-                // Now we need to update the current index (it will start on the next value)
-                current_index = i + 1;
+                encoded.entry(value.to_bits()).or_default().push(i);
             }
-            i += 1;
         }
         trace!("Encoded: {:?}", encoded);
-        // Convert HashMap to Vec<(RLE, Vec<usize>)>
-        let mut result: Vec<(f64, Vec<usize>)> = Vec::with_capacity(encoded.len());
-        for (value, indices) in encoded {
-            result.push((f64::from_bits(value), indices));
-        }
-        trace!("Vector: {:?}", result);
-        IndexRLE {
+        let rle: Vec<(f64, Vec<usize>)> = encoded
+            .into_iter()
+            .map(|(value, indices)| (f64::from_bits(value), indices))
+            .collect();
+        trace!("Vector: {:?}", rle);
+        let mut c = IndexRLE {
             id: RLE_COMPRESSOR_ID,
-            rle: result,
+            rle,
             bitdepth,
+            layout: Layout::Gaps,
+        };
+        let gaps = c.to_bytes().len();
+        c.layout = Layout::Pairs;
+        if c.to_bytes().len() >= gaps {
+            c.layout = Layout::Gaps;
         }
+        c
+    }
+
+    /// Start and value of every run, in order
+    fn runs(&self) -> Vec<(usize, f64)> {
+        let mut runs: Vec<(usize, f64)> = self
+            .rle
+            .iter()
+            .flat_map(|(value, indices)| indices.iter().map(|&index| (index, *value)))
+            .collect();
+        runs.sort_unstable_by_key(|&(index, _)| index);
+        runs
     }
 
     /// Receives a data stream and generates a Constant
@@ -203,31 +261,10 @@ impl IndexRLE {
 
     pub fn to_data(&self, frame_size: usize) -> Vec<f64> {
         let mut data: Vec<f64> = vec![0.0; frame_size];
-
-        // Optimize allocation, 1st: Calculate the total number of elements in the flattened vector
-        let total_elements: usize = self.rle.iter().map(|(_, indices)| indices.len()).sum();
-
-        // 2nd: Reserve the exact capacity for the flattened vector
-        let mut flattened: Vec<(usize, f64)> = Vec::with_capacity(total_elements);
-
-        // Flatten the RLE representation into a vector of (index, value) pairs
-        for (value, indices) in &self.rle {
-            for &index in indices.iter() {
-                flattened.push((index, *value));
-            }
-        }
-
-        // Sort the flattened vector by index
-        flattened.sort_unstable_by_key(|&(index, _)| index);
-
+        let runs = self.runs();
         // Fill the sequence based on the sorted (index, value) pairs
-        for i in 0..flattened.len() {
-            let (start_index, value) = flattened[i];
-            let end_index = if i + 1 < flattened.len() {
-                flattened[i + 1].0
-            } else {
-                frame_size
-            };
+        for (i, &(start_index, value)) in runs.iter().enumerate() {
+            let end_index = runs.get(i + 1).map_or(frame_size, |&(next, _)| next);
             for idx in data.iter_mut().take(end_index).skip(start_index) {
                 *idx = value;
             }
@@ -288,7 +325,7 @@ mod tests {
 
     #[test]
     fn test_for_constant() {
-        assert_roundtrip(&[1.0; 512], &[60, 3, 1, 1, 1, 0]);
+        assert_roundtrip(&[1.0; 512], &[60, 11, 1, 1]);
     }
 
     #[test]
@@ -297,7 +334,7 @@ mod tests {
             &[
                 1.0, 2.0, 2.0, 3.0, 3.0, 3.0, 4.0, 4.0, 4.0, 4.0, 5.0, 5.0, 5.0, 5.0, 5.0,
             ],
-            &[60, 3, 5, 1, 1, 0, 2, 1, 1, 3, 1, 3, 4, 1, 6, 5, 1, 10],
+            &[60, 11, 5, 1, 1, 2, 2, 3, 3, 4, 4, 5],
         );
     }
 
@@ -308,7 +345,7 @@ mod tests {
                 1.0, 1.0, 1.0, 1.0, 2.0, 2.0, 1.0, 1.0, 1.0, 1.0, 2.0, 2.0, 3.0, 3.0, 3.0, 3.0,
                 3.0, 3.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0,
             ],
-            &[60, 3, 3, 1, 3, 0, 6, 18, 2, 2, 4, 10, 3, 1, 12],
+            &[60, 11, 6, 1, 4, 2, 2, 1, 4, 2, 2, 3, 6, 1],
         );
     }
 
@@ -316,7 +353,59 @@ mod tests {
     fn test_rle_f64() {
         assert_roundtrip(
             &[1.23456, 1.23456, 1.23456, 1.23456, 1.23456],
-            &[60, 0, 1, 56, 50, 143, 252, 193, 192, 243, 63, 1, 0],
+            &[60, 8, 1, 56, 50, 143, 252, 193, 192, 243, 63],
         );
     }
+
+    #[test]
+    fn test_layouts() {
+        // Few values that take many bytes are stored once with gaps
+        let data: Vec<f64> = (0..4096).map(|x| ((x / 3) % 2) as f64 + 0.5).collect();
+        let rle = IndexRLE::new(&data, Bitdepth::F64);
+        assert_eq!(rle.layout, Layout::Gaps);
+        assert_eq!(rle_to_data(data.len(), &rle.to_bytes()), data);
+        // Many values with few runs each take fewer bytes as pairs
+        let data: Vec<f64> = (0..4096).map(|x| (x / 3 % 200) as f64).collect();
+        let rle = IndexRLE::new(&data, Bitdepth::U8);
+        assert_eq!(rle.layout, Layout::Pairs);
+        assert_eq!(rle_to_data(data.len(), &rle.to_bytes()), data);
+    }
+
+    #[test]
+    fn test_smaller_than_indices() {
+        // Long series with many runs
+        let data: Vec<f64> = (0..100_000).map(|x| ((x / 3) % 2) as f64).collect();
+        let mut rle = IndexRLE::new(&data, Bitdepth::U8);
+        let size = rle.to_bytes().len();
+        rle.layout = Layout::Indices;
+        let indices = rle.to_bytes().len();
+        // A byte per run against up to 5 for the indices
+        assert!(size < 34_000, "{}", size);
+        assert!(size * 3 < indices, "{} {}", size, indices);
+    }
+
+    #[test]
+    fn test_decodes_indices_layout() {
+        // Frames written before the layouts, the byte after the ID is only the bitdepth
+        let frames: [(&[f64], &[u8]); 3] = [
+            (&[1.0; 512], &[60, 3, 1, 1, 1, 0]),
+            (
+                &[
+                    1.0, 2.0, 2.0, 3.0, 3.0, 3.0, 4.0, 4.0, 4.0, 4.0, 5.0, 5.0, 5.0, 5.0, 5.0,
+                ],
+                &[60, 3, 5, 1, 1, 0, 2, 1, 1, 3, 1, 3, 4, 1, 6, 5, 1, 10],
+            ),
+            (
+                &[1.23456, 1.23456, 1.23456, 1.23456, 1.23456],
+                &[60, 0, 1, 56, 50, 143, 252, 193, 192, 243, 63, 1, 0],
+            ),
+        ];
+        for (data, encoded) in frames {
+            let rle = IndexRLE::decompress(encoded);
+            assert_eq!(rle.layout, Layout::Indices);
+            assert_eq!(rle.to_data(data.len()), data);
+            // Written back the same way
+            assert_eq!(rle.to_bytes(), encoded);
+        }
+    }
 }
@@ -0,0 +1,329 @@
+/*
+Copyright 2024 NetApp, Inc.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    https://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use crate::{
+    compressor::CompressorResult,
+    utils::{error::calculate_error, median},
+};
+
+use super::{BinConfig, DEFAULT_MAX_ERROR};
+use bincode::{Decode, Encode};
+use log::{debug, info, trace};
+
+const SPIKE_COMPRESSOR_ID: u8 = 80;
+/// Highest degree of the baseline, 0 is a constant, 1 a line and 2 a parabola
+const MAX_DEGREE: usize = 2;
+/// Rounds of fitting the baseline to the samples that are not spikes
+const FIT_ROUNDS: usize = 4;
+/// Samples further from the baseline than this many standard deviations (estimated from the median absolute
+/// deviation) are left out of the fit
+const OUTLIER_DEVIATIONS: f64 = 3.0 * 1.4826;
+
+/// Spike Compressor. A baseline (constant, line or parabola) plus the samples it misses by more than the error,
+/// stored exactly with their positions. The baseline is fitted leaving the spikes out, so they don't pull it away
+/// from the rest of the frame. Spikes keep their original value, every other sample is within the error of its
+/// value. Always LOSSY
+#[derive(Encode, Decode, PartialEq, Debug)]
+pub struct Spike {
+    pub id: u8,
+    /// Coefficients of the baseline over the position divided by the frame size, from the constant one
+    coefficients: Vec<f64>,
+    /// Positions of the spikes, each one as the distance to the previous one
+    gaps: Vec<u32>,
+    /// Values of the spikes
+    values: Vec<f64>,
+}
+
+impl Spike {
+    pub fn new() -> Self {
+        debug!("Spike compressor");
+        Spike {
+            id: SPIKE_COMPRESSOR_ID,
+            coefficients: Vec::new(),
+            gaps: Vec::new(),
+            values: Vec::new(),
+        }
+    }
+
+    /// Number of samples stored exactly
+    pub fn spike_count(&self) -> usize {
+        self.values.len()
+    }
+
+    /// Degree of the baseline
+    pub fn degree(&self) -> usize {
+        self.coefficients.len().saturating_sub(1)
+    }
+
+    /// Compresses the data with the baseline degree that takes the fewest bytes
+    pub fn compress_bounded(&mut self, data: &[f64], max_err: f64) {
+        let best = (0..=MAX_DEGREE)
+            .map(|degree| {
+                let mut c = Spike::new();
+                c.set_baseline(data, Spike::fit(data, degree, max_err), max_err);
+                c
+            })
+            .min_by_key(|c| c.to_bytes().len())
+            .unwrap();
+        *self = best;
+        trace!(
+            "Samples: {} Degree: {} Spikes: {}",
+            data.len(),
+            self.degree(),
+            self.spike_count()
+        );
+    }
+
+    /// Fits a baseline of `degree` to the data, leaving out the samples far from it. Starts from the median, so a
+    /// frame that is mostly flat ignores its spikes from the first round.
+    fn fit(data: &[f64], degree: usize, max_err: f64) -> Vec<f64> {
+        let finite: Vec<f64> = data.iter().copied().filter(|x| x.is_finite()).collect();
+        let mut coefficients = vec![median(&finite)];
+        let mut inliers = Vec::new();
+        for _ in 0..FIT_ROUNDS {
+            let baseline = evaluate(&coefficients, data.len());
+            let deviations: Vec<f64> = data
+                .iter()
+                .zip(&baseline)
+                .filter(|(x, _)| x.is_finite())
+                .map(|(x, y)| (x - y).abs())
+                .collect();
+            let spread = median(&deviations) * OUTLIER_DEVIATIONS;
+            let next: Vec<usize> = (0..data.len())
+                .filter(|&i| {
+                    let x = data[i];
+                    x.is_finite() && (x - baseline[i]).abs() <= spread.max((x * max_err).abs())
+                })
+                .collect();
+            if next == inliers || next.len() <= degree {
+                break;
+            }
+            inliers = next;
+            coefficients = least_squares(data, &inliers, degree);
+        }
+        coefficients
+    }
+
+    /// Keeps the samples the baseline misses by more than the error as spikes
+    fn set_baseline(&mut self, data: &[f64], coefficients: Vec<f64>, max_err: f64) {
+        let baseline = evaluate(&coefficients, data.len());
+        self.coefficients = coefficients;
+        self.gaps.clear();
+        self.values.clear();
+        let mut previous = 0;
+        for (i, (&x, &y)) in data.iter().zip(&baseline).enumerate() {
+            // Not finite samples are never within the error, they stay as they are
+            let within = x.is_finite() && (x - y).abs() <= (x * max_err).abs();
+            if !within {
+                self.gaps.push((i - previous) as u32);
+                self.values.push(x);
+                previous = i;
+            }
+        }
+    }
+
+    pub fn decompress(data: &[u8]) -> Self {
+        let config = BinConfig::get();
+        let (spike, _) = bincode::decode_from_slice(data, config).unwrap();
+        spike
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let config = BinConfig::get();
+        bincode::encode_to_vec(self, config).unwrap()
+    }
+
+    /// Returns the `frame_size` samples of the frame, the baseline with the spikes in place
+    pub fn to_data(&self, frame_size: usize) -> Vec<f64> {
+        let mut data = evaluate(&self.coefficients, frame_size);
+        let mut position = 0;
+        for (&gap, &value) in self.gaps.iter().zip(&self.values) {
+            position += gap as usize;
+            if let Some(sample) = data.get_mut(position) {
+                *sample = value;
+            }
+        }
+        data
+    }
+}
+
+impl Default for Spike {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Baseline values of the frame, the position goes from 0 to 1 so the coefficients stay in the range of the data
+fn evaluate(coefficients: &[f64], frame_size: usize) -> Vec<f64> {
+    (0..frame_size)
+        .map(|i| {
+            let t = i as f64 / frame_size as f64;
+            coefficients.iter().rev().fold(0.0, |acc, c| acc * t + c)
+        })
+        .collect()
+}
+
+/// Least squares polynomial of `degree` over the samples at `positions`, by Gaussian elimination of the normal
+/// equations. Coefficients the samples can't determine are left at 0.
+fn least_squares(data: &[f64], positions: &[usize], degree: usize) -> Vec<f64> {
+    let size = degree + 1;
+    // Augmented matrix of the normal equations
+    let mut matrix = vec![vec![0.0; size + 1]; size];
+    for &i in positions {
+        let t = i as f64 / data.len() as f64;
+        let powers: Vec<f64> = (0..size).map(|k| t.powi(k as i32)).collect();
+        for row in 0..size {
+            for column in 0..size {
+                matrix[row][column] += powers[row] * powers[column];
+            }
+            matrix[row][size] += powers[row] * data[i];
+        }
+    }
+    for column in 0..size {
+        let pivot = (column..size)
+            .max_by(|&a, &b| matrix[a][column].abs().total_cmp(&matrix[b][column].abs()))
+            .unwrap();
+        matrix.swap(column, pivot);
+        if matrix[column][column].abs() <= f64::EPSILON * positions.len() as f64 {
+            matrix[column].iter_mut().for_each(|x| *x = 0.0);
+            continue;
+        }
+        for row in 0..size {
+            if row != column {
+                let factor = matrix[row][column] / matrix[column][column];
+                for k in column..=size {
+                    matrix[row][k] -= factor * matrix[column][k];
+                }
+            }
+        }
+    }
+    matrix
+        .iter()
+        .enumerate()
+        .map(|(k, row)| {
+            if row[k] == 0.0 {
+                0.0
+            } else {
+                row[size] / row[k]
+            }
+        })
+        .collect()
+}
+
+/// Compresses as a baseline and spikes, keeping the samples that are not spikes within the default error
+pub fn spike(data: &[f64]) -> Vec<u8> {
+    info!("Initializing Spike Compressor");
+    let mut c = Spike::new();
+    c.compress_bounded(data, DEFAULT_MAX_ERROR);
+    c.to_bytes()
+}
+
+/// Compress keeping the samples that are not spikes within the max error allowed
+pub fn spike_compressor(data: &[f64], allowed_error: f64) -> CompressorResult {
+    debug!("Initializing Spike Compressor. Error provided");
+    let mut c = Spike::new();
+    c.compress_bounded(data, allowed_error);
+    let error = calculate_error(data, &c.to_data(data.len()));
+    debug!(
+        "Degree: {}, Spikes: {}, Error: {}",
+        c.degree(),
+        c.spike_count(),
+        error
+    );
+    CompressorResult::new(c.to_bytes(), error)
+}
+
+/// Uncompress a Spike frame
+pub fn spike_to_data(sample_number: usize, compressed_data: &[u8]) -> Vec<f64> {
+    Spike::decompress(compressed_data).to_data(sample_number)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compressor::fft::fft_compressor;
+    use crate::compressor::test_utils::{assert_within, spiky};
+    use crate::optimizer::utils::DataStats;
+
+    #[test]
+    fn test_spikes_are_exact() {
+        let data = spiky(1000);
+        let result = spike_compressor(&data, 0.01);
+        let c = Spike::decompress(&result.compressed_data);
+        assert_eq!(c.degree(), 1);
+        assert_eq!(c.spike_count(), 9);
+        let out = c.to_data(data.len());
+        assert_within(&data, &out, 0.01);
+        for x in (5..data.len()).step_by(113) {
+            assert_eq!(out[x], data[x]);
+        }
+        assert_eq!(calculate_error(&data, &out), result.error);
+    }
+
+    #[test]
+    fn test_smaller_than_fft() {
+        let data = spiky(1000);
+        let spike = spike_compressor(&data, 0.01);
+        let fft = fft_compressor(&data, 0.01, DataStats::new(&data));
+        assert!(
+            spike.compressed_data.len() * 10 < fft.compressed_data.len(),
+            "{} {}",
+            spike.compressed_data.len(),
+            fft.compressed_data.len()
+        );
+    }
+
+    #[test]
+    fn test_baselines() {
+        let constant: Vec<f64> = (0..100).map(|x| if x == 40 { 9.5 } else { 3.0 }).collect();
+        let parabola: Vec<f64> = (0..100)
+            .map(|x| ((x - 50) * (x - 50)) as f64 + 10.0)
+            .collect();
+        for (data, degree, spikes) in [(constant, 0, 1), (parabola, 2, 0)] {
+            let c = Spike::decompress(&spike(&data));
+            assert_eq!(c.degree(), degree);
+            assert_eq!(c.spike_count(), spikes);
+            assert_within(&data, &c.to_data(data.len()), DEFAULT_MAX_ERROR);
+        }
+    }
+
+    #[test]
+    fn test_zeros_and_not_finite() {
+        let data = [0.0, 0.0, 5.0, 0.0, f64::INFINITY, 0.0, -3.0, 0.0];
+        let out = spike_to_data(data.len(), &spike(&data));
+        assert_eq!(out, data);
+    }
+
+    #[test]
+    fn test_short_frames() {
+        for data in [vec![], vec![7.0], vec![7.0, 8.0]] {
+            let out = spike_to_data(data.len(), &spike(&data));
+            assert_within(&data, &out, DEFAULT_MAX_ERROR);
+        }
+    }
+
+    #[test]
+    fn test_least_squares() {
+        let data: Vec<f64> = (0..10).map(|x| 2.0 + 3.0 * x as f64 / 10.0).collect();
+        let positions: Vec<usize> = (0..10).collect();
+        let coefficients = least_squares(&data, &positions, 2);
+        for (c, expected) in coefficients.iter().zip([2.0, 3.0, 0.0]) {
+            assert!((c - expected).abs() < 1e-9, "{:?}", coefficients);
+        }
+        // A single position can't tell the slope
+        assert_eq!(least_squares(&data, &[4], 1)[1], 0.0);
+    }
+}
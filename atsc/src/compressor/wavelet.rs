@@ -0,0 +1,495 @@
+/*
+Copyright 2024 NetApp, Inc.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    https://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use crate::{
+    compressor::CompressorResult,
+    optimizer::utils::DataStats,
+    utils::{error::calculate_error, round_and_limit_f64, smallest_passing, DECIMAL_PRECISION},
+};
+
+use super::quantization::{
+    coarsest_exponent, dequantize, fine_exponent, quantize, top_exponent, Quantized,
+};
+use super::BinConfig;
+use bincode::{Decode, Encode};
+use log::{debug, info, trace};
+
+const WAVELET_COMPRESSOR_ID: u8 = 40;
+/// Deepest decomposition, the approximation left is 1/256 of the frame
+const MAX_LEVELS: u8 = 8;
+
+/// Scaling filter of the wavelets, the wavelet filter is derived from it
+const HAAR: [f64; 2] = [
+    std::f64::consts::FRAC_1_SQRT_2,
+    std::f64::consts::FRAC_1_SQRT_2,
+];
+const DAUBECHIES_2: [f64; 4] = [
+    0.48296291314453416,
+    0.8365163037378079,
+    0.2241438680420134,
+    -0.12940952255126037,
+];
+const DAUBECHIES_4: [f64; 8] = [
+    0.2303778133088965,
+    0.7148465705529154,
+    0.6308807679298589,
+    -0.02798376941685985,
+    -0.18703481171909309,
+    0.03084138183556076,
+    0.0328830116668852,
+    -0.01059740178506903,
+];
+
+/// Wavelet the frame is decomposed with. Haar fits steps and bursts, the longer Daubechies wavelets fit smoother
+/// changes.
+#[derive(Encode, Decode, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WaveletFamily {
+    #[default]
+    Haar,
+    /// Daubechies with 2 vanishing moments (4 taps)
+    Daubechies2,
+    /// Daubechies with 4 vanishing moments (8 taps)
+    Daubechies4,
+}
+
+impl WaveletFamily {
+    const ALL: [WaveletFamily; 3] = [
+        WaveletFamily::Haar,
+        WaveletFamily::Daubechies2,
+        WaveletFamily::Daubechies4,
+    ];
+
+    fn filter(&self) -> &'static [f64] {
+        match self {
+            WaveletFamily::Haar => &HAAR,
+            WaveletFamily::Daubechies2 => &DAUBECHIES_2,
+            WaveletFamily::Daubechies4 => &DAUBECHIES_4,
+        }
+    }
+
+    /// Levels a frame of `len` samples is decomposed in. Each level halves the approximation, while it is longer
+    /// than the filter, and the frame is padded to a multiple of 2^levels. Levels are dropped if that padding would
+    /// be more than 1/16 of the frame.
+    fn levels(&self, len: usize) -> u8 {
+        let taps = self.filter().len();
+        let mut levels = 0;
+        while levels < MAX_LEVELS && len >> (levels + 1) >= taps {
+            levels += 1;
+        }
+        while levels > 0 && padded_len(len, levels) - len > len / 16 {
+            levels -= 1;
+        }
+        levels
+    }
+
+    /// Wavelet (high pass) filter tap `k`, the scaling filter reversed with alternating signs
+    fn high(filter: &[f64], k: usize) -> f64 {
+        let tap = filter[filter.len() - 1 - k];
+        if k % 2 == 0 {
+            tap
+        } else {
+            -tap
+        }
+    }
+
+    /// Decomposes `data` in place, the length has to be a multiple of 2^`levels`. Ends up with the approximation
+    /// of the last level first, followed by the details from the last level to the first one.
+    fn forward(&self, data: &mut [f64], levels: u8) {
+        let filter = self.filter();
+        let mut scratch = vec![0.0; data.len()];
+        for level in 0..levels {
+            let n = data.len() >> level;
+            let half = n / 2;
+            for i in 0..half {
+                let (mut approximation, mut detail) = (0.0, 0.0);
+                for (k, tap) in filter.iter().enumerate() {
+                    // Periodic, the frame repeats itself past its end
+                    let x = data[(2 * i + k) % n];
+                    approximation += tap * x;
+                    detail += WaveletFamily::high(filter, k) * x;
+                }
+                scratch[i] = approximation;
+                scratch[half + i] = detail;
+            }
+            data[..n].copy_from_slice(&scratch[..n]);
+        }
+    }
+
+    /// Reverts `forward` in place
+    fn inverse(&self, coefficients: &mut [f64], levels: u8) {
+        let filter = self.filter();
+        let mut scratch = vec![0.0; coefficients.len()];
+        for level in (0..levels).rev() {
+            let n = coefficients.len() >> level;
+            let half = n / 2;
+            scratch[..n].fill(0.0);
+            for i in 0..half {
+                let (approximation, detail) = (coefficients[i], coefficients[half + i]);
+                for (k, tap) in filter.iter().enumerate() {
+                    scratch[(2 * i + k) % n] +=
+                        tap * approximation + WaveletFamily::high(filter, k) * detail;
+                }
+            }
+            coefficients[..n].copy_from_slice(&scratch[..n]);
+        }
+    }
+}
+
+/// Samples of a frame of `len` samples once padded for `levels` levels
+fn padded_len(len: usize, levels: u8) -> usize {
+    len.next_multiple_of(1 << levels)
+}
+
+/// Wavelet compressor. Decomposes the frame with a discrete wavelet transform and keeps the biggest coefficients.
+/// The wavelets are short and localized, so a burst or a step only needs the coefficients around it, where FFT
+/// spreads it over all the frequencies. Always LOSSY
+#[derive(Encode, Decode, PartialEq, Debug)]
+pub struct Wavelet {
+    pub id: u8,
+    pub family: WaveletFamily,
+    /// Levels of the decomposition, the frame is padded to a multiple of 2^levels
+    pub levels: u8,
+    /// Exponent of the power of two the coefficients are multiples of
+    pub exponent: i8,
+    /// Kept coefficients, sorted by position
+    coefficients: Vec<Quantized<i32>>,
+    pub max_value: f32,
+    pub min_value: f32,
+}
+
+/// Coefficients of a frame with a wavelet family
+struct Analysis {
+    family: WaveletFamily,
+    levels: u8,
+    coefficients: Vec<f64>,
+    /// Positions of the coefficients from the biggest to the smallest, without the empty ones
+    ranked: Vec<usize>,
+}
+
+impl Analysis {
+    fn new(data: &[f64], family: WaveletFamily) -> Self {
+        let levels = family.levels(data.len());
+        let mut coefficients = Wavelet::pad(data, padded_len(data.len(), levels));
+        family.forward(&mut coefficients, levels);
+        let mut ranked: Vec<usize> = (0..coefficients.len())
+            .filter(|&pos| coefficients[pos] != 0.0 && coefficients[pos].is_finite())
+            .collect();
+        ranked.sort_by(|&a, &b| coefficients[b].abs().total_cmp(&coefficients[a].abs()));
+        Analysis {
+            family,
+            levels,
+            coefficients,
+            ranked,
+        }
+    }
+
+    /// Positions and values of the first `count` ranked coefficients
+    fn kept(&self, count: usize) -> impl Iterator<Item = (usize, f64)> + '_ {
+        self.ranked[..count]
+            .iter()
+            .map(|&pos| (pos, self.coefficients[pos]))
+    }
+
+    /// Smallest exponent of a power of two that is bigger than the first `count` ranked coefficients
+    fn top_exponent(&self, count: usize) -> i32 {
+        top_exponent(self.kept(count).map(|(_, value)| value))
+    }
+}
+
+impl Wavelet {
+    pub fn new(min: f64, max: f64) -> Self {
+        debug!("Wavelet compressor: min:{} max:{}", min, max);
+        Wavelet {
+            id: WAVELET_COMPRESSOR_ID,
+            family: WaveletFamily::default(),
+            levels: 0,
+            exponent: 0,
+            coefficients: Vec::new(),
+            max_value: max as f32,
+            min_value: min as f32,
+        }
+    }
+
+    /// Pads the data to `size` samples, going from the last value to the first one with a raised cosine so the
+    /// frame repeats itself without a jump
+    fn pad(data: &[f64], size: usize) -> Vec<f64> {
+        let mut padded = data.to_vec();
+        if let (Some(&first), Some(&last)) = (data.first(), data.last()) {
+            let added_len = size - data.len();
+            padded.extend((1..=added_len).map(|k| {
+                let phase = std::f64::consts::PI * k as f64 / (added_len + 1) as f64;
+                last + (first - last) * (1.0 - phase.cos()) / 2.0
+            }));
+        }
+        padded
+    }
+
+    /// Stores the first `count` ranked coefficients as multiples of 2^`exponent`, the ones that round to 0 are
+    /// dropped
+    fn set_coefficients(&mut self, analysis: &Analysis, count: usize, exponent: i8) {
+        self.coefficients = quantize(analysis.kept(count), exponent);
+        self.exponent = exponent;
+        self.family = analysis.family;
+        self.levels = analysis.levels;
+    }
+
+    /// Keeps `count` coefficients with the fine quantization
+    fn set_fine(&mut self, analysis: &Analysis, count: usize) {
+        let exponent = fine_exponent(analysis.top_exponent(count));
+        self.set_coefficients(analysis, count, exponent);
+    }
+
+    /// Positions and values of the kept coefficients
+    fn coefficients(&self) -> impl Iterator<Item = (usize, f64)> + '_ {
+        dequantize(&self.coefficients, self.exponent)
+    }
+
+    /// Number of kept coefficients
+    pub fn coefficient_count(&self) -> usize {
+        self.coefficients.len()
+    }
+
+    /// Compresses keeping `count` coefficients, finely quantized, of the family with the lowest error
+    pub fn compress_hinted(&mut self, data: &[f64], count: usize) {
+        let mut best_err = f64::INFINITY;
+        for family in WaveletFamily::ALL {
+            let analysis = Analysis::new(data, family);
+            let mut candidate = Wavelet::new(self.min_value.into(), self.max_value.into());
+            candidate.set_fine(&analysis, count.min(analysis.ranked.len()));
+            let err = calculate_error(data, &candidate.to_data(data.len()));
+            trace!("Family: {:?} Current Err: {}", family, err);
+            // The first family is kept if no error can be computed
+            if err < best_err || family == WaveletFamily::default() {
+                best_err = best_err.min(err);
+                *self = candidate;
+            }
+        }
+    }
+
+    /// Compresses the data with every family, keeping the fewest coefficients that meet the error, quantized as
+    /// coarsely as the error allows, and keeps the smallest family. Returns the error, if no family meets it the
+    /// one with the lowest error is kept.
+    pub fn compress_bounded(&mut self, data: &[f64], max_err: f64) -> f64 {
+        let mut best: Option<(Wavelet, f64, usize)> = None;
+        for family in WaveletFamily::ALL {
+            let mut candidate = Wavelet::new(self.min_value.into(), self.max_value.into());
+            let err = candidate.compress_family(data, family, max_err);
+            let size = candidate.to_bytes().len();
+            let better = match &best {
+                None => true,
+                Some((_, best_err, best_size)) => match (err <= max_err, *best_err <= max_err) {
+                    (true, true) => size < *best_size,
+                    (true, false) => true,
+                    (false, true) => false,
+                    (false, false) => err < *best_err,
+                },
+            };
+            if better {
+                best = Some((candidate, err, size));
+            }
+        }
+        let (best, err, _) = best.unwrap();
+        *self = best;
+        err
+    }
+
+    /// Compresses with a single family, see `compress_bounded`
+    fn compress_family(&mut self, data: &[f64], family: WaveletFamily, max_err: f64) -> f64 {
+        let analysis = Analysis::new(data, family);
+        let ranked_len = analysis.ranked.len();
+        let default_count = Wavelet::default_count(data.len());
+        let mut iterations = 0;
+        let mut error_of = |count: usize, exponent: i8| -> f64 {
+            iterations += 1;
+            let mut candidate = Wavelet::new(self.min_value.into(), self.max_value.into());
+            candidate.set_coefficients(&analysis, count, exponent);
+            calculate_error(data, &candidate.to_data(data.len()))
+        };
+        if ranked_len == 0 {
+            let error = error_of(0, 0);
+            self.set_coefficients(&analysis, 0, 0);
+            return error;
+        }
+        // Finely quantized, the error drops as coefficients are added
+        let mut errors: Vec<Option<f64>> = vec![None; ranked_len + 1];
+        let mut error_with = |count: usize| -> f64 {
+            *errors[count].get_or_insert_with(|| {
+                let err = error_of(count, fine_exponent(analysis.top_exponent(count)));
+                trace!("Coefficients: {} Current Err: {}", count, err);
+                err
+            })
+        };
+        let mut count = smallest_passing(1, ranked_len, default_count, |count| {
+            error_with(count) <= max_err
+        });
+        // The error can't be computed for some data (e.g. zeros), then there is nothing to search for
+        if !error_with(count).is_finite() {
+            count = default_count.min(ranked_len);
+        }
+        let allowed_err = max_err.max(error_with(count));
+
+        // Search the coarsest step still in the bound
+        let (exponent, error) = coarsest_exponent(
+            analysis.top_exponent(count),
+            |exponent| error_of(count, exponent),
+            |err| err <= allowed_err,
+        );
+        self.set_coefficients(&analysis, count, exponent);
+        debug!(
+            "Family: {:?}, Iterations to convergence: {}, Coefficients P:{} S:{}, Exponent: {}, Error: {}",
+            family,
+            iterations,
+            default_count,
+            self.coefficients.len(),
+            self.exponent,
+            error
+        );
+        error
+    }
+
+    /// Coefficients kept without an error bound, 1/100 of the data length OR 3, which is bigger
+    fn default_count(len: usize) -> usize {
+        (len / 100).max(3)
+    }
+
+    pub fn decompress(data: &[u8]) -> Self {
+        let config = BinConfig::get();
+        let (wavelet, _) = bincode::decode_from_slice(data, config).unwrap();
+        wavelet
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let config = BinConfig::get();
+        bincode::encode_to_vec(self, config).unwrap()
+    }
+
+    /// Returns the `frame_size` samples of the frame
+    pub fn to_data(&self, frame_size: usize) -> Vec<f64> {
+        let mut samples = vec![0.0; padded_len(frame_size, self.levels)];
+        for (pos, value) in self.coefficients() {
+            samples[pos] = value;
+        }
+        self.family.inverse(&mut samples, self.levels);
+        samples.truncate(frame_size);
+        samples
+            .into_iter()
+            .map(|x| {
+                round_and_limit_f64(
+                    x,
+                    self.min_value.into(),
+                    self.max_value.into(),
+                    DECIMAL_PRECISION,
+                )
+            })
+            .collect()
+    }
+}
+
+/// Compresses via wavelets, keeping the default number of coefficients
+pub fn wavelet(data: &[f64]) -> Vec<u8> {
+    info!("Initializing Wavelet Compressor");
+    wavelet_set(data, Wavelet::default_count(data.len()))
+}
+
+/// Compresses via wavelets, keeping `count` coefficients
+pub fn wavelet_set(data: &[f64], count: usize) -> Vec<u8> {
+    let stats = DataStats::new(data);
+    let mut c = Wavelet::new(stats.min, stats.max);
+    c.compress_hinted(data, count);
+    c.to_bytes()
+}
+
+/// Compress targeting a specific max error allowed
+pub fn wavelet_compressor(data: &[f64], allowed_error: f64, stats: DataStats) -> CompressorResult {
+    debug!("Initializing Wavelet Compressor. Error and Stats provided");
+    let mut c = Wavelet::new(stats.min, stats.max);
+    let error = c.compress_bounded(data, allowed_error);
+    CompressorResult::new(c.to_bytes(), error)
+}
+
+/// Uncompress a wavelet frame
+pub fn wavelet_to_data(sample_number: usize, compressed_data: &[u8]) -> Vec<f64> {
+    Wavelet::decompress(compressed_data).to_data(sample_number)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compressor::fft::fft_compressor;
+
+    /// Request rate like data, a slow baseline with a few bursts
+    fn bursts(len: usize) -> Vec<f64> {
+        (0..len)
+            .map(|x| {
+                let burst = match x % 300 {
+                    100..=111 => 400.0,
+                    112..=115 => 150.0,
+                    _ => 0.0,
+                };
+                100.0 + (x as f64 / 200.0).sin() * 10.0 + burst
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_perfect_reconstruction() {
+        let data = bursts(1229);
+        for family in WaveletFamily::ALL {
+            let analysis = Analysis::new(&data, family);
+            assert!(analysis.levels > 0);
+            let mut c = Wavelet::new(0.0, 1000.0);
+            c.set_fine(&analysis, analysis.ranked.len());
+            let out = Wavelet::decompress(&c.to_bytes()).to_data(data.len());
+            for (x, y) in data.iter().zip(&out) {
+                assert!((x - y).abs() < 1e-3, "{:?} {} {}", family, x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn test_to_allowed_error() {
+        let data = bursts(1000);
+        for max_error in [0.001, 0.01, 0.03] {
+            let result = wavelet_compressor(&data, max_error, DataStats::new(&data));
+            let out = wavelet_to_data(data.len(), &result.compressed_data);
+            assert_eq!(calculate_error(&data, &out), result.error);
+            assert!(result.error <= max_error);
+        }
+    }
+
+    #[test]
+    fn test_smaller_than_fft_on_bursts() {
+        let data = bursts(2048);
+        let wavelet = wavelet_compressor(&data, 0.01, DataStats::new(&data));
+        let fft = fft_compressor(&data, 0.01, DataStats::new(&data));
+        assert!(
+            wavelet.compressed_data.len() < fft.compressed_data.len(),
+            "{} {}",
+            wavelet.compressed_data.len(),
+            fft.compressed_data.len()
+        );
+    }
+
+    #[test]
+    fn test_short_frames() {
+        for len in 1..8 {
+            let data = bursts(len);
+            let out = wavelet_to_data(len, &wavelet_set(&data, len));
+            assert_eq!(out.len(), len);
+            assert!(calculate_error(&data, &out) < 1e-6);
+        }
+    }
+}
@@ -14,11 +14,15 @@ See the License for the specific language governing permissions and
 limitations under the License.
 */
 
+use crate::compressor::mdct::{LappedSamples, Neighbours};
 use crate::compressor::{BinConfig, Compressor};
 use crate::frame::CompressorFrame;
 use crate::header::CompressorHeader;
+use crate::optimizer::cache::CachedSelection;
+use crate::optimizer::options::CompressionOptions;
 //use bincode::{Decode, Encode};
 use log::debug;
+use rayon::prelude::*;
 
 #[derive(Debug, Clone)]
 pub struct CompressedStream {
@@ -60,21 +64,180 @@ impl CompressedStream {
         max_error: f32,
         compression_speed: usize,
     ) {
+        let options = CompressionOptions {
+            sample_level: compression_speed,
+            ..Default::default()
+        };
+        self.compress_chunk_bounded_with_options(chunk, compressor, max_error, &options)
+    }
+
+    /// Same as `compress_chunk_bounded_with`, when `compressor` is Auto it is selected as set in `options`
+    pub fn compress_chunk_bounded_with_options(
+        &mut self,
+        chunk: &[f64],
+        compressor: Compressor,
+        max_error: f32,
+        options: &CompressionOptions,
+    ) {
+        self.compress_chunk_bounded_cached(chunk, compressor, max_error, options, None);
+    }
+
+    /// Same as `compress_chunk_bounded_with_options`, when `compressor` is Auto the `cached` selection is tried
+    /// first. Returns the selection of Auto frames, to be cached for the next run.
+    pub fn compress_chunk_bounded_cached(
+        &mut self,
+        chunk: &[f64],
+        compressor: Compressor,
+        max_error: f32,
+        options: &CompressionOptions,
+        cached: Option<CachedSelection>,
+    ) -> Option<CachedSelection> {
+        let (compressor_frame, selection) =
+            CompressedStream::bounded_frame(chunk, compressor, max_error, options, cached);
+        self.data_frames.push(compressor_frame);
+        self.header.add_frame();
+        selection
+    }
+
+    /// Compresses chunks with a specific compressor each, in parallel, adding them as new frames to the current
+    /// stream in the order they are provided. Consecutive MDCT chunks overlap each other. Runs in the current rayon
+    /// thread pool.
+    pub fn compress_chunks_with(&mut self, chunks: &[(Compressor, &[f64])]) {
+        let frames: Vec<CompressorFrame> = chunks
+            .par_iter()
+            .enumerate()
+            .map(|(i, (compressor, chunk))| {
+                let mut compressor_frame = CompressorFrame::new(Some(*compressor));
+                match compressor {
+                    Compressor::Mdct => compressor_frame.compress_lapped(
+                        chunk,
+                        CompressedStream::neighbours(chunks, i),
+                        None,
+                    ),
+                    _ => compressor_frame.compress(chunk),
+                }
+                compressor_frame.close();
+                compressor_frame
+            })
+            .collect();
+        self.add_frames(frames);
+    }
+
+    /// Chunks around chunk `i` that it overlaps, the ones next to it that are MDCT. The chunks are expected to be
+    /// consecutive parts of the same data. Chunks with values that are not finite don't overlap any other, the
+    /// blocks over them would spread those values.
+    fn neighbours<'a>(chunks: &[(Compressor, &'a [f64])], i: usize) -> Neighbours<'a> {
+        let finite = |chunk: &[f64]| chunk.iter().all(|x| x.is_finite());
+        if !finite(chunks[i].1) {
+            return Neighbours::default();
+        }
+        let lapped = |j: Option<usize>| match j.and_then(|j| chunks.get(j)) {
+            Some((Compressor::Mdct, chunk)) if finite(chunk) => *chunk,
+            _ => &[],
+        };
+        Neighbours {
+            before: lapped(i.checked_sub(1)),
+            after: lapped(Some(i + 1)),
+        }
+    }
+
+    /// Same as `compress_chunk_bounded_cached` for many chunks, in parallel. Frames are added in the order the
+    /// chunks are provided, so the output is the same as compressing them one by one, except for consecutive MDCT
+    /// chunks that overlap each other. `cached` has the selection
+    /// to try first for each chunk, it can be shorter than `chunks`. Runs in the current rayon thread pool.
+    /// Returns the selection of each frame, None for the ones that were not Auto.
+    pub fn compress_chunks_bounded_cached(
+        &mut self,
+        chunks: &[(Compressor, &[f64])],
+        max_error: f32,
+        options: &CompressionOptions,
+        cached: &[Option<CachedSelection>],
+    ) -> Vec<Option<CachedSelection>> {
+        debug!(
+            "Compressing {} chunks bounded with a max error of {}",
+            chunks.len(),
+            max_error
+        );
+        let (frames, selections): (Vec<_>, Vec<_>) = chunks
+            .par_iter()
+            .enumerate()
+            .map(|(i, (compressor, chunk))| {
+                let cached = cached.get(i).copied().flatten();
+                if *compressor == Compressor::Mdct {
+                    let mut compressor_frame = CompressorFrame::new(Some(*compressor));
+                    let neighbours = CompressedStream::neighbours(chunks, i);
+                    compressor_frame.compress_lapped(chunk, neighbours, Some(max_error));
+                    compressor_frame.close();
+                    return (compressor_frame, None);
+                }
+                CompressedStream::bounded_frame(chunk, *compressor, max_error, options, cached)
+            })
+            .unzip();
+        self.add_frames(frames);
+        selections
+    }
+
+    /// Compresses a chunk in a closed frame, with the selection when `compressor` is Auto
+    fn bounded_frame(
+        chunk: &[f64],
+        compressor: Compressor,
+        max_error: f32,
+        options: &CompressionOptions,
+        cached: Option<CachedSelection>,
+    ) -> (CompressorFrame, Option<CachedSelection>) {
         debug!(
             "Compressing chunk bounded with a max error of {}",
             max_error
         );
         let mut compressor_frame = CompressorFrame::new(Some(compressor));
-        match compressor {
+        let selection = match compressor {
             // Auto means the frame will pick the best
-            Compressor::Auto => compressor_frame.compress_best(chunk, max_error, compression_speed),
-            _ => compressor_frame.compress_bounded(chunk, max_error),
+            Compressor::Auto => {
+                compressor_frame.compress_best_cached(chunk, max_error, options, cached);
+                Some(compressor_frame.selection())
+            }
+            _ => {
+                compressor_frame.compress_bounded(chunk, max_error);
+                None
+            }
+        };
+        compressor_frame.close();
+        (compressor_frame, selection)
+    }
+
+    fn add_frames(&mut self, frames: Vec<CompressorFrame>) {
+        for frame in frames {
+            self.data_frames.push(frame);
+            self.header.add_frame();
         }
+    }
+
+    /// Adds a chunk that was already compressed with `compressor` as a new frame to the current stream
+    pub fn add_compressed_chunk(
+        &mut self,
+        sample_count: usize,
+        compressor: Compressor,
+        compressed_data: Vec<u8>,
+    ) {
+        let mut compressor_frame =
+            CompressorFrame::from_compressed(compressor, sample_count, compressed_data);
         compressor_frame.close();
         self.data_frames.push(compressor_frame);
         self.header.add_frame();
     }
 
+    /// Bytes used by the stream and frame headers for frames with the provided sample counts, the compressed data
+    /// not included. The compressed data length of each frame is unknown here, so the worst case up to 64KB
+    /// (3 bytes varint) is assumed.
+    pub fn overhead(sample_counts: &[usize]) -> usize {
+        let mut cs = CompressedStream::new();
+        for &sample_count in sample_counts {
+            cs.add_compressed_chunk(sample_count, Compressor::Noop, Vec::new());
+        }
+        // Empty data length takes 1 byte
+        cs.to_bytes().len() + 2 * sample_counts.len()
+    }
+
     /// Transforms the whole CompressedStream into bytes to be written to a file
     pub fn to_bytes(self) -> Vec<u8> {
         let mut out = Vec::new();
@@ -101,17 +264,58 @@ impl CompressedStream {
             data_frames,
         }
     }
+
+    /// Decompresses the frames in parallel, in the current rayon thread pool, and joins them in order. MDCT frames
+    /// get the overlap of the next frame added.
     pub fn decompress(&self) -> Vec<f64> {
-        self.data_frames
-            .iter()
-            .flat_map(|f| f.decompress())
-            .collect()
+        let frames: Vec<Decompressed> = self
+            .data_frames
+            .par_iter()
+            .map(|f| match f.decompress_lapped() {
+                Some(lapped) => Decompressed::Lapped(lapped),
+                None => Decompressed::Samples(f.decompress()),
+            })
+            .collect();
+        let mut out = Vec::new();
+        // MDCT frame waiting for the overlap of the next one, with the position of its samples
+        let mut open: Option<(usize, LappedSamples)> = None;
+        for frame in frames {
+            if let Some((start, previous)) = open.take() {
+                if let (Decompressed::Lapped(next), false) = (&frame, previous.trailing) {
+                    let overlap_start = out.len().saturating_sub(next.overlap.len()).max(start);
+                    for (x, overlap) in out[overlap_start..].iter_mut().zip(&next.overlap) {
+                        *x += overlap;
+                    }
+                }
+                previous.finish(&mut out[start..]);
+            }
+            match frame {
+                Decompressed::Lapped(mut lapped) => {
+                    let start = out.len();
+                    out.append(&mut lapped.samples);
+                    open = Some((start, lapped));
+                }
+                Decompressed::Samples(mut samples) => out.append(&mut samples),
+            }
+        }
+        if let Some((start, previous)) = open {
+            previous.finish(&mut out[start..]);
+        }
+        out
     }
 }
 
+/// Output of a frame, MDCT frames are only final once joined with their neighbours
+enum Decompressed {
+    Lapped(LappedSamples),
+    Samples(Vec<f64>),
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::optimizer::classifier::ClassifierMode;
+    use crate::utils::error::calculate_error;
 
     #[test]
     fn test_compress_chunk() {
@@ -141,6 +345,154 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_add_compressed_chunk() {
+        let vector1 = vec![1.0; 1024];
+        let compressed = Compressor::Constant.compress(&vector1);
+        let mut cs = CompressedStream::new();
+        cs.add_compressed_chunk(vector1.len(), Compressor::Constant, compressed);
+        let b = cs.to_bytes();
+        let out = CompressedStream::from_bytes(&b).decompress();
+        assert_eq!(vector1, out);
+    }
+
+    #[test]
+    fn test_compress_chunk_bounded_with_options() {
+        let vector1: Vec<f64> = (0..1024).map(|x| (x / 256) as f64 + 1.0).collect();
+        for classifier in [
+            None,
+            Some(ClassifierMode::Trust),
+            Some(ClassifierMode::VerifyTop2),
+        ] {
+            let options = CompressionOptions {
+                candidates: vec![Compressor::Idw, Compressor::RLE],
+                classifier,
+                ..Default::default()
+            };
+            let mut cs = CompressedStream::new();
+            cs.compress_chunk_bounded_with_options(&vector1, Compressor::Auto, 0.01, &options);
+            let b = cs.to_bytes();
+            let out = CompressedStream::from_bytes(&b).decompress();
+            assert_eq!(vector1, out);
+        }
+    }
+
+    #[test]
+    fn test_compress_chunks_lapped() {
+        let mut vector1: Vec<f64> = (0..2048)
+            .map(|x| (x as f64 / 40.0).sin() * 10.0 + 20.0 + x as f64 / 100.0)
+            .collect();
+        // Not finite values don't spread to the frames around them
+        vector1[1600] = f64::NAN;
+        let chunks: Vec<(Compressor, &[f64])> =
+            vector1.chunks(512).map(|c| (Compressor::Mdct, c)).collect();
+        let mut cs = CompressedStream::new();
+        cs.compress_chunks_bounded_cached(&chunks, 0.01, &CompressionOptions::default(), &[]);
+        let b = cs.to_bytes();
+        let out = CompressedStream::from_bytes(&b).decompress();
+        assert_eq!(out.len(), vector1.len());
+        // Each frame is within the error once joined with the overlap of the next one
+        for (chunk, out) in vector1.chunks(512).zip(out.chunks(512)).take(3) {
+            assert!(calculate_error(chunk, out) <= 0.01);
+        }
+        // No jumps where the frames join
+        for boundary in [512, 1024] {
+            let step = (out[boundary] - out[boundary - 1]).abs();
+            assert!(step < 0.5, "{}", step);
+        }
+    }
+
+    #[test]
+    fn test_compress_chunk_bounded_cached() {
+        let vector1: Vec<f64> = (0..2048)
+            .map(|x| (x as f64 * std::f64::consts::PI / 128.0).sin() * 10.0 + 20.0)
+            .collect();
+        let options = CompressionOptions::default();
+        let mut cs = CompressedStream::new();
+        let selection = cs
+            .compress_chunk_bounded_cached(&vector1, Compressor::Auto, 0.01, &options, None)
+            .unwrap();
+        assert!(selection.parameter.is_some());
+        // The cached selection compresses to the same frame
+        let mut cached = CompressedStream::new();
+        cached.compress_chunk_bounded_cached(
+            &vector1,
+            Compressor::Auto,
+            0.01,
+            &options,
+            Some(selection),
+        );
+        assert_eq!(cached.to_bytes(), cs.to_bytes());
+        // A cached selection that misses the error is replaced
+        let too_small = CachedSelection {
+            compressor: Compressor::Polynomial,
+            parameter: Some(1),
+            quantization: None,
+            samples: vector1.len(),
+        };
+        let mut fallback = CompressedStream::new();
+        let selection = fallback
+            .compress_chunk_bounded_cached(
+                &vector1,
+                Compressor::Auto,
+                0.01,
+                &options,
+                Some(too_small),
+            )
+            .unwrap();
+        assert_ne!(selection, too_small);
+        // Forced compressors are not cached
+        assert!(fallback
+            .compress_chunk_bounded_cached(&vector1, Compressor::RLE, 0.01, &options, None)
+            .is_none());
+    }
+
+    #[test]
+    fn test_compress_chunks_bounded_cached() {
+        let data: Vec<f64> = (0..4096)
+            .map(|x| match x {
+                _ if x < 1024 => 5.0,
+                _ if x < 2048 => (x / 100) as f64,
+                _ => (x as f64 / 50.0).sin() * 10.0 + 20.0,
+            })
+            .collect();
+        let chunks: Vec<(Compressor, &[f64])> = data
+            .chunks(1024)
+            .map(|chunk| (Compressor::Auto, chunk))
+            .collect();
+        let options = CompressionOptions::default();
+        let mut sequential = CompressedStream::new();
+        for (compressor, chunk) in chunks.iter() {
+            sequential.compress_chunk_bounded_with_options(chunk, *compressor, 0.01, &options);
+        }
+        let expected = sequential.to_bytes();
+        for threads in [1, 4] {
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap();
+            let mut cs = CompressedStream::new();
+            let selections =
+                pool.install(|| cs.compress_chunks_bounded_cached(&chunks, 0.01, &options, &[]));
+            assert_eq!(selections.len(), chunks.len());
+            assert!(selections.iter().all(|s| s.is_some()));
+            let b = cs.to_bytes();
+            assert_eq!(b, expected);
+            let out = pool.install(|| CompressedStream::from_bytes(&b).decompress());
+            assert_eq!(out.len(), data.len());
+        }
+    }
+
+    #[test]
+    fn test_overhead() {
+        let vector1 = vec![1.0; 1024];
+        let mut cs = CompressedStream::new();
+        cs.compress_chunk_with(&vector1, Compressor::Constant);
+        let b = cs.to_bytes();
+        // Constant data takes 3 bytes
+        assert!(CompressedStream::overhead(&[1024]) >= b.len() - 3);
+    }
+
     #[test]
     fn test_to_bytes() {
         let vector1 = vec![1.0; 1024];
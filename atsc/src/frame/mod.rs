@@ -14,13 +14,44 @@ See the License for the specific language governing permissions and
 limitations under the License.
 */
 
-use crate::{compressor::Compressor, optimizer::utils::DataStats};
+use crate::compressor::mdct::{
+    mdct_compressor, mdct_lapped, mdct_to_lapped, LappedSamples, Neighbours,
+};
+use crate::compressor::Compressor;
+use crate::optimizer::budget::measure;
+use crate::optimizer::cache::CachedSelection;
+use crate::optimizer::classifier::{rank, ClassifierMode, Features};
+use crate::optimizer::options::CompressionOptions;
+use crate::optimizer::report::{CompressorRun, FrameReport};
+use crate::optimizer::utils::DataStats;
 use bincode::{Decode, Encode};
 use log::debug;
+use serde::Serialize;
 use std::mem::size_of_val;
+use std::time::Instant;
 
 const COMPRESSION_SPEED: [i32; 7] = [i32::MAX, 4096, 2048, 1024, 512, 256, 128];
 
+/// How the compressor of a frame was selected
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Selection {
+    /// Assigned by the optimizer plan, or forced
+    Planned,
+    /// A single value or a line meets the error
+    Constant,
+    /// Selected for the same series on a previous run, and still meets the error
+    Cached,
+    /// Predicted by the classifier, and meets the error
+    Classifier,
+    /// Smallest on the sampled windows, and meets the error on the whole data
+    Sampled,
+    /// Smallest of the candidates that meet the error
+    Smallest,
+    /// No candidate meets the error, smallest of all
+    NoneMeetsError,
+}
+
 /// This is the structure of a compressor frame
 #[derive(Encode, Decode, Debug, Clone)]
 pub struct CompressorFrame {
@@ -45,6 +76,16 @@ impl CompressorFrame {
         }
     }
 
+    /// Creates a compressor frame from data that was already compressed with `compressor`
+    pub fn from_compressed(compressor: Compressor, sample_count: usize, data: Vec<u8>) -> Self {
+        CompressorFrame {
+            frame_size: 0,
+            sample_count,
+            compressor,
+            data,
+        }
+    }
+
     /// Calculates the size of the Frame and "closes it"
     // TODO this is probably wrong, so we have to use the write stream to dump the bytes written
     pub fn close(&mut self) {
@@ -67,85 +108,294 @@ impl CompressorFrame {
         self.data = self.compressor.compress_bounded(data, max_error as f64);
     }
 
+    /// Compresses with MDCT, with blocks that overlap the `neighbours` frames. Bounded if `max_error` is provided.
+    pub fn compress_lapped(
+        &mut self,
+        data: &[f64],
+        neighbours: Neighbours,
+        max_error: Option<f32>,
+    ) {
+        self.sample_count = data.len();
+        self.compressor = Compressor::Mdct;
+        self.data = match max_error {
+            Some(max_error) => {
+                mdct_compressor(data, neighbours, max_error as f64, DataStats::new(data))
+                    .compressed_data
+            }
+            None => mdct_lapped(data, neighbours),
+        };
+    }
+
     /// This function tries to detect the best compressor for use and apply it to the data size
     pub fn compress_best(&mut self, data: &[f64], max_error: f32, compression_speed: usize) {
+        let options = CompressionOptions {
+            sample_level: compression_speed,
+            ..Default::default()
+        };
+        self.compress_best_with(data, max_error, &options);
+    }
+
+    /// Same as `compress_best`, with the candidates, the sampling and the classifier of `options`
+    /// Returns how the compressor was selected.
+    pub fn compress_best_with(
+        &mut self,
+        data: &[f64],
+        max_error: f32,
+        options: &CompressionOptions,
+    ) -> Selection {
+        self.compress_best_cached(data, max_error, options, None)
+    }
+
+    /// Same as `compress_best_with`, trying first the `cached` selection of a previous run. If it is still a
+    /// candidate and meets the error, the search is skipped.
+    pub fn compress_best_cached(
+        &mut self,
+        data: &[f64],
+        max_error: f32,
+        options: &CompressionOptions,
+        cached: Option<CachedSelection>,
+    ) -> Selection {
         self.sample_count = data.len();
         // Speed factor limits the amount of data that is sampled to calculate the best compressor.
         // We need enough samples to do decent compression, minimum is 128 (2^7)
-        let data_sample = COMPRESSION_SPEED[compression_speed] as usize;
-        // Eligible compressors for use
-        let compressor_list = [Compressor::FFT, Compressor::Polynomial, Compressor::RLE];
-        // Do a statistical analysis of the data, let's see if we can pick a compressor out of this.
+        let data_sample = COMPRESSION_SPEED[options.sample_level] as usize;
+        // Eligible compressors for use, Constant and Trend are tried first
+        let (flat_list, compressor_list): (Vec<Compressor>, Vec<Compressor>) = options
+            .candidates
+            .iter()
+            .partition(|compressor| matches!(compressor, Compressor::Constant | Compressor::Trend));
+        // If the data is constant, or a single value or a line meets the error, well, constant frame, nothing is
+        // smaller
         let stats = DataStats::new(data);
-        // Checking the statistical analysis and chose, if possible, a compressor
-        // If the data is constant, well, constant frame
-        if stats.min == stats.max {
-            self.compressor = Compressor::Constant;
-            // Now do the full data compression
-            self.data = self
-                .compressor
-                .get_compress_bounded_results(data, max_error as f64)
-                .compressed_data;
-        } else if self.sample_count >= data_sample {
+        let flat = [Compressor::Constant, Compressor::Trend]
+            .into_iter()
+            .filter(|compressor| {
+                flat_list.contains(compressor)
+                    || (*compressor == Compressor::Constant && stats.min == stats.max)
+            })
+            .find_map(|compressor| {
+                let result = compressor.get_compress_bounded_results(data, max_error as f64);
+                (result.error <= max_error as f64).then_some((compressor, result))
+            });
+        if let Some((compressor, result)) = flat {
+            self.compressor = compressor;
+            self.data = result.compressed_data;
+            debug!("Auto Compressor Selection: {:?}", self.compressor);
+            return Selection::Constant;
+        }
+        // Unless they are the only candidates, they are not worth trying again
+        let compressor_list = if compressor_list.is_empty() {
+            flat_list
+        } else {
+            compressor_list
+        };
+        if let Some(cached) = cached.filter(|c| options.is_candidate(c.compressor)) {
+            let point = measure(data, cached.compressor, cached.compress(data));
+            if point.error <= max_error as f64 {
+                self.compressor = cached.compressor;
+                self.data = point.compressed_data;
+                debug!("Cached Compressor Selection: {:?}", cached);
+                return Selection::Cached;
+            }
+            debug!(
+                "Cached selection {:?} misses the error with {}, searching",
+                cached, point.error
+            );
+        }
+        if let Some(mode) = options.classifier {
+            if self.compress_classified(data, max_error, mode, options) {
+                debug!("Classifier Compressor Selection: {:?}", self.compressor);
+                return Selection::Classifier;
+            }
+            debug!("Classifier prediction failed the error bound, trying all compressors");
+        }
+        if self.sample_count > data_sample {
             // Any technique determine the best compressor seems to be slower than this one
             // Sample the dataset for a fast compressor run
             // Pick the best compression
             // Compress the full dataset that way
-            let (_smallest_result, chosen_compressor) = compressor_list
+            let windows = options.sampling.windows(self.sample_count, data_sample);
+            debug!("Sampling windows: {:?}", windows);
+            // A compressor has to meet the error on every window, the total size over the windows decides
+            let sampled_results: Vec<_> = compressor_list
                 .iter()
                 .map(|compressor| {
-                    (
-                        compressor
-                            .get_compress_bounded_results(&data[0..data_sample], max_error as f64),
-                        compressor,
-                    )
+                    windows
+                        .iter()
+                        .map(|window| {
+                            compressor.get_compress_bounded_results(
+                                &data[window.clone()],
+                                max_error as f64,
+                            )
+                        })
+                        .fold(
+                            (0, true, *compressor),
+                            |(size, valid, compressor), result| {
+                                (
+                                    size + result.compressed_data.len(),
+                                    valid && result.error <= max_error as f64,
+                                    compressor,
+                                )
+                            },
+                        )
                 })
-                .filter(|(result, _)| result.error <= max_error as f64)
-                .min_by_key(|x| x.0.compressed_data.len())
+                .collect();
+            // If no compressor meets the error, just pick the smallest
+            let any_valid = sampled_results.iter().any(|(_, valid, _)| *valid);
+            let (_smallest_size, _, chosen_compressor) = sampled_results
+                .into_iter()
+                .filter(|(_, valid, _)| *valid || !any_valid)
+                .min_by_key(|(size, _, _)| *size)
                 .unwrap();
-            self.compressor = *chosen_compressor;
             // Now do the full data compression
-            self.data = self
-                .compressor
-                .get_compress_bounded_results(data, max_error as f64)
-                .compressed_data;
+            let result = chosen_compressor.get_compress_bounded_results(data, max_error as f64);
+            // The samples can miss parts of the data the compressor can't handle, those go through the full selection
+            if result.error <= max_error as f64 {
+                self.compressor = chosen_compressor;
+                self.data = result.compressed_data;
+                debug!("Auto Compressor Selection: {:?}", self.compressor);
+                return Selection::Sampled;
+            }
+            debug!(
+                "Sampled selection {:?} misses the error on the full data, trying all compressors",
+                chosen_compressor
+            );
+        }
+        // Run all the eligible compressors and choose smallest
+        let compressor_results: Vec<_> = compressor_list
+            .iter()
+            .map(|compressor| {
+                (
+                    compressor.get_compress_bounded_results(data, max_error as f64),
+                    *compressor,
+                )
+            })
+            .collect();
+
+        #[allow(
+            clippy::neg_cmp_op_on_partial_ord,
+            reason = "we need to exactly negate `result.error < max_error`, we can't apply de morgans to the expression due to NaN values"
+        )]
+        let none_meets_error = compressor_results
+            .iter()
+            .all(|(result, _)| !(result.error <= max_error as f64));
+        let best_compressor = if none_meets_error {
+            // To ensure we always have at least one result,
+            // if all results are above the max error just pick the smallest.
+            compressor_results
+                .into_iter()
+                .min_by_key(|x| x.0.compressed_data.len())
         } else {
-            // Run all the eligible compressors and choose smallest
-            let compressor_results: Vec<_> = compressor_list
-                .iter()
-                .map(|compressor| {
-                    (
-                        compressor.get_compress_bounded_results(data, max_error as f64),
-                        *compressor,
-                    )
-                })
-                .collect();
+            compressor_results
+                .into_iter()
+                .filter(|(result, _)| result.error <= max_error as f64)
+                .min_by_key(|x| x.0.compressed_data.len())
+        };
 
-            #[allow(
-                clippy::neg_cmp_op_on_partial_ord,
-                reason = "we need to exactly negate `result.error < max_error`, we can't apply de morgans to the expression due to NaN values"
-            )]
-            let best_compressor = if compressor_results
-                .iter()
-                .all(|(result, _)| !(result.error <= max_error as f64))
-            {
-                // To ensure we always have at least one result,
-                // if all results are above the max error just pick the smallest.
-                compressor_results
-                    .into_iter()
-                    .min_by_key(|x| x.0.compressed_data.len())
-            } else {
-                compressor_results
-                    .into_iter()
-                    .filter(|(result, _)| result.error <= max_error as f64)
-                    .min_by_key(|x| x.0.compressed_data.len())
-            };
-
-            let (result, compressor) = best_compressor.unwrap();
-            self.data = result.compressed_data;
-            self.compressor = compressor;
-        }
+        let (result, compressor) = best_compressor.unwrap();
+        self.data = result.compressed_data;
+        self.compressor = compressor;
         debug!("Auto Compressor Selection: {:?}", self.compressor);
+        if none_meets_error {
+            Selection::NoneMeetsError
+        } else {
+            Selection::Smallest
+        }
+    }
+
+    /// Picks the compressor from the features of the data instead of trial compressions.
+    /// With `ClassifierMode::Trust` only the predicted compressor runs, with `ClassifierMode::VerifyTop2` the 2 best
+    /// ranked run and the smallest is kept. Only the candidates of `options` are considered.
+    /// Returns false, leaving the frame as is, if no prediction meets the error.
+    fn compress_classified(
+        &mut self,
+        data: &[f64],
+        max_error: f32,
+        mode: ClassifierMode,
+        options: &CompressionOptions,
+    ) -> bool {
+        let tries = match mode {
+            ClassifierMode::Trust => 1,
+            ClassifierMode::VerifyTop2 => 2,
+        };
+        let best = rank(&Features::new(data))
+            .into_iter()
+            .filter(|compressor| options.is_candidate(*compressor))
+            .take(tries)
+            .map(|compressor| {
+                (
+                    compressor.get_compress_bounded_results(data, max_error as f64),
+                    compressor,
+                )
+            })
+            .filter(|(result, _)| result.error <= max_error as f64)
+            .min_by_key(|(result, _)| result.compressed_data.len());
+        match best {
+            Some((result, compressor)) => {
+                self.compressor = compressor;
+                self.data = result.compressed_data;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Compresses the data with every candidate of `options` (and `planned`, if it is not Auto), measuring size,
+    /// error and time, then compresses it as it would be for real: with `planned`, or with the Auto selection.
+    /// Nothing is stored in the frame.
+    pub fn dry_run(
+        data: &[f64],
+        planned: Compressor,
+        max_error: f32,
+        options: &CompressionOptions,
+    ) -> FrameReport {
+        let mut compressors = options.candidates.clone();
+        if planned != Compressor::Auto && !compressors.contains(&planned) {
+            compressors.push(planned);
+        }
+        let runs = compressors
+            .into_iter()
+            .map(|compressor| {
+                let start = Instant::now();
+                let result = compressor.get_compress_bounded_results(data, max_error as f64);
+                let elapsed = start.elapsed();
+                CompressorRun::new(
+                    measure(data, compressor, result.compressed_data),
+                    max_error,
+                    elapsed,
+                )
+            })
+            .collect();
+        let mut frame = CompressorFrame::new(Some(planned));
+        let start = Instant::now();
+        let reason = match planned {
+            Compressor::Auto => frame.compress_best_with(data, max_error, options),
+            _ => {
+                frame.compress_bounded(data, max_error);
+                Selection::Planned
+            }
+        };
+        let elapsed = start.elapsed();
+        let selected = CompressorRun::new(
+            measure(data, frame.compressor, frame.data),
+            max_error,
+            elapsed,
+        );
+        FrameReport {
+            runs,
+            selected,
+            reason,
+        }
+    }
+
+    /// Gets the compressor of the frame and its parameter, to be cached for the next run
+    pub fn selection(&self) -> CachedSelection {
+        CachedSelection::new(self.compressor, self.sample_count, &self.data)
+    }
+
+    /// Decompresses a MDCT frame, to be joined with its neighbours. None for other compressors.
+    pub fn decompress_lapped(&self) -> Option<LappedSamples> {
+        (self.compressor == Compressor::Mdct).then(|| mdct_to_lapped(self.sample_count, &self.data))
     }
 
     /// Decompresses a frame and returns the resulting data array
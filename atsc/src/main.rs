@@ -17,57 +17,99 @@ limitations under the License.
 use atsc::compressor::Compressor;
 use atsc::csv::{read_samples, read_samples_with_headers};
 use atsc::data::CompressedStream;
+use atsc::optimizer::budget::stream_error;
+use atsc::optimizer::cache::SelectionCache;
+use atsc::optimizer::classifier::ClassifierMode;
+use atsc::optimizer::options::{CompressionOptions, Preset};
+use atsc::optimizer::report::Report;
+use atsc::optimizer::sampling::{SamplingStrategy, DEFAULT_SEED};
 use atsc::optimizer::OptimizerPlan;
 use atsc::utils::readers::bro_reader;
 use clap::{arg, command, Parser};
 use log::{debug, error};
+use rayon::prelude::*;
+use serde::Serialize;
 use std::error::Error;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
 use wavbrro::wavbrro::WavBrro;
 
 /// Processes the given input based on the provided arguments.
 fn process_args(arguments: &Args) -> Result<(), Box<dyn Error>> {
     let metadata = std::fs::metadata(&arguments.input)?;
+    if metadata.is_dir() && arguments.series_name.is_some() {
+        return Err(
+            "--series-name can't be used with a directory, each file is its own series".into(),
+        );
+    }
+    // The selection cache is loaded once and shared by all the files, then saved once
+    let cache = match &arguments.selection_cache {
+        Some(cache_path) => Some(Mutex::new(SelectionCache::load(cache_path)?)),
+        None => None,
+    };
 
     // If the input path points to a single file
-    if metadata.is_file() {
+    let result = if metadata.is_file() {
         debug!("Target is a file");
-        process_single_file(arguments.input.clone(), arguments)?;
+        process_single_file(arguments.input.clone(), arguments, cache.as_ref())
     }
     // If the input path points to a directory
     else if metadata.is_dir() {
         debug!("Target is a directory");
-        process_directory(arguments)?;
+        process_directory(arguments, cache.as_ref())
     }
     // If the input path is neither a file nor a directory
     else {
         return Err("The provided path is neither a file nor a directory.".into());
-    }
+    };
 
-    Ok(())
+    // The selections of the files that were compressed are kept, even if others failed
+    if let (Some(cache_path), Some(cache)) = (&arguments.selection_cache, cache) {
+        cache
+            .into_inner()
+            .unwrap_or_else(PoisonError::into_inner)
+            .save(cache_path)?;
+    }
+    result
 }
 
-fn process_directory(arguments: &Args) -> Result<(), Box<dyn Error>> {
-    // Assuming you want to process each file inside this directory
-    for entry in std::fs::read_dir(arguments.input.clone())? {
-        let path = entry?.path();
-        if path.is_file() {
-            match process_single_file(path.clone(), arguments) {
-                Ok(_) => continue,
-                //TODO: Files are created while this walks the dir, gives a funny output
-                //NOTE: Due to the way read_dir works, it seems we can't do much about this except collecting
-                //      before and then iterating. But that might lead to a MASSIVE array. So it keeps a `funny` output
-                //      output for the time beeing.
-                Err(err) => error!("{} File: {}", err, path.display()),
+fn process_directory(
+    arguments: &Args,
+    cache: Option<&Mutex<SelectionCache>>,
+) -> Result<(), Box<dyn Error>> {
+    // Files are processed in parallel, each one is independent
+    //TODO: Files are created while this walks the dir, gives a funny output
+    //NOTE: Due to the way read_dir works, it seems we can't do much about this except collecting
+    //      before and then iterating. But that might lead to a MASSIVE array. So it keeps a `funny` output
+    //      output for the time beeing.
+    let failed = std::fs::read_dir(arguments.input.clone())?
+        .par_bridge()
+        .filter_map(|entry| {
+            let path = match entry {
+                Ok(entry) => entry.path(),
+                Err(err) => return Some(err.into()),
+            };
+            if !path.is_file() {
+                return None;
             }
             // We need to make sure we skip anything but BRO and WBRO, this can be done on single file processors
-            process_single_file(path, arguments)?;
-        }
+            process_single_file(path.clone(), arguments, cache)
+                .map_err(|err| format!("{} File: {}", err, path.display()).into())
+                .err()
+        })
+        .map(|err: Box<dyn Error + Send + Sync>| error!("{}", err))
+        .count();
+    if failed > 0 {
+        return Err(format!("Failed to process {} files", failed).into());
     }
     Ok(())
 }
 
-fn process_single_file(mut file_path: PathBuf, arguments: &Args) -> Result<(), Box<dyn Error>> {
+fn process_single_file(
+    mut file_path: PathBuf,
+    arguments: &Args,
+    cache: Option<&Mutex<SelectionCache>>,
+) -> Result<(), Box<dyn Error>> {
     debug!("Processing single file...");
     if arguments.uncompress {
         //read
@@ -102,9 +144,13 @@ fn process_single_file(mut file_path: PathBuf, arguments: &Args) -> Result<(), B
         if arguments.verbose {
             println!("Input={:?}", data);
         }
+        if arguments.dry_run {
+            print_report(&data, arguments, &file_path)?;
+            return Ok(());
+        }
 
         // Compress
-        let compressed_data = compress_data(&data, arguments);
+        let compressed_data = compress_with_cache(&data, arguments, &file_path, cache);
 
         // Write
         file_path.set_extension("bro");
@@ -115,9 +161,13 @@ fn process_single_file(mut file_path: PathBuf, arguments: &Args) -> Result<(), B
         if arguments.verbose {
             println!("Input={:?}", data);
         }
+        if arguments.dry_run {
+            print_report(&data, arguments, &file_path)?;
+            return Ok(());
+        }
 
         //compress
-        let compressed_data = compress_data(&data, arguments);
+        let compressed_data = compress_with_cache(&data, arguments, &file_path, cache);
 
         //write
         file_path.set_extension("bro");
@@ -126,42 +176,235 @@ fn process_single_file(mut file_path: PathBuf, arguments: &Args) -> Result<(), B
     Ok(())
 }
 
+/// Compresses the data, with the selection cache if one is provided. The series is the file name, unless set.
+/// The selections of the series are copied out of the shared cache, so files compress in parallel without holding it.
+fn compress_with_cache(
+    vec: &[f64],
+    arguments: &Args,
+    file_path: &Path,
+    cache: Option<&Mutex<SelectionCache>>,
+) -> Vec<u8> {
+    let Some(cache) = cache else {
+        return compress_data(vec, arguments, None);
+    };
+    let series = match &arguments.series_name {
+        Some(name) => name.clone(),
+        None => file_path
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned(),
+    };
+    let lock = || cache.lock().unwrap_or_else(PoisonError::into_inner);
+    let mut selections = lock().series(&series);
+    let compressed_data = compress_data(vec, arguments, Some((&mut selections, &series)));
+    lock().merge(selections);
+    compressed_data
+}
+
 /// Compresses the data based on the provided tag and arguments.
-fn compress_data(vec: &[f64], arguments: &Args) -> Vec<u8> {
+/// `cache` has the selections of previous runs for the series, Auto frames try them first and record theirs.
+fn compress_data(
+    vec: &[f64],
+    arguments: &Args,
+    mut cache: Option<(&mut SelectionCache, &str)>,
+) -> Vec<u8> {
     debug!("Compressing data!");
     // Create Optimization Plan and Stream for the data.
-    let mut op = OptimizerPlan::plan(vec);
+    let max_error = arguments.error as f32 / 100.0;
+    let target_bytes = target_size(arguments, vec.len());
+    let per_frame_error = !arguments.global_error && target_bytes.is_none();
+    let options = compression_options(arguments);
+    debug!("Compression options: {:?}", options);
+    let op = optimizer_plan(vec, arguments, &options, per_frame_error);
     let mut cs = CompressedStream::new();
     let mut frame_number = 1;
+    // Compress to a size, the error is whatever is the lowest that fits
+    if let Some(max_bytes) = target_bytes {
+        let payload_bytes = max_bytes.saturating_sub(CompressedStream::overhead(&op.chunk_sizes));
+        let selection = op.fit_size(payload_bytes);
+        let error = stream_error(&selection, &op.chunk_sizes);
+        for (point, size) in selection.into_iter().zip(op.chunk_sizes.iter()) {
+            debug!(
+                "--- Frame {}. Chunk size: {}, Compressor: {:?}, Error: {}",
+                frame_number, size, point.compressor, point.error
+            );
+            frame_number += 1;
+            cs.add_compressed_chunk(*size, point.compressor, point.compressed_data);
+        }
+        let compressed_data = cs.to_bytes();
+        println!(
+            "Target size: {} bytes, Compressed size: {} bytes, Error: {:.4}%",
+            max_bytes,
+            compressed_data.len(),
+            error * 100.0
+        );
+        return compressed_data;
+    }
+    // The error is a target for the whole series, let the optimizer spread it over the frames
+    if arguments.global_error && is_lossy(&arguments.compressor) {
+        let selection = op.distribute_error(max_error);
+        for (point, size) in selection.into_iter().zip(op.chunk_sizes.iter()) {
+            debug!(
+                "--- Frame {}. Chunk size: {}, Compressor: {:?}, Error: {}",
+                frame_number, size, point.compressor, point.error
+            );
+            frame_number += 1;
+            cs.add_compressed_chunk(*size, point.compressor, point.compressed_data);
+        }
+        return cs.to_bytes();
+    }
+    let execution = op.get_execution();
+    for (cpr, data) in execution.iter() {
+        debug!(
+            "--- Frame {}. Chunk size: {}, Compressor: {:?}",
+            frame_number,
+            data.len(),
+            cpr
+        );
+        frame_number += 1;
+    }
+    let chunks: Vec<(Compressor, &[f64])> = execution
+        .into_iter()
+        .map(|(cpr, data)| (*cpr, data))
+        .collect();
+    // If compressor is a lossy one, compress with the error defined, or default
+    if is_lossy(&arguments.compressor) {
+        let cached: Vec<_> = match &cache {
+            Some((cache, series)) => (0..chunks.len())
+                .map(|frame| cache.get(series, frame))
+                .collect(),
+            None => Vec::new(),
+        };
+        let selections = cs.compress_chunks_bounded_cached(&chunks, max_error, &options, &cached);
+        if let Some((cache, series)) = cache.as_mut() {
+            for (frame, selection) in selections.into_iter().enumerate() {
+                if let Some(selection) = selection {
+                    cache.record(series, frame, selection);
+                }
+            }
+        }
+    } else {
+        // If compressor is a lossless one, just compress
+        cs.compress_chunks_with(&chunks)
+    }
+    cs.to_bytes()
+}
+
+/// Creates the optimization plan for the data, with the compressor that was selected
+fn optimizer_plan(
+    vec: &[f64],
+    arguments: &Args,
+    options: &CompressionOptions,
+    per_frame_error: bool,
+) -> OptimizerPlan {
+    let max_error = arguments.error as f32 / 100.0;
+    // With an error per frame, the optimizer can assign the compressors itself
+    let plan_error = match arguments.compressor {
+        CompressorType::Auto if per_frame_error => Some(max_error),
+        _ => None,
+    };
+    let mut op = OptimizerPlan::plan_with_options(vec, plan_error, options);
     // Assign the compressor if it was selected
     match arguments.compressor {
         CompressorType::Noop => op.set_compressor(Compressor::Noop),
         CompressorType::Constant => op.set_compressor(Compressor::Constant),
         CompressorType::Rle => op.set_compressor(Compressor::RLE),
+        CompressorType::Dictionary => op.set_compressor(Compressor::Dictionary),
         CompressorType::Fft => op.set_compressor(Compressor::FFT),
         CompressorType::Polynomial => op.set_compressor(Compressor::Polynomial),
         CompressorType::Idw => op.set_compressor(Compressor::Idw),
+        CompressorType::Mdct => op.set_compressor(Compressor::Mdct),
+        CompressorType::Wavelet => op.set_compressor(Compressor::Wavelet),
+        CompressorType::Dct => op.set_compressor(Compressor::Dct),
+        CompressorType::Pla => op.set_compressor(Compressor::Pla),
+        CompressorType::Linear => op.set_compressor(Compressor::Linear),
+        CompressorType::Spline => op.set_compressor(Compressor::Spline),
+        CompressorType::Pchip => op.set_compressor(Compressor::Pchip),
+        CompressorType::Akima => op.set_compressor(Compressor::Akima),
+        CompressorType::Residual => op.set_compressor(Compressor::Residual),
+        CompressorType::Spike => op.set_compressor(Compressor::Spike),
+        CompressorType::Trend => op.set_compressor(Compressor::Trend),
+        CompressorType::Auto if per_frame_error => {}
         CompressorType::Auto => op.set_compressor(Compressor::Auto),
     }
-    for (cpr, data) in op.get_execution().into_iter() {
-        debug!("--- Frame {}. Chunk size: {}", frame_number, data.len());
-        frame_number += 1;
-        // If compressor is a lossy one, compress with the error defined, or default
-        match arguments.compressor {
-            CompressorType::Fft
-            | CompressorType::Polynomial
-            | CompressorType::Idw
-            | CompressorType::Auto => cs.compress_chunk_bounded_with(
-                data,
-                cpr.to_owned(),
-                arguments.error as f32 / 100.0,
-                arguments.compression_selection_sample_level as usize,
-            ),
-            // If compressor is a lossless one, just compress
-            _ => cs.compress_chunk_with(data, cpr.to_owned()),
+    op
+}
+
+/// Compresses the data with every candidate without writing the output, and prints the sizes, errors and times
+fn print_report(vec: &[f64], arguments: &Args, file_path: &Path) -> Result<(), Box<dyn Error>> {
+    let max_error = arguments.error as f32 / 100.0;
+    let options = compression_options(arguments);
+    let report = optimizer_plan(vec, arguments, &options, true).dry_run(max_error, &options);
+    match arguments.report {
+        ReportFormat::Text => println!("{}\n{}", file_path.display(), report),
+        ReportFormat::Json => {
+            let file_report = FileReport {
+                input: file_path.display().to_string(),
+                report: &report,
+            };
+            println!("{}", serde_json::to_string_pretty(&file_report)?)
         }
     }
-    cs.to_bytes()
+    Ok(())
+}
+
+/// Gets the compression options of the preset, with the options that were set explicitly on top
+fn compression_options(arguments: &Args) -> CompressionOptions {
+    let mut options = arguments.preset.preset().options();
+    if let Some(candidates) = &arguments.candidates {
+        options.candidates = candidates.clone();
+    }
+    if let Some(level) = arguments.compression_selection_sample_level {
+        options.sample_level = level as usize;
+    }
+    if let Some(sampling) = &arguments.sampling {
+        options.sampling = sampling.strategy();
+    }
+    if let Some(classifier) = &arguments.classifier {
+        options.classifier = Some(classifier.mode());
+    }
+    if let Some(size) = arguments.max_frame_size {
+        options.max_frame_size = size as usize;
+    }
+    if let Some(size) = arguments.min_frame_size {
+        options.min_frame_size = size as usize;
+    }
+    options.adaptive_frames |= arguments.adaptive_frames;
+    options
+}
+
+/// Gets the target compressed size in bytes, if one was requested either as a size or as a ratio
+fn target_size(arguments: &Args, sample_count: usize) -> Option<usize> {
+    match (arguments.target_size, arguments.target_ratio) {
+        (Some(size), _) => Some(size),
+        // Ratio is against the raw data, 64bits per sample
+        (None, Some(ratio)) => Some((sample_count as f64 * 8.0 / ratio) as usize),
+        (None, None) => None,
+    }
+}
+
+/// Checks if the compressor selected works with an error bound
+fn is_lossy(compressor: &CompressorType) -> bool {
+    matches!(
+        compressor,
+        CompressorType::Constant
+            | CompressorType::Trend
+            | CompressorType::Fft
+            | CompressorType::Polynomial
+            | CompressorType::Idw
+            | CompressorType::Mdct
+            | CompressorType::Wavelet
+            | CompressorType::Dct
+            | CompressorType::Pla
+            | CompressorType::Linear
+            | CompressorType::Spline
+            | CompressorType::Pchip
+            | CompressorType::Akima
+            | CompressorType::Residual
+            | CompressorType::Spike
+            | CompressorType::Auto
+    )
 }
 
 /// Compresses the data based on the provided tag and arguments.
@@ -180,6 +423,22 @@ struct Args {
     #[arg(long, value_enum, default_value = "auto")]
     compressor: CompressorType,
 
+    /// Set of options for the Auto selection and the frame sizes, trading speed for compression ratio.
+    /// fast: predicts the compressor of each frame from features of the data
+    /// balanced: compresses each frame with constant, trend, fft, polynomial, rle and dictionary, keeps the smallest
+    /// max: compresses each frame with every compressor, frames follow the change points of the data
+    /// Options set explicitly take precedence over the preset.
+    #[arg(long, value_enum, default_value = "balanced", verbatim_doc_comment)]
+    preset: PresetType,
+
+    /// Comma separated compressors the Auto selection picks from, e.g. fft,idw,rle.
+    /// Possible values: constant, trend, fft, polynomial, idw, rle, noop, mdct, wavelet, dct, pla, linear, spline,
+    /// pchip, akima, residual, spike, dictionary.
+    /// Constant and trend are picked first when they meet the error, constant data is always stored as constant.
+    /// Default comes from the preset.
+    #[arg(long, value_delimiter = ',', value_parser = parse_candidate, verbatim_doc_comment)]
+    candidates: Option<Vec<Compressor>>,
+
     /// Sets the maximum allowed error for the compressed data, must be between 0 and 50. Default is 3 (3%).
     /// 0 is lossless compression
     /// 50 will do a median filter on the data.
@@ -187,17 +446,95 @@ struct Args {
     #[arg(short, long, default_value_t = 3, value_parser = clap::value_parser!(u8).range(0..51), verbatim_doc_comment )]
     error: u8,
 
+    /// Applies the error to the whole series instead of each frame.
+    /// The error budget is distributed across frames to minimize the total size,
+    /// easy frames take more error and hard frames less.
+    #[arg(long, action, verbatim_doc_comment)]
+    global_error: bool,
+
+    /// Compresses to fit a target size in bytes, ignoring the error.
+    /// Picks the configuration with the lowest error that fits, and reports the achieved error.
+    #[arg(long, conflicts_with_all = ["target_ratio", "global_error"], verbatim_doc_comment)]
+    target_size: Option<usize>,
+
+    /// Compresses to a target compression ratio (e.g. 100 for 100x smaller than the raw data), ignoring the error.
+    /// Picks the configuration with the lowest error that fits, and reports the achieved error.
+    #[arg(long, conflicts_with = "global_error", value_parser = parse_ratio, verbatim_doc_comment)]
+    target_ratio: Option<f64>,
+
+    /// Places the frame boundaries at the change points of the data (level shifts, variance changes,
+    /// constant runs) instead of splitting it in fixed sizes.
+    #[arg(long, action, verbatim_doc_comment)]
+    adaptive_frames: bool,
+
+    /// Biggest frame size, in samples. Default is 131072.
+    /// Frames are made bigger if the data would need more than 255 frames.
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..), verbatim_doc_comment)]
+    max_frame_size: Option<u64>,
+
+    /// Data left at the end smaller than this goes in a single frame instead of being split further.
+    /// Default is 512.
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..), verbatim_doc_comment)]
+    min_frame_size: Option<u64>,
+
+    /// Picks the compressor of Auto frames from features of the data (distinct values, spectral flatness,
+    /// autocorrelation, smoothness) instead of compressing with every compressor. Faster, might compress less.
+    /// trust: compresses only with the predicted compressor
+    /// verify: compresses with the 2 best predicted compressors and keeps the smallest
+    #[arg(long, value_enum, verbatim_doc_comment)]
+    classifier: Option<ClassifierType>,
+
     /// Uncompresses the input file/directory
     #[arg(short, action)]
     uncompress: bool,
 
+    /// Compresses every frame with every candidate compressor and reports the size, error and time of each,
+    /// and which one was selected and why. Nothing is written.
+    #[arg(
+        long,
+        action,
+        conflicts_with_all = ["uncompress", "global_error", "target_size", "target_ratio"],
+        verbatim_doc_comment
+    )]
+    dry_run: bool,
+
+    /// JSON file with the compressors selected for each frame on previous runs. Auto frames try the cached
+    /// compressor and parameters first, and skip the search if the error is still met. The file is created if
+    /// it doesn't exist and updated with the new selections.
+    /// Not used with --global-error, --target-size or --target-ratio.
+    #[arg(long, conflicts_with_all = ["uncompress", "dry_run"], verbatim_doc_comment)]
+    selection_cache: Option<PathBuf>,
+
+    /// Name of the series in the selection cache. Default is the input file name, without the extension.
+    /// Not allowed when the input is a directory, each file is its own series.
+    #[arg(long, requires = "selection_cache", verbatim_doc_comment)]
+    series_name: Option<String>,
+
+    /// Format of the dry run report
+    #[arg(long, value_enum, default_value = "text", requires = "dry_run")]
+    report: ReportFormat,
+
     /// Samples the input data instead of using all the data for selecting the optimal compressor.
     /// Only impacts speed, might or not increased compression ratio. For best results use 0 (default).
+    /// The fast preset uses 4.
     /// Only works when compression = Auto.
     /// 0 will use all the data (slowest)
     /// 6 will sample 128 data points (fastest)
-    #[arg(short, long, default_value_t = 0, value_parser = clap::value_parser!(u8).range(0..7), verbatim_doc_comment )]
-    compression_selection_sample_level: u8,
+    #[arg(short, long, value_parser = clap::value_parser!(u8).range(0..7), verbatim_doc_comment )]
+    compression_selection_sample_level: Option<u8>,
+
+    /// How the samples are taken when the sample level is not 0.
+    /// prefix: the first samples of each frame
+    /// strided: windows evenly spaced over the frame
+    /// random: windows at random positions (fixed seed, the output is always the same)
+    /// Default is strided.
+    #[arg(long, value_enum, verbatim_doc_comment)]
+    sampling: Option<SamplingType>,
+
+    /// Number of threads used to compress and decompress frames, and to process the files of a directory.
+    /// 0 uses one per CPU core. The output is the same for any number of threads.
+    #[arg(long, default_value_t = 0, verbatim_doc_comment)]
+    threads: usize,
 
     /// Verbose output, dumps everysample in the input file (for compression) and in the ouput file (for decompression)
     #[arg(long, action)]
@@ -219,6 +556,105 @@ struct Args {
     fields: Option<String>,
 }
 
+fn parse_candidate(s: &str) -> Result<Compressor, String> {
+    match s.trim() {
+        "constant" => Ok(Compressor::Constant),
+        "trend" => Ok(Compressor::Trend),
+        "fft" => Ok(Compressor::FFT),
+        "polynomial" => Ok(Compressor::Polynomial),
+        "idw" => Ok(Compressor::Idw),
+        "rle" => Ok(Compressor::RLE),
+        "dictionary" => Ok(Compressor::Dictionary),
+        "noop" => Ok(Compressor::Noop),
+        "mdct" => Ok(Compressor::Mdct),
+        "wavelet" => Ok(Compressor::Wavelet),
+        "dct" => Ok(Compressor::Dct),
+        "pla" => Ok(Compressor::Pla),
+        "linear" => Ok(Compressor::Linear),
+        "spline" => Ok(Compressor::Spline),
+        "pchip" => Ok(Compressor::Pchip),
+        "akima" => Ok(Compressor::Akima),
+        "residual" => Ok(Compressor::Residual),
+        "spike" => Ok(Compressor::Spike),
+        other => Err(format!(
+            "Unknown compressor {other}, possible values: constant, trend, fft, polynomial, idw, rle, noop, mdct, wavelet, dct, pla, linear, spline, pchip, akima, residual, spike, dictionary"
+        )),
+    }
+}
+
+fn parse_ratio(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(ratio) if ratio > 0.0 => Ok(ratio),
+        _ => Err("Ratio must be a number bigger than 0".to_string()),
+    }
+}
+
+#[derive(clap::ValueEnum, Default, Clone, Debug)]
+enum ReportFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+/// Dry run report of a file
+#[derive(Serialize)]
+struct FileReport<'a> {
+    input: String,
+    #[serde(flatten)]
+    report: &'a Report,
+}
+
+#[derive(clap::ValueEnum, Default, Clone, Debug)]
+enum PresetType {
+    Fast,
+    #[default]
+    Balanced,
+    Max,
+}
+
+impl PresetType {
+    fn preset(&self) -> Preset {
+        match self {
+            PresetType::Fast => Preset::Fast,
+            PresetType::Balanced => Preset::Balanced,
+            PresetType::Max => Preset::Max,
+        }
+    }
+}
+
+#[derive(clap::ValueEnum, Default, Clone, Debug)]
+enum SamplingType {
+    Prefix,
+    #[default]
+    Strided,
+    Random,
+}
+
+impl SamplingType {
+    fn strategy(&self) -> SamplingStrategy {
+        match self {
+            SamplingType::Prefix => SamplingStrategy::Prefix,
+            SamplingType::Strided => SamplingStrategy::Strided,
+            SamplingType::Random => SamplingStrategy::Random { seed: DEFAULT_SEED },
+        }
+    }
+}
+
+#[derive(clap::ValueEnum, Clone, Debug)]
+enum ClassifierType {
+    Trust,
+    Verify,
+}
+
+impl ClassifierType {
+    fn mode(&self) -> ClassifierMode {
+        match self {
+            ClassifierType::Trust => ClassifierMode::Trust,
+            ClassifierType::Verify => ClassifierMode::VerifyTop2,
+        }
+    }
+}
+
 #[derive(clap::ValueEnum, Default, Clone, Debug)]
 enum CompressorType {
     #[default]
@@ -229,6 +665,18 @@ enum CompressorType {
     Polynomial,
     Idw,
     Rle,
+    Mdct,
+    Wavelet,
+    Dct,
+    Pla,
+    Linear,
+    Spline,
+    Pchip,
+    Akima,
+    Residual,
+    Spike,
+    Dictionary,
+    Trend,
 }
 
 fn main() {
@@ -236,6 +684,14 @@ fn main() {
     let arguments = Args::parse();
     debug!("{:?}", arguments);
 
+    if let Err(e) = rayon::ThreadPoolBuilder::new()
+        .num_threads(arguments.threads)
+        .build_global()
+    {
+        error!("{}", e);
+        std::process::exit(1);
+    }
+
     if let Err(e) = process_args(&arguments) {
         error!("{}", e);
         std::process::exit(1);
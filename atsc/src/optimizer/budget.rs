@@ -0,0 +1,407 @@
+/*
+Copyright 2024 NetApp, Inc.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    https://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use crate::{
+    compressor::{mdct::BLOCK_SIZE, Compressor},
+    utils::{error::calculate_error, next_size},
+};
+use log::{debug, trace};
+
+/// Error levels, relative to the global target, used to sample the size-vs-error curve of a compressor.
+/// Frames that are easy to compress can go above the target, hard ones go below it.
+const ERROR_LEVELS: [f64; 7] = [0.25, 0.5, 1.0, 1.5, 2.0, 4.0, 8.0];
+
+/// One point of the size-vs-error curve of a frame
+#[derive(Debug, Clone, PartialEq)]
+pub struct FramePoint {
+    pub compressor: Compressor,
+    pub compressed_data: Vec<u8>,
+    /// Error measured over the decompressed frame
+    pub error: f64,
+}
+
+impl FramePoint {
+    pub fn size(&self) -> usize {
+        self.compressed_data.len()
+    }
+}
+
+/// Computes the size-vs-error curve of a frame for the provided compressors.
+/// Each compressor is run for several error levels around `target`, and the error is measured on the
+/// decompressed output, not trusted from the compressor.
+/// Returns only the useful points (Pareto front), sorted by increasing size and decreasing error.
+pub fn frame_curve(data: &[f64], compressors: &[Compressor], target: f64) -> Vec<FramePoint> {
+    let mut points = Vec::with_capacity(compressors.len() * ERROR_LEVELS.len());
+    for compressor in compressors {
+        // Lossless compressors, and the single value or line, don't care about the error, a single run is enough
+        let levels: &[f64] = match compressor {
+            Compressor::Noop
+            | Compressor::Constant
+            | Compressor::Trend
+            | Compressor::RLE
+            | Compressor::Dictionary => &[1.0],
+            _ => &ERROR_LEVELS,
+        };
+        for level in levels {
+            let result = compressor.get_compress_bounded_results(data, target * level);
+            trace!("Curve point: {:?} level: {}", compressor, level);
+            points.push(measure(data, *compressor, result.compressed_data));
+        }
+    }
+    pareto_front(points)
+}
+
+/// Computes the size-vs-error curve of a frame by walking the parameters of the provided compressors,
+/// the number of frequencies for FFT, of coefficients for DCT, MDCT and Wavelet and the number of points for
+/// Polynomial, IDW and the other interpolations.
+/// Parameters grow geometrically, so large frames don't need a run per parameter value.
+/// Returns only the useful points (Pareto front), sorted by increasing size and decreasing error.
+pub fn frame_size_curve(data: &[f64], compressors: &[Compressor]) -> Vec<FramePoint> {
+    let mut points = Vec::new();
+    for compressor in compressors {
+        let hints = match compressor {
+            // Real signal, half the spectrum (plus the DC component) is all there is
+            Compressor::FFT => geometric_range(1, next_size(data.len()) / 2 + 1),
+            // Blocks of a frame on its own, up to the one past the end
+            Compressor::Mdct => {
+                geometric_range(1, (data.len().div_ceil(BLOCK_SIZE) + 1) * BLOCK_SIZE)
+            }
+            // A coefficient per sample
+            Compressor::Dct => geometric_range(1, data.len()),
+            // Past the samples of the frame the coefficients left are the ones of the padding
+            Compressor::Wavelet => geometric_range(1, data.len()),
+            // Point step is stored in a byte, so there is a minimum number of points
+            Compressor::Polynomial
+            | Compressor::Idw
+            | Compressor::Linear
+            | Compressor::Spline
+            | Compressor::Pchip
+            | Compressor::Akima => {
+                geometric_range(data.len().div_ceil(u8::MAX as usize).max(2), data.len())
+            }
+            _ => vec![0],
+        };
+        for hint in hints {
+            trace!("Curve point: {:?} hint: {}", compressor, hint);
+            let compressed_data = compressor.compress_hinted(data, hint);
+            points.push(measure(data, *compressor, compressed_data));
+        }
+    }
+    pareto_front(points)
+}
+
+/// Values from `start` to `end` (both included), growing by half of the previous value each step
+pub(crate) fn geometric_range(start: usize, end: usize) -> Vec<usize> {
+    let mut values = Vec::new();
+    let mut value = start.max(1);
+    while value < end {
+        values.push(value);
+        value += (value / 2).max(1);
+    }
+    values.push(end);
+    values
+}
+
+/// Decompresses the data and measures the error of a frame compression
+pub(crate) fn measure(
+    data: &[f64],
+    compressor: Compressor,
+    compressed_data: Vec<u8>,
+) -> FramePoint {
+    let out = compressor.decompress(data.len(), &compressed_data);
+    // Some metrics can't handle zeros in the data, a perfect reconstruction is always 0 error and an
+    // undefined one is as bad as it gets.
+    let error = match calculate_error(data, &out) {
+        _ if out == data => 0.0,
+        e if e.is_nan() => f64::INFINITY,
+        e => e,
+    };
+    trace!(
+        "Measured {:?} size: {} error: {}",
+        compressor,
+        compressed_data.len(),
+        error
+    );
+    FramePoint {
+        compressor,
+        compressed_data,
+        error,
+    }
+}
+
+/// Keeps the points that are not beaten in both size and error by another point
+fn pareto_front(mut points: Vec<FramePoint>) -> Vec<FramePoint> {
+    points.sort_by(|a, b| a.size().cmp(&b.size()).then(a.error.total_cmp(&b.error)));
+    let mut front: Vec<FramePoint> = Vec::with_capacity(points.len());
+    for point in points {
+        match front.last() {
+            Some(last) if point.error >= last.error => continue,
+            _ => front.push(point),
+        }
+    }
+    front
+}
+
+/// Picks one point per frame so that the total size is minimal and the stream error stays under `target`.
+/// The stream error is the sample weighted mean of the frames error, which is exact for the mean based
+/// metrics (e.g. MAPE, the default).
+///
+/// Starts every frame at its smallest point, and then, while the stream is above the target, moves the frame
+/// that buys the most error reduction per extra byte to a better point of its curve. Once a single move is
+/// enough to reach the target, the cheapest of those is taken. Finally, frames are moved back to smaller
+/// points while there is budget left.
+/// If the target can't be reached, every frame ends on its smallest error point.
+///
+/// Returns the index of the selected point for each frame.
+pub fn distribute_error(
+    curves: &[Vec<FramePoint>],
+    frame_sizes: &[usize],
+    target: f64,
+) -> Vec<usize> {
+    let total_samples: usize = frame_sizes.iter().sum();
+    let mut selection = vec![0; curves.len()];
+    let mut stream_error = weighted_error(curves, frame_sizes, &selection, total_samples);
+    while stream_error > target {
+        // Best move is the one with the largest error drop per byte added
+        let mut best_move: Option<(usize, usize, f64)> = None;
+        // Cheapest move that gets the stream under the target
+        let mut finishing_move: Option<(usize, usize, usize)> = None;
+        for (frame, curve) in curves.iter().enumerate() {
+            let current = &curve[selection[frame]];
+            for (i, candidate) in curve.iter().enumerate().skip(selection[frame] + 1) {
+                let error_drop = (current.error - candidate.error) * frame_sizes[frame] as f64;
+                let extra_bytes = candidate.size() - current.size();
+                if stream_error - error_drop / total_samples as f64 <= target
+                    && finishing_move.map_or(true, |(_, _, bytes)| extra_bytes < bytes)
+                {
+                    finishing_move = Some((frame, i, extra_bytes));
+                }
+                let gain = error_drop / extra_bytes.max(1) as f64;
+                if gain.is_nan() {
+                    continue;
+                }
+                if best_move.map_or(true, |(_, _, best_gain)| gain > best_gain) {
+                    best_move = Some((frame, i, gain));
+                }
+            }
+        }
+        match (finishing_move, best_move) {
+            (Some((frame, i, _)), _) | (None, Some((frame, i, _))) => selection[frame] = i,
+            // Every frame is on the lowest error it can do
+            (None, None) => break,
+        }
+        stream_error = weighted_error(curves, frame_sizes, &selection, total_samples);
+    }
+    // Greedy moves can overshoot, give back whatever budget is left to the frames that save more bytes
+    if stream_error <= target {
+        loop {
+            let mut best_relax: Option<(usize, usize, usize)> = None;
+            for (frame, curve) in curves.iter().enumerate() {
+                let current = &curve[selection[frame]];
+                for (i, candidate) in curve.iter().enumerate().take(selection[frame]) {
+                    let error_rise = (candidate.error - current.error) * frame_sizes[frame] as f64;
+                    let saved_bytes = current.size() - candidate.size();
+                    if stream_error + error_rise / total_samples as f64 <= target
+                        && best_relax.map_or(true, |(_, _, bytes)| saved_bytes > bytes)
+                    {
+                        best_relax = Some((frame, i, saved_bytes));
+                    }
+                }
+            }
+            match best_relax {
+                Some((frame, i, _)) => selection[frame] = i,
+                None => break,
+            }
+            stream_error = weighted_error(curves, frame_sizes, &selection, total_samples);
+        }
+    }
+    debug!(
+        "Error budget distributed. Target: {} Stream error: {}",
+        target, stream_error
+    );
+    selection
+}
+
+/// Picks one point per frame so that the stream error is minimal and the total size stays under `max_bytes`.
+/// The mirror of [`distribute_error`]: starts every frame at its smallest point, and then, while there are
+/// bytes left, moves the frame that buys the most error reduction per extra byte to a better point of its
+/// curve, as long as the move fits.
+/// If even the smallest points don't fit, those are returned.
+///
+/// Returns the index of the selected point for each frame.
+pub fn fit_size(curves: &[Vec<FramePoint>], frame_sizes: &[usize], max_bytes: usize) -> Vec<usize> {
+    let mut selection = vec![0; curves.len()];
+    let mut total_bytes: usize = curves.iter().map(|curve| curve[0].size()).sum();
+    loop {
+        let mut best_move: Option<(usize, usize, f64)> = None;
+        for (frame, curve) in curves.iter().enumerate() {
+            let current = &curve[selection[frame]];
+            for (i, candidate) in curve.iter().enumerate().skip(selection[frame] + 1) {
+                let extra_bytes = candidate.size() - current.size();
+                if total_bytes + extra_bytes > max_bytes {
+                    // Curves are sorted by size, nothing else fits for this frame
+                    break;
+                }
+                let error_drop = (current.error - candidate.error) * frame_sizes[frame] as f64;
+                let gain = error_drop / extra_bytes.max(1) as f64;
+                if gain.is_nan() {
+                    continue;
+                }
+                if best_move.map_or(true, |(_, _, best_gain)| gain > best_gain) {
+                    best_move = Some((frame, i, gain));
+                }
+            }
+        }
+        match best_move {
+            Some((frame, i, _)) => {
+                total_bytes += curves[frame][i].size() - curves[frame][selection[frame]].size();
+                selection[frame] = i;
+            }
+            None => break,
+        }
+    }
+    debug!(
+        "Size budget distributed. Target: {} Size: {} Stream error: {}",
+        max_bytes,
+        total_bytes,
+        weighted_error(curves, frame_sizes, &selection, frame_sizes.iter().sum())
+    );
+    selection
+}
+
+/// Sample weighted mean of the frames error, this is the error of the whole stream for the mean based metrics
+pub fn stream_error(points: &[FramePoint], frame_sizes: &[usize]) -> f64 {
+    let total_samples: usize = frame_sizes.iter().sum();
+    if total_samples == 0 {
+        return 0.0;
+    }
+    points
+        .iter()
+        .zip(frame_sizes)
+        .map(|(point, &size)| point.error * size as f64)
+        .sum::<f64>()
+        / total_samples as f64
+}
+
+/// Sample weighted mean of the selected frames error
+fn weighted_error(
+    curves: &[Vec<FramePoint>],
+    frame_sizes: &[usize],
+    selection: &[usize],
+    total_samples: usize,
+) -> f64 {
+    if total_samples == 0 {
+        return 0.0;
+    }
+    curves
+        .iter()
+        .zip(selection)
+        .zip(frame_sizes)
+        .map(|((curve, &i), &size)| curve[i].error * size as f64)
+        .sum::<f64>()
+        / total_samples as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(size: usize, error: f64) -> FramePoint {
+        FramePoint {
+            compressor: Compressor::FFT,
+            compressed_data: vec![0; size],
+            error,
+        }
+    }
+
+    #[test]
+    fn test_pareto_front() {
+        let points = vec![
+            point(10, 0.1),
+            point(20, 0.2),
+            point(30, 0.0),
+            point(5, 0.5),
+        ];
+        let front = pareto_front(points);
+        let sizes: Vec<usize> = front.iter().map(|p| p.size()).collect();
+        assert_eq!(sizes, [5, 10, 30]);
+    }
+
+    #[test]
+    fn test_distribute_error() {
+        // First frame is easy, it can go almost lossless for cheap. Second is hard.
+        let curves = vec![
+            vec![point(10, 0.04), point(12, 0.0)],
+            vec![point(10, 0.04), point(100, 0.02)],
+        ];
+        let selection = distribute_error(&curves, &[100, 100], 0.02);
+        assert_eq!(selection, [1, 0]);
+        let error = weighted_error(&curves, &[100, 100], &selection, 200);
+        assert!(error <= 0.02);
+    }
+
+    #[test]
+    fn test_distribute_error_unreachable() {
+        let curves = vec![vec![point(10, 0.5), point(20, 0.3)], vec![point(10, 0.4)]];
+        let selection = distribute_error(&curves, &[10, 10], 0.01);
+        assert_eq!(selection, [1, 0]);
+    }
+
+    #[test]
+    fn test_fit_size() {
+        let curves = vec![
+            vec![point(10, 0.04), point(12, 0.0)],
+            vec![point(10, 0.04), point(20, 0.03), point(100, 0.02)],
+        ];
+        assert_eq!(fit_size(&curves, &[100, 100], 30), [1, 0]);
+        assert_eq!(fit_size(&curves, &[100, 100], 32), [1, 1]);
+        assert_eq!(fit_size(&curves, &[100, 100], 500), [1, 2]);
+        // Doesn't fit at all
+        assert_eq!(fit_size(&curves, &[100, 100], 5), [0, 0]);
+    }
+
+    #[test]
+    fn test_geometric_range() {
+        assert_eq!(geometric_range(1, 10), [1, 2, 3, 4, 6, 9, 10]);
+        assert_eq!(geometric_range(5, 5), [5]);
+    }
+
+    #[test]
+    fn test_frame_size_curve() {
+        let data: Vec<f64> = (0..512)
+            .map(|x| (x as f64 / 20.0).sin() * 10.0 + 20.0)
+            .collect();
+        let curve = frame_size_curve(&data, &[Compressor::FFT, Compressor::Polynomial]);
+        assert!(curve.len() > 2);
+        for pair in curve.windows(2) {
+            assert!(pair[0].size() < pair[1].size());
+            assert!(pair[0].error > pair[1].error);
+        }
+    }
+
+    #[test]
+    fn test_frame_curve() {
+        let data: Vec<f64> = (0..512)
+            .map(|x| (x as f64 / 20.0).sin() * 10.0 + 20.0)
+            .collect();
+        let curve = frame_curve(&data, &[Compressor::FFT, Compressor::RLE], 0.01);
+        assert!(!curve.is_empty());
+        for pair in curve.windows(2) {
+            assert!(pair[0].size() < pair[1].size());
+            assert!(pair[0].error > pair[1].error);
+        }
+    }
+}
@@ -0,0 +1,245 @@
+/*
+Copyright 2024 NetApp, Inc.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    https://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use crate::compressor::dct::{dct_set_quantized, Dct};
+use crate::compressor::fft::{fft_set_quantized, FFT};
+use crate::compressor::polynomial::{Polynomial, ADAPTIVE_STEP};
+use crate::compressor::wavelet::Wavelet;
+use crate::compressor::Compressor;
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Failed to read or write the selection cache")]
+    Io(#[from] std::io::Error),
+
+    #[error("The selection cache is not valid")]
+    Format(#[from] serde_json::Error),
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Compressor that won the Auto selection of a frame, and the parameter it used
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct CachedSelection {
+    pub compressor: Compressor,
+    /// Frequency count for FFT, coefficient count for DCT and Wavelet, point count for Polynomial, IDW and the other
+    /// interpolations, none for compressors without parameters
+    pub parameter: Option<usize>,
+    /// Exponent of the quantization step of the FFT frequencies or DCT coefficients, if they were quantized
+    #[serde(default)]
+    pub quantization: Option<i8>,
+    /// Samples of the frame it was selected for
+    pub samples: usize,
+}
+
+impl CachedSelection {
+    /// Gets the selection from the output of `compressor` for a frame of `samples` samples
+    pub fn new(compressor: Compressor, samples: usize, compressed_data: &[u8]) -> Self {
+        let mut quantization = None;
+        let parameter = match compressor {
+            Compressor::FFT => {
+                let fft = FFT::decompress(compressed_data);
+                quantization = fft.quantization;
+                Some(fft.frequencies.len())
+            }
+            Compressor::Dct => {
+                let dct = Dct::decompress(compressed_data);
+                quantization = Some(dct.exponent);
+                Some(dct.coefficient_count())
+            }
+            Compressor::Wavelet => Some(Wavelet::decompress(compressed_data).coefficient_count()),
+            // Points on the step grid, the last sample that is always stored doesn't count. Points placed where the
+            // error is highest are recompressed as the same number of points on the grid.
+            Compressor::Polynomial
+            | Compressor::Idw
+            | Compressor::Linear
+            | Compressor::Spline
+            | Compressor::Pchip
+            | Compressor::Akima => {
+                let polynomial = Polynomial::decompress(compressed_data);
+                match polynomial.point_step {
+                    ADAPTIVE_STEP => Some(polynomial.data_points.len().saturating_sub(1).max(1)),
+                    step => Some((samples / step as usize).max(1)),
+                }
+            }
+            _ => None,
+        };
+        CachedSelection {
+            compressor,
+            parameter,
+            quantization,
+            samples,
+        }
+    }
+
+    /// Compresses `data` with the cached compressor and parameter. The parameter is scaled if the data doesn't have
+    /// the samples it was selected for, and so is the quantization, the frequencies and coefficients grow with the
+    /// samples.
+    pub fn compress(&self, data: &[f64]) -> Vec<u8> {
+        match self.parameter {
+            Some(parameter) => {
+                let hint = (parameter * data.len())
+                    .div_ceil(self.samples.max(1))
+                    .max(1);
+                let ratio = data.len() as f64 / self.samples.max(1) as f64;
+                let scaled = |exponent: i8| exponent.saturating_add(ratio.log2().round() as i8);
+                match (self.compressor, self.quantization) {
+                    (Compressor::FFT, Some(exponent)) => {
+                        fft_set_quantized(data, hint, scaled(exponent))
+                    }
+                    (Compressor::Dct, Some(exponent)) => {
+                        dct_set_quantized(data, hint, scaled(exponent))
+                    }
+                    _ => self.compressor.compress_hinted(data, hint),
+                }
+            }
+            None => self.compressor.compress(data),
+        }
+    }
+}
+
+/// Compressors selected for each frame of a series on previous runs, so compressing the same kind of data again
+/// can skip the search. Persisted as JSON.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct SelectionCache {
+    /// Selections of each series, by frame position
+    series: BTreeMap<String, Vec<Option<CachedSelection>>>,
+}
+
+impl SelectionCache {
+    /// Loads the cache from `path`, a missing file is an empty cache
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            debug!("No selection cache at {:?}, starting empty", path);
+            return Ok(SelectionCache::default());
+        }
+        let content = std::fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    /// Saves the cache to `path`. It is written next to it and renamed over it, so it is never read half written
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let temporary = path.with_extension("tmp");
+        std::fs::write(&temporary, serde_json::to_string_pretty(self)?)?;
+        std::fs::rename(&temporary, path)?;
+        Ok(())
+    }
+
+    /// Gets a cache with only the selections of `series`
+    pub fn series(&self, series: &str) -> SelectionCache {
+        SelectionCache {
+            series: self
+                .series
+                .get_key_value(series)
+                .map(|(name, frames)| (name.clone(), frames.clone()))
+                .into_iter()
+                .collect(),
+        }
+    }
+
+    /// Adds the selections of `other`, replacing the ones of the same series
+    pub fn merge(&mut self, other: SelectionCache) {
+        self.series.extend(other.series);
+    }
+
+    /// Gets the selection of the frame at position `frame` of `series`
+    pub fn get(&self, series: &str, frame: usize) -> Option<CachedSelection> {
+        self.series
+            .get(series)
+            .and_then(|frames| frames.get(frame).copied().flatten())
+    }
+
+    /// Records the selection of the frame at position `frame` of `series`, replacing the previous one
+    pub fn record(&mut self, series: &str, frame: usize, selection: CachedSelection) {
+        let frames = self.series.entry(series.to_string()).or_default();
+        if frames.len() <= frame {
+            frames.resize(frame + 1, None);
+        }
+        frames[frame] = Some(selection);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compressor::dct::dct_set;
+    use crate::compressor::fft::fft_set;
+    use crate::compressor::polynomial::{polynomial_set, PolynomialType};
+    use crate::compressor::wavelet::wavelet_set;
+
+    #[test]
+    fn test_cached_selection() {
+        let data: Vec<f64> = (0..1024)
+            .map(|x| (x as f64 * std::f64::consts::PI / 64.0).sin() * 10.0 + 20.0)
+            .collect();
+        let selection = CachedSelection::new(Compressor::FFT, data.len(), &fft_set(&data, 4));
+        assert_eq!(selection.parameter, Some(4));
+        assert_eq!(selection.compress(&data), fft_set(&data, 4));
+        // Quantized frequencies are quantized again with the same step
+        let quantized = fft_set_quantized(&data, 4, -2);
+        let selection = CachedSelection::new(Compressor::FFT, data.len(), &quantized);
+        assert_eq!(selection.quantization, Some(-2));
+        assert_eq!(selection.compress(&data), quantized);
+        // Hinting the point count gives back the same compression
+        let points = polynomial_set(&data, 100, PolynomialType::Polynomial);
+        let selection = CachedSelection::new(Compressor::Polynomial, data.len(), &points);
+        assert_eq!(selection.compress(&data), points);
+        let coefficients = dct_set(&data, 10);
+        let selection = CachedSelection::new(Compressor::Dct, data.len(), &coefficients);
+        assert_eq!(selection.parameter, Some(10));
+        assert_eq!(selection.compress(&data), coefficients);
+        let coefficients = wavelet_set(&data, 20);
+        let selection = CachedSelection::new(Compressor::Wavelet, data.len(), &coefficients);
+        assert_eq!(selection.parameter, Some(20));
+        assert_eq!(selection.compress(&data), coefficients);
+        let rle = CachedSelection::new(Compressor::RLE, data.len(), &[]);
+        assert_eq!(rle.parameter, None);
+    }
+
+    #[test]
+    fn test_selection_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cache.json");
+        let mut cache = SelectionCache::load(&path).unwrap();
+        assert_eq!(cache, SelectionCache::default());
+        let selection = CachedSelection {
+            compressor: Compressor::Polynomial,
+            parameter: Some(12),
+            quantization: None,
+            samples: 4096,
+        };
+        cache.record("memory_used", 2, selection);
+        assert_eq!(cache.get("memory_used", 2), Some(selection));
+        assert_eq!(cache.get("memory_used", 0), None);
+        assert_eq!(cache.get("uptime", 2), None);
+        cache.save(&path).unwrap();
+        assert_eq!(SelectionCache::load(&path).unwrap(), cache);
+        // A series is taken out, compressed and merged back
+        cache.record("uptime", 0, selection);
+        let mut memory_used = cache.series("memory_used");
+        assert_eq!(memory_used.get("memory_used", 2), Some(selection));
+        assert_eq!(memory_used.get("uptime", 0), None);
+        memory_used.record("memory_used", 0, selection);
+        cache.merge(memory_used);
+        assert_eq!(cache.get("memory_used", 0), Some(selection));
+        assert_eq!(cache.get("memory_used", 2), Some(selection));
+        assert_eq!(cache.get("uptime", 0), Some(selection));
+    }
+}
@@ -0,0 +1,197 @@
+/*
+Copyright 2024 NetApp, Inc.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    https://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::collections::HashSet;
+
+use crate::compressor::Compressor::{self, Constant, Polynomial, FFT, RLE};
+use crate::optimizer::utils::DataStats;
+use log::debug;
+use rustfft::{num_complex::Complex, FftPlanner};
+
+/// Max number of samples used to compute the spectrum, longer chunks are decimated
+const SPECTRUM_SIZE: usize = 4096;
+
+/// How much the Auto selection trusts the classifier
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ClassifierMode {
+    /// Compress only with the predicted compressor
+    #[default]
+    Trust,
+    /// Compress with the 2 best ranked compressors and keep the smallest
+    VerifyTop2,
+}
+
+/// Features of a chunk of data that are cheap to compute and tell which compressor fits it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Features {
+    /// Distinct values over the number of samples
+    pub distinct_ratio: f64,
+    /// Runs of equal consecutive values over the number of samples
+    pub run_ratio: f64,
+    /// Geometric mean over arithmetic mean of the power spectrum (DC excluded). Close to 0 for a few dominant
+    /// frequencies (periodic signal), close to 1 for white noise.
+    pub spectral_flatness: f64,
+    /// Lag 1 autocorrelation, close to 1 for slow moving signals
+    pub autocorrelation: f64,
+    /// Mean absolute change between samples over the range of the data, small for smooth signals
+    pub smoothness: f64,
+    /// All the values are integers
+    pub integer: bool,
+    /// All the values are the same
+    pub constant: bool,
+}
+
+impl Features {
+    pub fn new(data: &[f64]) -> Self {
+        let stats = DataStats::new(data);
+        let len = data.len() as f64;
+        let distinct: HashSet<u64> = data.iter().map(|x| x.to_bits()).collect();
+        let range = stats.max - stats.min;
+        let smoothness = if range > 0.0 {
+            stats.mean_abs_delta / range
+        } else {
+            0.0
+        };
+        Features {
+            distinct_ratio: distinct.len() as f64 / len,
+            run_ratio: stats.runs as f64 / len,
+            spectral_flatness: Features::spectral_flatness(data, stats.mean),
+            autocorrelation: Features::autocorrelation(data, stats.mean),
+            smoothness,
+            integer: !stats.fractional,
+            constant: stats.min == stats.max,
+        }
+    }
+
+    fn autocorrelation(data: &[f64], mean: f64) -> f64 {
+        let variance: f64 = data.iter().map(|x| (x - mean).powi(2)).sum();
+        if variance == 0.0 {
+            return 1.0;
+        }
+        let covariance: f64 = data.windows(2).map(|w| (w[0] - mean) * (w[1] - mean)).sum();
+        covariance / variance
+    }
+
+    fn spectral_flatness(data: &[f64], mean: f64) -> f64 {
+        // Decimate long chunks, the overall shape of the spectrum is what matters
+        let step = data.len().div_ceil(SPECTRUM_SIZE).max(1);
+        let mut buffer: Vec<Complex<f64>> = data
+            .iter()
+            .step_by(step)
+            .map(|&x| Complex {
+                re: x - mean,
+                im: 0.0,
+            })
+            .collect();
+        let len = buffer.len();
+        if len < 4 {
+            return 1.0;
+        }
+        FftPlanner::new().plan_fft_forward(len).process(&mut buffer);
+        let power: Vec<f64> = buffer[1..=len / 2].iter().map(|c| c.norm_sqr()).collect();
+        let arithmetic_mean = power.iter().sum::<f64>() / power.len() as f64;
+        if arithmetic_mean == 0.0 {
+            return 1.0;
+        }
+        // Log domain, with a floor so a single empty bin doesn't zero the geometric mean
+        let floor = arithmetic_mean * 1e-12;
+        let log_mean = power.iter().map(|p| p.max(floor).ln()).sum::<f64>() / power.len() as f64;
+        log_mean.exp() / arithmetic_mean
+    }
+}
+
+/// Ranks the compressors for a chunk from its features, best first:
+/// 1. Constant data is Constant, nothing else needed
+/// 2. Few runs are best handled by RLE, lossless and small
+/// 3. Very smooth data with long runs (e.g. slow counters) is still RLE, FFT is the runner up
+/// 4. Smooth and correlated data with the energy in a few frequencies is FFT, as is any almost pure tone
+/// 5. Everything else is Polynomial, it can always meet the error by adding points
+pub fn rank(features: &Features) -> Vec<Compressor> {
+    let ranking = match features {
+        Features { constant: true, .. } => vec![Constant],
+        Features { run_ratio, .. } if *run_ratio <= 0.05 => vec![RLE, Polynomial, FFT],
+        Features {
+            run_ratio,
+            smoothness,
+            ..
+        } if *run_ratio <= 0.2 && *smoothness <= 0.005 => vec![RLE, FFT, Polynomial],
+        Features {
+            smoothness,
+            spectral_flatness,
+            autocorrelation,
+            ..
+        } if *autocorrelation >= 0.7
+            && (*spectral_flatness <= 0.01 || *smoothness <= 0.02 && *spectral_flatness <= 0.3) =>
+        {
+            vec![FFT, Polynomial, RLE]
+        }
+        // Integers are cheaper to store as points
+        Features { integer: true, .. } => vec![Polynomial, FFT, RLE],
+        _ => vec![Polynomial, RLE, FFT],
+    };
+    debug!("Classifier ranking: {:?} Features: {:?}", ranking, features);
+    ranking
+}
+
+/// Predicts the best compressor for a chunk without compressing it
+pub fn predict(data: &[f64]) -> Compressor {
+    rank(&Features::new(data))[0]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_features() {
+        let constant = Features::new(&[1.0; 64]);
+        assert!(constant.constant);
+        assert!(constant.integer);
+        assert_eq!(constant.run_ratio, 1.0 / 64.0);
+
+        let sine: Vec<f64> = (0..1024)
+            .map(|x| (x as f64 * std::f64::consts::PI / 32.0).sin() * 10.0 + 0.5)
+            .collect();
+        let features = Features::new(&sine);
+        assert!(!features.integer);
+        assert!(features.spectral_flatness < 0.01);
+        assert!(features.autocorrelation > 0.9);
+
+        // Splitmix64, white noise without pulling in a rng
+        let noise: Vec<f64> = (0..1024u64)
+            .map(|x| {
+                let mut z = x.wrapping_mul(0x9E3779B97F4A7C15);
+                z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+                z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+                ((z ^ (z >> 31)) % 1000) as f64
+            })
+            .collect();
+        let features = Features::new(&noise);
+        assert!(features.spectral_flatness > 0.3);
+        assert!(features.autocorrelation < 0.5);
+    }
+
+    #[test]
+    fn test_predict() {
+        assert_eq!(predict(&[3.0; 256]), Compressor::Constant);
+        let steps: Vec<f64> = (0..1024).map(|x| (x / 256) as f64).collect();
+        assert_eq!(predict(&steps), Compressor::RLE);
+        let sine: Vec<f64> = (0..1024)
+            .map(|x| (x as f64 * std::f64::consts::PI / 32.0).sin() * 10.0 + 20.5)
+            .collect();
+        assert_eq!(predict(&sine), Compressor::FFT);
+    }
+}
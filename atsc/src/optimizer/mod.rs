@@ -14,20 +14,44 @@ See the License for the specific language governing permissions and
 limitations under the License.
 */
 
+use crate::data::CompressedStream;
+use crate::frame::CompressorFrame;
 use crate::{
-    compressor::Compressor,
-    utils::{f64_to_u64, prev_power_of_two},
+    compressor::{bits::bits_for, Compressor},
+    utils::prev_power_of_two,
 };
+use budget::{distribute_error, fit_size, frame_curve, frame_size_curve, FramePoint};
+use log::debug;
+use options::CompressionOptions;
+use rayon::prelude::*;
+use report::{ChunkReport, Report};
+use segmentation::segment_sizes;
+use utils::{Bitdepth, DataStats};
 
+pub mod budget;
+pub mod cache;
+pub mod classifier;
+pub mod options;
+pub mod report;
+pub mod sampling;
+pub mod segmentation;
 pub mod utils;
 
 /// Max Frame size, this can aprox. 36h of data at 1point/sec rate, a little more than 1 week at 1point/5sec
 /// and 1 month (30 days) at 1 point/20sec.
 /// This would be aprox. 1MB of Raw data (131072 * 64bits).
-const MAX_FRAME_SIZE: usize = 131072; // 2^17
+pub(crate) const MAX_FRAME_SIZE: usize = 131072; // 2^17
 /// The Min frame size is one that allows our compressors potentially achieve 100x compression. Currently the most
 /// limited one is the FFT compressor, that needs 3 frequencies at minimum, 3x100 = 300, next power of 2 is 512.
-const MIN_FRAME_SIZE: usize = 512; // 2^9
+pub(crate) const MIN_FRAME_SIZE: usize = 512; // 2^9
+/// The stream header stores the frame count in a byte
+pub(crate) const MAX_FRAMES: usize = u8::MAX as usize;
+/// Max ratio of runs (sequences of the same value) to samples for a chunk to be assigned to RLE.
+const RLE_RUN_RATIO: f64 = 0.05;
+/// Max ratio of runs to samples for a chunk to be assigned to RLE when compressing without loss.
+const LOSSLESS_RLE_RUN_RATIO: f64 = 0.5;
+/// Max distinct values for a chunk to be assigned to Dictionary, the states of a status series.
+const DICTIONARY_MAX_VALUES: usize = 16;
 
 // My idea here:
 // 1. Clean data
@@ -40,18 +64,65 @@ pub struct OptimizerPlan {
     pub data: Vec<f64>,
     pub chunk_sizes: Vec<usize>,
     pub compressors: Vec<Compressor>,
+    /// Compressors the Auto selection picks from
+    pub candidates: Vec<Compressor>,
 }
 
 impl OptimizerPlan {
+    /// Creates an optimal data compression plan for a maximum error. Each chunk gets the compressor that fits its
+    /// data, or Auto if it needs to be picked by compressing.
+    pub fn plan_bounded(data: &[f64], max_error: f32) -> Self {
+        OptimizerPlan::plan_with_options(data, Some(max_error), &CompressionOptions::default())
+    }
+
     /// Creates an optimal data compression plan
     pub fn plan(data: &[f64]) -> Self {
+        OptimizerPlan::plan_with_options(data, None, &CompressionOptions::default())
+    }
+
+    /// Creates a data compression plan where the chunk boundaries are placed at the change points of the data
+    /// (level shifts, variance changes, constant runs) instead of fixed sizes, so each chunk suits one compressor.
+    /// If a maximum error is provided, compressors are assigned as in [`OptimizerPlan::plan_bounded`].
+    pub fn plan_adaptive(data: &[f64], max_error: Option<f32>) -> Self {
+        let options = CompressionOptions {
+            adaptive_frames: true,
+            ..Default::default()
+        };
+        OptimizerPlan::plan_with_options(data, max_error, &options)
+    }
+
+    /// Creates a data compression plan with the frame sizing and the candidates of `options`.
+    /// If a maximum error is provided, compressors are assigned as in [`OptimizerPlan::plan_bounded`].
+    pub fn plan_with_options(
+        data: &[f64],
+        max_error: Option<f32>,
+        options: &CompressionOptions,
+    ) -> Self {
         let c_data = OptimizerPlan::clean_data(data);
-        let chunks = OptimizerPlan::get_chunks_sizes(c_data.len());
-        let optimizer = OptimizerPlan::assign_compressor(&c_data, &chunks, None);
+        // Frames grow until they fit in the header frame count
+        let mut max_frame_size = options.max_frame_size;
+        let chunks = loop {
+            let chunks = if options.adaptive_frames {
+                segment_sizes(&c_data, max_frame_size)
+            } else {
+                OptimizerPlan::get_chunks_sizes(
+                    c_data.len(),
+                    options.min_frame_size.min(max_frame_size),
+                    max_frame_size,
+                )
+            };
+            if chunks.len() <= MAX_FRAMES {
+                break chunks;
+            }
+            max_frame_size *= 2;
+        };
+        let compressors =
+            OptimizerPlan::assign_compressor(&c_data, &chunks, max_error, &options.candidates);
         OptimizerPlan {
             data: c_data,
             chunk_sizes: chunks,
-            compressors: optimizer,
+            compressors,
+            candidates: options.candidates.clone(),
         }
     }
 
@@ -60,6 +131,89 @@ impl OptimizerPlan {
         self.compressors = new_compressors;
     }
 
+    /// Distributes a single error target over the whole data instead of applying it to every chunk.
+    /// Builds (in parallel) the size-vs-error curve of each chunk for its assigned compressor (or the Auto candidates) and
+    /// picks the combination with the smallest total size that keeps the stream error under `max_error`.
+    /// Returns the selected compression for each chunk, in execution order.
+    pub fn distribute_error(&self, max_error: f32) -> Vec<FramePoint> {
+        let target = max_error as f64;
+        let curves: Vec<Vec<FramePoint>> = self
+            .get_execution()
+            .into_par_iter()
+            .map(|(compressor, data)| {
+                frame_curve(data, &self.curve_candidates(compressor, data), target)
+            })
+            .collect();
+        let selection = distribute_error(&curves, &self.chunk_sizes, target);
+        OptimizerPlan::select_points(curves, selection)
+    }
+
+    /// Compresses the data to fit in `max_bytes` with the lowest possible error.
+    /// Searches (in parallel) the number of frequencies (FFT) and points (Polynomial, IDW) of each chunk for its assigned
+    /// compressor (or the Auto candidates) and picks the combination with the smallest stream error that fits.
+    /// `max_bytes` only accounts for the compressed data, the stream and frames headers are not included.
+    /// Returns the selected compression for each chunk, in execution order.
+    pub fn fit_size(&self, max_bytes: usize) -> Vec<FramePoint> {
+        let curves: Vec<Vec<FramePoint>> = self
+            .get_execution()
+            .into_par_iter()
+            .map(|(compressor, data)| {
+                frame_size_curve(data, &self.curve_candidates(compressor, data))
+            })
+            .collect();
+        let selection = fit_size(&curves, &self.chunk_sizes, max_bytes);
+        OptimizerPlan::select_points(curves, selection)
+    }
+
+    /// Compresses every chunk with all the candidates of `options` and with the compressor the compression would use,
+    /// without keeping the output. Reports size, error and time of each, and why the compressor was selected.
+    pub fn dry_run(&self, max_error: f32, options: &CompressionOptions) -> Report {
+        let mut start = 0;
+        let chunks = self
+            .get_execution()
+            .into_iter()
+            .map(|(compressor, data)| {
+                debug!("Dry run of chunk at {} of size {}", start, data.len());
+                let chunk = ChunkReport {
+                    start,
+                    samples: data.len(),
+                    planned: *compressor,
+                    frame: CompressorFrame::dry_run(data, *compressor, max_error, options),
+                };
+                start += data.len();
+                chunk
+            })
+            .collect();
+        Report::new(
+            max_error,
+            chunks,
+            CompressedStream::overhead(&self.chunk_sizes),
+        )
+    }
+
+    /// Compressors to build the size-vs-error curve of a chunk
+    fn curve_candidates(&self, compressor: &Compressor, data: &[f64]) -> Vec<Compressor> {
+        match compressor {
+            Compressor::Auto => {
+                let stats = DataStats::new(data);
+                if stats.min == stats.max {
+                    vec![Compressor::Constant]
+                } else {
+                    self.candidates.clone()
+                }
+            }
+            _ => vec![*compressor],
+        }
+    }
+
+    fn select_points(curves: Vec<Vec<FramePoint>>, selection: Vec<usize>) -> Vec<FramePoint> {
+        curves
+            .into_iter()
+            .zip(selection)
+            .map(|(mut curve, i)| curve.swap_remove(i))
+            .collect()
+    }
+
     /// Removes NaN and infinite references from the data
     pub fn clean_data(wav_data: &[f64]) -> Vec<f64> {
         // Cleaning data, removing NaN, etc. This might reduce sample count
@@ -75,15 +229,19 @@ impl OptimizerPlan {
     /// 1. Collection rate - It is not expected that the collection rate exceeds 1point sec (it is expected actually less)
     /// 2. Maximum compression achievable - A compressed frame as overhead and a minimum number of segments, small frames don't allow great compressions
     /// 3. FFT operates faster under power of 2
-    fn get_chunks_sizes(mut len: usize) -> Vec<usize> {
+    fn get_chunks_sizes(
+        mut len: usize,
+        min_frame_size: usize,
+        max_frame_size: usize,
+    ) -> Vec<usize> {
         let mut chunk_sizes = Vec::<usize>::new();
         while len > 0 {
             match len {
-                _ if len >= MAX_FRAME_SIZE => {
-                    chunk_sizes.push(MAX_FRAME_SIZE);
-                    len -= MAX_FRAME_SIZE;
+                _ if len >= max_frame_size => {
+                    chunk_sizes.push(max_frame_size);
+                    len -= max_frame_size;
                 }
-                _ if len <= MIN_FRAME_SIZE => {
+                _ if len <= min_frame_size => {
                     chunk_sizes.push(len);
                     len = 0;
                 }
@@ -108,35 +266,108 @@ impl OptimizerPlan {
         output
     }
 
-    /// Walks the data, checks how much variability is in the data, and assigns a compressor based on that
-    fn get_compressor(data: &[f64]) -> Compressor {
-        let _ = data.iter().map(|&f| f64_to_u64(f, 0));
-        Compressor::FFT
+    /// Walks the data, checks how much variability is in the data, and assigns a compressor based on that.
+    /// With an error bound, chunks that don't clearly fit one compressor are left for the Auto selection.
+    /// 1. Constant data goes to Constant. With an error bound, so does data a single value meets the error for, or
+    ///    Trend if a line does. Only if they are candidates.
+    /// 2. Data with few runs goes to RLE, it is lossless and small. Lossless compression has no other
+    ///    option than storing the values, so RLE takes more runs in that case. Only if RLE is a candidate.
+    /// 3. Data with few distinct values goes to Dictionary, also lossless. With few runs as well, the one with the
+    ///    smallest estimated size between RLE and Dictionary. Only if Dictionary is a candidate.
+    fn get_compressor(
+        data: &[f64],
+        max_error: Option<f32>,
+        candidates: &[Compressor],
+    ) -> Compressor {
+        let stats = DataStats::new(data);
+        if stats.min == stats.max {
+            return Compressor::Constant;
+        }
+        if let Some(err) = max_error {
+            let flat = [Compressor::Constant, Compressor::Trend]
+                .into_iter()
+                .filter(|c| candidates.contains(c))
+                .find(|c| c.get_compress_bounded_results(data, err as f64).error <= err as f64);
+            if let Some(compressor) = flat {
+                return compressor;
+            }
+        }
+        let run_ratio = stats.runs as f64 / data.len() as f64;
+        let rle = candidates.contains(&Compressor::RLE);
+        let dictionary =
+            candidates.contains(&Compressor::Dictionary) && stats.distinct <= DICTIONARY_MAX_VALUES;
+        let runs =
+            if dictionary && dictionary_size(&stats, data.len()) < rle_size(&stats, data.len()) {
+                Compressor::Dictionary
+            } else {
+                Compressor::RLE
+            };
+        match max_error {
+            Some(err) if rle && err == 0.0 && run_ratio <= LOSSLESS_RLE_RUN_RATIO => runs,
+            _ if rle && run_ratio <= RLE_RUN_RATIO => runs,
+            _ if dictionary => Compressor::Dictionary,
+            // Let the frame pick by compressing
+            Some(_) => Compressor::Auto,
+            // No bound, keep the default
+            None => Compressor::FFT,
+        }
     }
 
     fn assign_compressor(
         clean_data: &[f64],
         chunks: &[usize],
         max_error: Option<f32>,
+        candidates: &[Compressor],
     ) -> Vec<Compressor> {
         let mut selection = Vec::with_capacity(chunks.len());
-        match max_error {
-            Some(_err) => todo!(),
-            None => {
-                let mut s = 0;
-                for size in chunks.iter() {
-                    selection.push(OptimizerPlan::get_compressor(&clean_data[s..(s + *size)]));
-                    s += *size;
-                }
-            }
+        let mut s = 0;
+        for size in chunks.iter() {
+            let compressor =
+                OptimizerPlan::get_compressor(&clean_data[s..(s + *size)], max_error, candidates);
+            debug!("Chunk of size {} assigned to {:?}", size, compressor);
+            selection.push(compressor);
+            s += *size;
         }
         selection
     }
 }
 
+/// Estimated bytes of an Index RLE frame, the smallest of a gap to the previous run of the same value per run, or
+/// a value and a length per run
+fn rle_size(stats: &DataStats, len: usize) -> f64 {
+    let run = len as f64 / stats.runs as f64;
+    let gaps = stats.runs as f64 * varint_size(run * stats.distinct as f64);
+    let value = match stats.bitdepth {
+        Bitdepth::U8 => 1.0,
+        Bitdepth::I16 => 2.0,
+        Bitdepth::I32 => 4.0,
+        Bitdepth::F64 => 8.0,
+    };
+    gaps.min(stats.runs as f64 * (value + varint_size(run)))
+}
+
+/// Bytes of a variable length integer
+fn varint_size(value: f64) -> f64 {
+    match value {
+        _ if value < 251.0 => 1.0,
+        _ if value < 65536.0 => 3.0,
+        _ => 5.0,
+    }
+}
+
+/// Estimated bytes of a Dictionary frame, a code per sample or a code and a length per run
+fn dictionary_size(stats: &DataStats, len: usize) -> f64 {
+    let code = bits_for(stats.distinct) as f64;
+    // Rice coded around the mean length of the runs
+    let length = (len as f64 / stats.runs as f64).log2() + 2.0;
+    (len as f64 * code).min(stats.runs as f64 * (code + length)) / 8.0
+}
+
 #[cfg(test)]
 mod tests {
+    use super::options::DEFAULT_CANDIDATES;
     use super::*;
+    use crate::frame::Selection;
 
     #[test]
     fn optimizer() {
@@ -153,13 +384,19 @@ mod tests {
         let len_right_sized: usize = 2048;
         let len_some_size: usize = 12032;
         assert_eq!(
-            OptimizerPlan::get_chunks_sizes(len_very_large),
+            OptimizerPlan::get_chunks_sizes(len_very_large, MIN_FRAME_SIZE, MAX_FRAME_SIZE),
             [131072, 131072, 131072, 1024, 512, 229]
         );
-        assert_eq!(OptimizerPlan::get_chunks_sizes(len_small), [31]);
-        assert_eq!(OptimizerPlan::get_chunks_sizes(len_right_sized), [2048]);
         assert_eq!(
-            OptimizerPlan::get_chunks_sizes(len_some_size),
+            OptimizerPlan::get_chunks_sizes(len_small, MIN_FRAME_SIZE, MAX_FRAME_SIZE),
+            [31]
+        );
+        assert_eq!(
+            OptimizerPlan::get_chunks_sizes(len_right_sized, MIN_FRAME_SIZE, MAX_FRAME_SIZE),
+            [2048]
+        );
+        assert_eq!(
+            OptimizerPlan::get_chunks_sizes(len_some_size, MIN_FRAME_SIZE, MAX_FRAME_SIZE),
             [8192, 2048, 1024, 512, 256]
         );
     }
@@ -167,8 +404,131 @@ mod tests {
     #[test]
     fn assign_compressor() {
         let fake_data = vec![12.23; 132671];
-        let chunks = OptimizerPlan::get_chunks_sizes(fake_data.len());
-        let compressor_vec = OptimizerPlan::assign_compressor(&fake_data, &chunks, None);
+        let chunks =
+            OptimizerPlan::get_chunks_sizes(fake_data.len(), MIN_FRAME_SIZE, MAX_FRAME_SIZE);
+        let compressor_vec =
+            OptimizerPlan::assign_compressor(&fake_data, &chunks, None, &DEFAULT_CANDIDATES);
         assert_eq!(compressor_vec.len(), 4);
     }
+
+    #[test]
+    fn assign_compressor_bounded() {
+        let constant = vec![12.23; 1024];
+        let steps: Vec<f64> = (0..1024).map(|x| (x / 256) as f64).collect();
+        let smooth: Vec<f64> = (0..1024)
+            .map(|x| 100.0 + (x as f64 / 100.0).sin())
+            .collect();
+        let repeated: Vec<f64> = (0..1024).map(|x| ((x / 3) % 5) as f64).collect();
+        let noise: Vec<f64> = (0..1024).map(|x| ((x * 7919) % 101) as f64 + 1.0).collect();
+        let data = [constant, steps, smooth, noise, repeated].concat();
+        let chunks = [1024, 1024, 1024, 1024, 1024];
+        let compressor_vec =
+            OptimizerPlan::assign_compressor(&data, &chunks, Some(0.01), &DEFAULT_CANDIDATES);
+        assert_eq!(
+            compressor_vec,
+            [
+                Compressor::Constant,
+                Compressor::Dictionary,
+                Compressor::Constant,
+                Compressor::Auto,
+                Compressor::Dictionary
+            ]
+        );
+        // Without Dictionary, few runs go to RLE and lossless has nothing better than RLE for repeated values
+        let candidates = [Compressor::FFT, Compressor::RLE];
+        let compressor_vec =
+            OptimizerPlan::assign_compressor(&data, &chunks, Some(0.01), &candidates);
+        assert_eq!(compressor_vec[1], Compressor::RLE);
+        assert_eq!(compressor_vec[4], Compressor::Auto);
+        let compressor_vec =
+            OptimizerPlan::assign_compressor(&data, &chunks, Some(0.0), &candidates);
+        assert_eq!(compressor_vec[4], Compressor::RLE);
+        // A ramp with jitter is a line within the error, only if Trend is a candidate
+        let ramp: Vec<f64> = (0..1024)
+            .map(|x| 100.0 + x as f64 + (x % 3) as f64 * 0.1)
+            .collect();
+        let compressor_vec =
+            OptimizerPlan::assign_compressor(&ramp, &[1024], Some(0.01), &candidates);
+        assert_eq!(compressor_vec, [Compressor::Auto]);
+        let compressor_vec =
+            OptimizerPlan::assign_compressor(&ramp, &[1024], Some(0.01), &DEFAULT_CANDIDATES);
+        assert_eq!(compressor_vec, [Compressor::Trend]);
+    }
+
+    #[test]
+    fn plan_adaptive() {
+        let data: Vec<f64> = (0..4096)
+            .map(|x| {
+                if x < 3000 {
+                    10.0
+                } else {
+                    20.0 + (x % 7) as f64
+                }
+            })
+            .collect();
+        let op = OptimizerPlan::plan_adaptive(&data, Some(0.01));
+        assert_eq!(op.chunk_sizes, [3000, 1096]);
+        assert_eq!(op.compressors[0], Compressor::Constant);
+        assert_eq!(op.get_execution().len(), 2);
+    }
+
+    #[test]
+    fn plan_with_options() {
+        let data: Vec<f64> = (0..4096).map(|x| ((x / 16) % 32) as f64 + 1.0).collect();
+        let options = CompressionOptions {
+            candidates: vec![Compressor::Polynomial, Compressor::Idw],
+            max_frame_size: 1024,
+            ..Default::default()
+        };
+        let op = OptimizerPlan::plan_with_options(&data, Some(0.01), &options);
+        assert_eq!(op.chunk_sizes, [1024, 1024, 1024, 1024]);
+        // Without RLE the runs are left for the Auto selection
+        assert_eq!(op.compressors, [Compressor::Auto; 4]);
+        // The frame count fits in the header
+        let options = CompressionOptions {
+            max_frame_size: 8,
+            min_frame_size: 8,
+            ..Default::default()
+        };
+        let op = OptimizerPlan::plan_with_options(&data, None, &options);
+        assert!(op.chunk_sizes.len() <= MAX_FRAMES);
+        assert_eq!(op.chunk_sizes.iter().sum::<usize>(), data.len());
+    }
+
+    #[test]
+    fn dry_run() {
+        let steps: Vec<f64> = (0..1024).map(|x| (x / 256) as f64).collect();
+        let noise: Vec<f64> = (0..1024).map(|x| ((x * 7919) % 101) as f64 + 1.0).collect();
+        let data = [vec![12.23; 1024], steps, noise].concat();
+        let options = CompressionOptions {
+            max_frame_size: 1024,
+            ..Default::default()
+        };
+        let op = OptimizerPlan::plan_with_options(&data, Some(0.01), &options);
+        let report = op.dry_run(0.01, &options);
+        assert_eq!(report.chunks.len(), 3);
+        assert_eq!(report.chunks[1].start, 1024);
+        assert_eq!(report.chunks[0].frame.reason, Selection::Planned);
+        assert_eq!(report.chunks[2].planned, Compressor::Auto);
+        // The Auto selection keeps the smallest candidate that meets the error
+        let auto = &report.chunks[2].frame;
+        let smallest = auto
+            .runs
+            .iter()
+            .filter(|run| run.meets_error)
+            .map(|run| run.size)
+            .min();
+        assert_eq!(Some(auto.selected.size), smallest);
+        assert_eq!(report.totals.samples, data.len());
+        // The candidates, Constant among them
+        assert_eq!(report.totals.compressors.len(), 6);
+        assert!(report.to_json().contains("\"selected_size\""));
+    }
+
+    #[test]
+    fn plan_bounded() {
+        let fake_data = vec![12.23; 2049];
+        let op = OptimizerPlan::plan_bounded(&fake_data, 0.01);
+        assert_eq!(op.compressors, [Compressor::Constant, Compressor::Constant]);
+    }
 }
@@ -0,0 +1,150 @@
+/*
+Copyright 2024 NetApp, Inc.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    https://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use crate::compressor::Compressor;
+use crate::optimizer::classifier::ClassifierMode;
+use crate::optimizer::sampling::SamplingStrategy;
+use crate::optimizer::{MAX_FRAME_SIZE, MIN_FRAME_SIZE};
+
+/// Compressors the Auto selection picks from, unless configured otherwise
+pub const DEFAULT_CANDIDATES: [Compressor; 6] = [
+    Compressor::Constant,
+    Compressor::Trend,
+    Compressor::FFT,
+    Compressor::Polynomial,
+    Compressor::RLE,
+    Compressor::Dictionary,
+];
+
+/// How the data is split in frames and how the compressor of each frame is selected
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompressionOptions {
+    /// Compressors the Auto selection picks from. Constant and Trend, when candidates, are picked first if a single
+    /// value or a line meets the error. Constant is always picked for constant data.
+    pub candidates: Vec<Compressor>,
+    /// Limits the samples used to select a compressor, 0 uses all of them and 6 only 128
+    pub sample_level: usize,
+    /// How the samples are taken when the sample level is not 0
+    pub sampling: SamplingStrategy,
+    /// Predicts the compressor from features of the data instead of compressing with all the candidates
+    pub classifier: Option<ClassifierMode>,
+    /// Biggest frame, in samples
+    pub max_frame_size: usize,
+    /// Data left at the end that is smaller than this goes in a single frame instead of being split further
+    pub min_frame_size: usize,
+    /// Places the frame boundaries at the change points of the data
+    pub adaptive_frames: bool,
+}
+
+impl Default for CompressionOptions {
+    fn default() -> Self {
+        CompressionOptions {
+            candidates: DEFAULT_CANDIDATES.to_vec(),
+            sample_level: 0,
+            sampling: SamplingStrategy::default(),
+            classifier: None,
+            max_frame_size: MAX_FRAME_SIZE,
+            min_frame_size: MIN_FRAME_SIZE,
+            adaptive_frames: false,
+        }
+    }
+}
+
+impl CompressionOptions {
+    /// Checks if the Auto selection can pick `compressor`
+    pub fn is_candidate(&self, compressor: Compressor) -> bool {
+        compressor == Compressor::Constant || self.candidates.contains(&compressor)
+    }
+}
+
+/// Named sets of options, trading speed for compression ratio
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Preset {
+    /// Predicts the compressor of each frame and only falls back to compressing with the candidates when the
+    /// prediction can't meet the error
+    Fast,
+    /// Compresses each frame with all the default candidates and keeps the smallest
+    #[default]
+    Balanced,
+    /// Compresses each frame with every compressor, frames follow the change points of the data
+    Max,
+}
+
+impl Preset {
+    pub fn options(&self) -> CompressionOptions {
+        match self {
+            Preset::Fast => CompressionOptions {
+                sample_level: 4,
+                classifier: Some(ClassifierMode::Trust),
+                ..Default::default()
+            },
+            Preset::Balanced => CompressionOptions::default(),
+            Preset::Max => CompressionOptions {
+                candidates: vec![
+                    Compressor::Constant,
+                    Compressor::Trend,
+                    Compressor::FFT,
+                    Compressor::Dct,
+                    Compressor::Polynomial,
+                    Compressor::Idw,
+                    Compressor::Pla,
+                    Compressor::Linear,
+                    Compressor::Spline,
+                    Compressor::Pchip,
+                    Compressor::Akima,
+                    Compressor::Residual,
+                    Compressor::Spike,
+                    Compressor::Wavelet,
+                    Compressor::RLE,
+                    Compressor::Dictionary,
+                    Compressor::Noop,
+                ],
+                adaptive_frames: true,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_presets() {
+        assert_eq!(Preset::default().options(), CompressionOptions::default());
+        let fast = Preset::Fast.options();
+        assert!(fast.classifier.is_some());
+        let max = Preset::Max.options();
+        assert!(max.is_candidate(Compressor::Idw));
+        assert!(max.is_candidate(Compressor::Wavelet));
+        assert!(max.is_candidate(Compressor::Dct));
+        assert!(max.is_candidate(Compressor::Pla));
+        assert!(max.is_candidate(Compressor::Pchip));
+        assert!(max.is_candidate(Compressor::Residual));
+        assert!(max.is_candidate(Compressor::Spike));
+        assert!(max.is_candidate(Compressor::Constant));
+        assert!(max.is_candidate(Compressor::Trend));
+        assert!(!fast.is_candidate(Compressor::Idw));
+        // Constant data is always stored as constant
+        let fft = CompressionOptions {
+            candidates: vec![Compressor::FFT],
+            ..Default::default()
+        };
+        assert!(fft.is_candidate(Compressor::Constant));
+        assert!(!fft.is_candidate(Compressor::Trend));
+    }
+}
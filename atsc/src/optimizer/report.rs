@@ -0,0 +1,224 @@
+/*
+Copyright 2024 NetApp, Inc.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    https://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use crate::compressor::Compressor;
+use crate::frame::Selection;
+use crate::optimizer::budget::FramePoint;
+use serde::Serialize;
+use std::fmt;
+use std::time::Duration;
+
+/// Result of compressing a chunk with one compressor
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct CompressorRun {
+    pub compressor: Compressor,
+    /// Compressed size in bytes
+    pub size: usize,
+    /// Error of the decompressed data, null if the error metric is undefined for the data
+    pub error: f64,
+    pub meets_error: bool,
+    /// Compression time in microseconds
+    pub time_us: u64,
+}
+
+impl CompressorRun {
+    pub fn new(point: FramePoint, max_error: f32, time: Duration) -> Self {
+        CompressorRun {
+            compressor: point.compressor,
+            size: point.size(),
+            error: point.error,
+            meets_error: point.error <= max_error as f64,
+            time_us: time.as_micros() as u64,
+        }
+    }
+}
+
+/// Dry run of a frame, every compressor that was tried and what the compression would do
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct FrameReport {
+    pub runs: Vec<CompressorRun>,
+    /// The compression that would be written, its time includes the selection
+    pub selected: CompressorRun,
+    pub reason: Selection,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ChunkReport {
+    /// Position of the first sample of the chunk
+    pub start: usize,
+    pub samples: usize,
+    /// Compressor assigned by the plan, Auto if the frame selects it
+    pub planned: Compressor,
+    #[serde(flatten)]
+    pub frame: FrameReport,
+}
+
+/// Totals for a compressor, as if it was used for every chunk
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct CompressorTotal {
+    pub compressor: Compressor,
+    pub size: usize,
+    pub time_us: u64,
+    /// Chunks where the error is under the maximum
+    pub chunks_meeting_error: usize,
+    /// Chunks where it was tried
+    pub chunks: usize,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Totals {
+    pub samples: usize,
+    /// Size of the uncompressed data, 64bits per sample
+    pub raw_size: usize,
+    /// Size of the selected compressions
+    pub selected_size: usize,
+    /// Size of the output, the selected compressions and the stream and frame headers
+    pub stream_size: usize,
+    /// Raw size over stream size
+    pub ratio: f64,
+    /// Error of the whole output
+    pub error: f64,
+    pub selected_time_us: u64,
+    pub compressors: Vec<CompressorTotal>,
+}
+
+/// Report of a dry run of the optimizer, for each chunk every candidate compressor and the selected one
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Report {
+    pub max_error: f32,
+    pub chunks: Vec<ChunkReport>,
+    pub totals: Totals,
+}
+
+impl Report {
+    /// Creates the report from the chunk reports, `overhead` is the size of the stream and frame headers
+    pub fn new(max_error: f32, chunks: Vec<ChunkReport>, overhead: usize) -> Self {
+        let samples: usize = chunks.iter().map(|c| c.samples).sum();
+        let selected_size: usize = chunks.iter().map(|c| c.frame.selected.size).sum();
+        let stream_size = selected_size + overhead;
+        let mut compressors: Vec<CompressorTotal> = Vec::new();
+        for run in chunks.iter().flat_map(|c| c.frame.runs.iter()) {
+            let index = match compressors
+                .iter()
+                .position(|t| t.compressor == run.compressor)
+            {
+                Some(index) => index,
+                None => {
+                    compressors.push(CompressorTotal {
+                        compressor: run.compressor,
+                        size: 0,
+                        time_us: 0,
+                        chunks_meeting_error: 0,
+                        chunks: 0,
+                    });
+                    compressors.len() - 1
+                }
+            };
+            let total = &mut compressors[index];
+            total.size += run.size;
+            total.time_us += run.time_us;
+            total.chunks_meeting_error += usize::from(run.meets_error);
+            total.chunks += 1;
+        }
+        // The error is a mean over the samples, each chunk weights by its samples
+        let error = if samples > 0 {
+            chunks
+                .iter()
+                .map(|c| c.frame.selected.error * c.samples as f64)
+                .sum::<f64>()
+                / samples as f64
+        } else {
+            0.0
+        };
+        let totals = Totals {
+            samples,
+            raw_size: samples * 8,
+            selected_size,
+            stream_size,
+            ratio: (samples * 8) as f64 / stream_size as f64,
+            error,
+            selected_time_us: chunks.iter().map(|c| c.frame.selected.time_us).sum(),
+            compressors,
+        };
+        Report {
+            max_error,
+            chunks,
+            totals,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("The report is always serializable")
+    }
+}
+
+impl fmt::Display for CompressorRun {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?}: {} bytes, error {:.4}%{}, {}us",
+            self.compressor,
+            self.size,
+            self.error * 100.0,
+            if self.meets_error { "" } else { " (over)" },
+            self.time_us
+        )
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Max error: {:.4}%", self.max_error * 100.0)?;
+        for (i, chunk) in self.chunks.iter().enumerate() {
+            writeln!(
+                f,
+                "Chunk {} [{}, {}) planned {:?}, selected ({:?}) {}",
+                i,
+                chunk.start,
+                chunk.start + chunk.samples,
+                chunk.planned,
+                chunk.frame.reason,
+                chunk.frame.selected
+            )?;
+            for run in chunk.frame.runs.iter() {
+                writeln!(f, "    {}", run)?;
+            }
+        }
+        let totals = &self.totals;
+        writeln!(
+            f,
+            "Total: {} samples, {} bytes raw, {} bytes compressed ({:.2}x), error {:.4}%, {}us",
+            totals.samples,
+            totals.raw_size,
+            totals.stream_size,
+            totals.ratio,
+            totals.error * 100.0,
+            totals.selected_time_us
+        )?;
+        for total in totals.compressors.iter() {
+            writeln!(
+                f,
+                "    {:?} on every chunk: {} bytes, meets the error on {}/{} chunks, {}us",
+                total.compressor,
+                total.size,
+                total.chunks_meeting_error,
+                total.chunks,
+                total.time_us
+            )?;
+        }
+        Ok(())
+    }
+}
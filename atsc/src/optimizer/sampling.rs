@@ -0,0 +1,120 @@
+/*
+Copyright 2024 NetApp, Inc.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    https://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::ops::Range;
+
+/// Max number of windows the sample is split into
+const SAMPLE_WINDOWS: usize = 4;
+/// Smallest window, compressors need some samples to show how they do on the data
+const MIN_WINDOW_SIZE: usize = 64;
+/// Seed for the random windows, fixed so the same data always compresses the same way
+pub const DEFAULT_SEED: u64 = 0x5EED;
+
+/// How the samples used to select a compressor are taken from a chunk
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SamplingStrategy {
+    /// The first samples of the chunk
+    Prefix,
+    /// Windows evenly spaced over the chunk, the first starts at the beginning and the last ends at the end
+    #[default]
+    Strided,
+    /// Windows at random positions, from a fixed seed
+    Random { seed: u64 },
+}
+
+impl SamplingStrategy {
+    /// Gets the windows of a chunk of `len` samples that are used for selection, taking `sample_size` samples in
+    /// total. The windows are sorted and don't overlap. If the chunk is not bigger than the sample, it is used whole.
+    pub fn windows(&self, len: usize, sample_size: usize) -> Vec<Range<usize>> {
+        let (count, window) = match self {
+            _ if len <= sample_size => (1, len),
+            SamplingStrategy::Prefix => (1, sample_size),
+            _ => {
+                let count = (sample_size / MIN_WINDOW_SIZE).clamp(1, SAMPLE_WINDOWS);
+                (count, sample_size / count)
+            }
+        };
+        // Free positions left once all the windows are placed
+        let slack = len - window * count;
+        let mut offsets: Vec<usize> = match self {
+            SamplingStrategy::Prefix => vec![0],
+            SamplingStrategy::Strided if count == 1 => vec![0],
+            SamplingStrategy::Strided => (0..count).map(|i| i * slack / (count - 1)).collect(),
+            SamplingStrategy::Random { seed } => {
+                let mut state = *seed;
+                (0..count)
+                    .map(|_| (splitmix64(&mut state) % (slack as u64 + 1)) as usize)
+                    .collect()
+            }
+        };
+        // Offsets are in the free space, stacking the windows before each one makes them disjoint
+        offsets.sort_unstable();
+        offsets
+            .into_iter()
+            .enumerate()
+            .map(|(i, offset)| {
+                let start = offset + i * window;
+                start..start + window
+            })
+            .collect()
+    }
+}
+
+/// Splitmix64 step, a small and good enough generator for picking positions
+fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9E3779B97F4A7C15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_windows() {
+        // Windows as (start, end) pairs
+        let windows = |strategy: SamplingStrategy, len, sample_size| -> Vec<(usize, usize)> {
+            strategy
+                .windows(len, sample_size)
+                .into_iter()
+                .map(|w| (w.start, w.end))
+                .collect()
+        };
+        assert_eq!(windows(SamplingStrategy::Prefix, 1000, 256), [(0, 256)]);
+        assert_eq!(
+            windows(SamplingStrategy::Strided, 1000, 256),
+            [(0, 64), (312, 376), (624, 688), (936, 1000)]
+        );
+        assert_eq!(windows(SamplingStrategy::Strided, 1000, 100), [(0, 100)]);
+        assert_eq!(windows(SamplingStrategy::Strided, 100, 256), [(0, 100)]);
+    }
+
+    #[test]
+    fn test_random_windows() {
+        let strategy = SamplingStrategy::Random { seed: DEFAULT_SEED };
+        let windows = strategy.windows(10000, 512);
+        // Same seed, same windows
+        assert_eq!(windows, strategy.windows(10000, 512));
+        assert_eq!(windows.len(), SAMPLE_WINDOWS);
+        for pair in windows.windows(2) {
+            assert!(pair[0].end <= pair[1].start);
+        }
+        assert!(windows.iter().all(|w| w.len() == 128 && w.end <= 10000));
+    }
+}
@@ -0,0 +1,171 @@
+/*
+Copyright 2024 NetApp, Inc.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    https://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use super::MAX_FRAMES;
+use log::debug;
+
+/// Minimum segment length, smaller frames have too much overhead to compress well
+pub const MIN_SEGMENT_SIZE: usize = 256;
+/// Cost reduction (in log-likelihood units, per log of the segment length) that a split has to beat.
+/// Higher means less, longer, segments.
+const SPLIT_PENALTY: f64 = 20.0;
+
+/// Prefix sums of the data and its square, to get the variance of any segment in O(1)
+struct PrefixSums {
+    sum: Vec<f64>,
+    sum_sq: Vec<f64>,
+    /// Smallest variance considered, keeps constant segments from going to -inf
+    variance_floor: f64,
+}
+
+impl PrefixSums {
+    fn new(data: &[f64]) -> Self {
+        // Centering the data keeps the squares from losing precision with large values
+        let mean = data.iter().sum::<f64>() / data.len() as f64;
+        let mut sum = Vec::with_capacity(data.len() + 1);
+        let mut sum_sq = Vec::with_capacity(data.len() + 1);
+        sum.push(0.0);
+        sum_sq.push(0.0);
+        for (i, value) in data.iter().enumerate() {
+            let centered = value - mean;
+            sum.push(sum[i] + centered);
+            sum_sq.push(sum_sq[i] + centered * centered);
+        }
+        let variance = sum_sq[data.len()] / data.len() as f64;
+        PrefixSums {
+            sum,
+            sum_sq,
+            variance_floor: (variance * 1e-6).max(f64::MIN_POSITIVE),
+        }
+    }
+
+    /// Cost of modelling the segment [start, end) as a gaussian with its own mean and variance
+    /// (negative log-likelihood, without the constants)
+    fn cost(&self, start: usize, end: usize) -> f64 {
+        let n = (end - start) as f64;
+        let mean = (self.sum[end] - self.sum[start]) / n;
+        let variance = (self.sum_sq[end] - self.sum_sq[start]) / n - mean * mean;
+        n * variance.max(self.variance_floor).ln()
+    }
+
+    /// Best position to split the segment [start, end) and the cost reduction of doing it
+    fn best_split(&self, start: usize, end: usize) -> Option<(usize, f64)> {
+        if end - start < 2 * MIN_SEGMENT_SIZE {
+            return None;
+        }
+        let total = self.cost(start, end);
+        ((start + MIN_SEGMENT_SIZE)..=(end - MIN_SEGMENT_SIZE))
+            .map(|split| {
+                (
+                    split,
+                    total - self.cost(start, split) - self.cost(split, end),
+                )
+            })
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+    }
+}
+
+/// Splits the data into segments with boundaries at the detected change points, so each segment is homogeneous
+/// and can be handled by a single compressor. Level shifts, variance changes and constant runs all show as a
+/// change in the mean or the variance of the data.
+///
+/// Uses binary segmentation: the split that reduces the cost the most is applied first, until no split is worth
+/// its penalty or the frame count limit is reached. Segments bigger than `max_size` are then split evenly.
+///
+/// Returns the segment sizes, in order.
+pub fn segment_sizes(data: &[f64], max_size: usize) -> Vec<usize> {
+    let mut boundaries = vec![0, data.len()];
+    if data.len() >= 2 * MIN_SEGMENT_SIZE {
+        let sums = PrefixSums::new(data);
+        // Candidate splits, (start, end, split position, cost reduction)
+        let mut candidates: Vec<(usize, usize, usize, f64)> = Vec::new();
+        let push_candidate = |candidates: &mut Vec<(usize, usize, usize, f64)>, start, end| {
+            if let Some((split, gain)) = sums.best_split(start, end) {
+                let penalty = SPLIT_PENALTY * ((end - start) as f64).ln();
+                if gain > penalty {
+                    candidates.push((start, end, split, gain));
+                }
+            }
+        };
+        push_candidate(&mut candidates, 0, data.len());
+        while boundaries.len() - 1 < MAX_FRAMES {
+            let best = candidates
+                .iter()
+                .enumerate()
+                .max_by(|a, b| a.1 .3.total_cmp(&b.1 .3))
+                .map(|(i, _)| i);
+            let Some(best) = best else {
+                break;
+            };
+            let (start, end, split, gain) = candidates.swap_remove(best);
+            debug!(
+                "Change point at {} for segment [{}, {}). Gain: {}",
+                split, start, end, gain
+            );
+            boundaries.push(split);
+            push_candidate(&mut candidates, start, split);
+            push_candidate(&mut candidates, split, end);
+        }
+        boundaries.sort_unstable();
+    }
+    // Big segments are split into even parts
+    let mut sizes = Vec::with_capacity(boundaries.len());
+    for window in boundaries.windows(2) {
+        let len = window[1] - window[0];
+        let parts = len.div_ceil(max_size).max(1);
+        for part in 0..parts {
+            // Spread the remainder over the first parts
+            sizes.push(len / parts + usize::from(part < len % parts));
+        }
+    }
+    sizes.retain(|&size| size > 0);
+    sizes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_level_shift() {
+        let data: Vec<f64> = (0..2048)
+            .map(|x| if x < 1000 { 10.0 } else { 50.0 } + ((x * 7919) % 13) as f64)
+            .collect();
+        assert_eq!(segment_sizes(&data, 131072), [1000, 1048]);
+    }
+
+    #[test]
+    fn test_constant_run_and_variance_change() {
+        let noise = |x: usize, amplitude: f64| ((x * 7919) % 101) as f64 / 100.0 * amplitude;
+        let data: Vec<f64> = (0..3000)
+            .map(|x| match x {
+                _ if x < 1000 => 100.0 + noise(x, 1.0),
+                _ if x < 2000 => 100.0,
+                _ => 100.0 + noise(x, 30.0),
+            })
+            .collect();
+        assert_eq!(segment_sizes(&data, 131072), [1000, 1000, 1000]);
+    }
+
+    #[test]
+    fn test_homogeneous() {
+        let data: Vec<f64> = (0..4096).map(|x| ((x * 7919) % 101) as f64).collect();
+        assert_eq!(segment_sizes(&data, 131072), [4096]);
+        assert_eq!(segment_sizes(&data, 1024), [1024, 1024, 1024, 1024]);
+        assert_eq!(segment_sizes(&data[0..100], 131072), [100]);
+        assert_eq!(segment_sizes(&[], 131072), Vec::<usize>::new());
+    }
+}
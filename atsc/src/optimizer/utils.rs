@@ -16,6 +16,7 @@ limitations under the License.
 
 use bincode::{Decode, Encode};
 use log::debug;
+use std::collections::HashSet;
 
 #[derive(PartialEq, Debug, Clone, Copy, Encode, Decode)]
 pub enum Bitdepth {
@@ -33,6 +34,12 @@ pub struct DataStats {
     pub mean: f64,
     pub bitdepth: Bitdepth,
     pub fractional: bool,
+    /// Number of runs of consecutive equal values
+    pub runs: usize,
+    /// Mean of the absolute difference between consecutive values
+    pub mean_abs_delta: f64,
+    /// Number of distinct values
+    pub distinct: usize,
 }
 
 impl DataStats {
@@ -45,11 +52,15 @@ impl DataStats {
         let mut fractional = false;
         let mut mean: f64 = 0.0;
         let mut recommended_bitdepth = Bitdepth::F64;
+        let mut runs = 1;
+        let mut abs_delta: f64 = 0.0;
+        let mut values = HashSet::new();
 
         // Walk the data and perform the analysis
         for (i, value) in data.iter().enumerate() {
             let t_value = *value;
             mean += value;
+            values.insert(t_value.to_bits());
             if split_n(t_value).1 != 0.0 {
                 fractional = true;
             }
@@ -61,8 +72,18 @@ impl DataStats {
                 min = t_value;
                 min_loc = i;
             };
+            // Runs are split where the bits change, like the RLE and Dictionary encoders do
+            if i > 0 && t_value.to_bits() != data[i - 1].to_bits() {
+                runs += 1;
+                abs_delta += (t_value - data[i - 1]).abs();
+            }
         }
         mean /= data.len() as f64;
+        let mean_abs_delta = if data.len() > 1 {
+            abs_delta / (data.len() - 1) as f64
+        } else {
+            0.0
+        };
         // Check max size of values
         // TODO: for very large numbers (i32 and i64), it might be ideal to detect the dc component
         // of the signal. And then remove it later
@@ -85,6 +106,9 @@ impl DataStats {
             mean,
             bitdepth: recommended_bitdepth,
             fractional,
+            runs,
+            mean_abs_delta,
+            distinct: values.len(),
         }
     }
 
@@ -174,6 +198,9 @@ mod tests {
         assert_eq!(stats.min_loc, 0);
         assert_eq!(stats.max_loc, 0);
         assert!(!stats.fractional);
+        assert_eq!(stats.runs, 1);
+        assert_eq!(stats.mean_abs_delta, 0.0);
+        assert_eq!(stats.distinct, 1);
     }
 
     #[test]
@@ -187,6 +214,18 @@ mod tests {
         assert_eq!(stats.min_loc, 0);
         assert_eq!(stats.max_loc, 2);
         assert!(!stats.fractional);
+        assert_eq!(stats.runs, 3);
+        assert_eq!(stats.mean_abs_delta, 3.0);
+        assert_eq!(stats.distinct, 3);
+    }
+
+    #[test]
+    fn test_stats_runs() {
+        // Signed zeros are different values, repeated infinities are a single run
+        let data = vec![0.0, -0.0, f64::INFINITY, f64::INFINITY, f64::INFINITY, 1.0];
+        let stats = DataStats::new(&data);
+        assert_eq!(stats.runs, 4);
+        assert_eq!(stats.distinct, 4);
     }
 
     #[test]
@@ -48,6 +48,45 @@ pub fn is_decomposable(mut n: usize) -> bool {
     n == 1
 }
 
+/// Finds the smallest value in `[low, high]` for which `passes` is true, assuming that once a value passes every
+/// bigger one does too. The search starts at `guess`, doubling the distance above it until a value passes, and then
+/// bisects, so a good guess takes a few calls. Returns `high` if no value passes, without checking it twice.
+pub fn smallest_passing(
+    low: usize,
+    high: usize,
+    guess: usize,
+    mut passes: impl FnMut(usize) -> bool,
+) -> usize {
+    let guess = guess.clamp(low, high);
+    // Invariant: every value under `low` fails, `high` passes or is the last resort
+    let (mut low, mut high) = if passes(guess) {
+        (low, guess)
+    } else {
+        let mut failed = guess;
+        let mut jump = 1;
+        loop {
+            if failed >= high {
+                return high;
+            }
+            let next = (failed + jump).min(high);
+            if passes(next) {
+                break (failed + 1, next);
+            }
+            failed = next;
+            jump *= 2;
+        }
+    };
+    while low < high {
+        let mid = low + (high - low) / 2;
+        if passes(mid) {
+            high = mid;
+        } else {
+            low = mid + 1;
+        }
+    }
+    high
+}
+
 /// Converts a float to u64 with a given precision
 pub fn f64_to_u64(number: f64, precision: usize) -> u64 {
     // TODO: Panic on overflow
@@ -73,6 +112,21 @@ pub fn round_and_limit_f64(x: f64, min: f64, max: f64, decimals: u32) -> f64 {
     }
 }
 
+/// Median of the values, 0 if there are none
+pub fn median(values: &[f64]) -> f64 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    let mut sorted = values.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    let middle = sorted.len() / 2;
+    if sorted.len() % 2 == 0 {
+        (sorted[middle - 1] + sorted[middle]) / 2.0
+    } else {
+        sorted[middle]
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -85,6 +139,13 @@ mod tests {
         assert_eq!(round_and_limit_f64(3.123452312, 2., 4., 3), 3.123);
     }
 
+    #[test]
+    fn test_median() {
+        assert_eq!(median(&[]), 0.0);
+        assert_eq!(median(&[3.0, -1.0, 7.0]), 3.0);
+        assert_eq!(median(&[4.0, 1.0, 2.0, 10.0]), 3.0);
+    }
+
     #[test]
     fn test_is_decomposable() {
         assert!(is_decomposable(2048));
@@ -99,4 +160,21 @@ mod tests {
         assert_eq!(next_size(128), 144);
         assert_eq!(next_size(12432), 13122);
     }
+
+    #[test]
+    fn test_smallest_passing() {
+        for threshold in [0, 1, 7, 50, 99, 100] {
+            for guess in [0, 3, 50, 100] {
+                let mut calls = 0;
+                let found = smallest_passing(0, 100, guess, |x| {
+                    calls += 1;
+                    x >= threshold
+                });
+                assert_eq!(found, threshold);
+                assert!(calls <= 16, "{} calls", calls);
+            }
+        }
+        // Nothing passes, the last resort is the highest value
+        assert_eq!(smallest_passing(1, 10, 2, |_| false), 10);
+    }
 }
@@ -38,11 +38,51 @@ fn test_compressor_rle() {
     test_lossless_compression("rle")
 }
 
+#[test]
+fn test_compressor_dictionary() {
+    test_lossless_compression("dictionary")
+}
+
 #[test]
 fn test_compressor_fft_lossy() {
     test_lossy_compression("fft")
 }
 
+#[test]
+fn test_compressor_mdct_lossy() {
+    test_lossy_compression("mdct")
+}
+
+#[test]
+fn test_compressor_dct_lossy() {
+    test_lossy_compression("dct")
+}
+
+#[test]
+fn test_compressor_wavelet_lossy() {
+    test_lossy_compression("wavelet")
+}
+
+#[test]
+fn test_compressor_pla_lossy() {
+    test_lossy_compression("pla")
+}
+
+#[test]
+fn test_compressor_pchip_lossy() {
+    test_lossy_compression("pchip")
+}
+
+#[test]
+fn test_compressor_residual_lossy() {
+    test_lossy_compression("residual")
+}
+
+#[test]
+fn test_compressor_spike_lossy() {
+    test_lossy_compression("spike")
+}
+
 #[test]
 fn test_compressor_auto_lossless() {
     test_lossless_compression("auto")
@@ -53,6 +93,257 @@ fn test_compressor_auto_lossy() {
     test_lossy_compression("auto")
 }
 
+#[test]
+fn test_compressor_auto_global_error() {
+    let test_dir = prepare_test_dir();
+
+    run_compressor(&[
+        "--compressor",
+        "auto",
+        "--error",
+        "5",
+        "--global-error",
+        test_dir.join(TEST_FILE_NAME).to_str().unwrap(),
+    ]);
+
+    run_compressor(&[
+        "-u",
+        test_dir.join(TEST_COMPRESSED_FILE_NAME).to_str().unwrap(),
+    ]);
+
+    compare_samples_with_allowed_error(
+        &PathBuf::from(TEST_WBRO_PATH),
+        &test_dir.join(TEST_FILE_NAME),
+    )
+}
+
+#[test]
+fn test_compressor_auto_classifier() {
+    for classifier in ["trust", "verify"] {
+        let test_dir = prepare_test_dir();
+
+        run_compressor(&[
+            "--compressor",
+            "auto",
+            "--error",
+            "5",
+            "--classifier",
+            classifier,
+            test_dir.join(TEST_FILE_NAME).to_str().unwrap(),
+        ]);
+
+        run_compressor(&[
+            "-u",
+            test_dir.join(TEST_COMPRESSED_FILE_NAME).to_str().unwrap(),
+        ]);
+
+        compare_samples_with_allowed_error(
+            &PathBuf::from(TEST_WBRO_PATH),
+            &test_dir.join(TEST_FILE_NAME),
+        )
+    }
+}
+
+#[test]
+fn test_compressor_presets() {
+    for preset in ["fast", "balanced", "max"] {
+        let test_dir = prepare_test_dir();
+
+        run_compressor(&[
+            "--preset",
+            preset,
+            "--error",
+            "5",
+            test_dir.join(TEST_FILE_NAME).to_str().unwrap(),
+        ]);
+
+        run_compressor(&[
+            "-u",
+            test_dir.join(TEST_COMPRESSED_FILE_NAME).to_str().unwrap(),
+        ]);
+
+        compare_samples_with_allowed_error(
+            &PathBuf::from(TEST_WBRO_PATH),
+            &test_dir.join(TEST_FILE_NAME),
+        )
+    }
+}
+
+#[test]
+fn test_compressor_candidates() {
+    let test_dir = prepare_test_dir();
+
+    run_compressor(&[
+        "--candidates",
+        "idw,noop",
+        "--error",
+        "5",
+        "--max-frame-size",
+        "1024",
+        test_dir.join(TEST_FILE_NAME).to_str().unwrap(),
+    ]);
+
+    run_compressor(&[
+        "-u",
+        test_dir.join(TEST_COMPRESSED_FILE_NAME).to_str().unwrap(),
+    ]);
+
+    compare_samples_with_allowed_error(
+        &PathBuf::from(TEST_WBRO_PATH),
+        &test_dir.join(TEST_FILE_NAME),
+    )
+}
+
+#[test]
+fn test_dry_run_report() {
+    let test_dir = prepare_test_dir();
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_atsc"))
+        .args([
+            "--dry-run",
+            "--report",
+            "json",
+            test_dir.join(TEST_FILE_NAME).to_str().unwrap(),
+        ])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let chunks = report["chunks"].as_array().unwrap();
+    assert!(!chunks.is_empty());
+    // Every default candidate is tried on every chunk
+    assert!(chunks
+        .iter()
+        .all(|chunk| chunk["runs"].as_array().unwrap().len() >= 3));
+    assert!(report["totals"]["stream_size"].as_u64().unwrap() > 0);
+    // Nothing is written
+    assert!(!test_dir.join(TEST_COMPRESSED_FILE_NAME).exists());
+}
+
+#[test]
+fn test_compressor_selection_cache() {
+    let test_dir = prepare_test_dir();
+    let cache = test_dir.join("selections.json");
+    let input = test_dir.join(TEST_FILE_NAME);
+    let args = [
+        "--selection-cache",
+        cache.to_str().unwrap(),
+        input.to_str().unwrap(),
+    ];
+
+    run_compressor(&args);
+    let uncached = fs::read(test_dir.join(TEST_COMPRESSED_FILE_NAME)).unwrap();
+    let selections: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&cache).unwrap()).unwrap();
+    assert!(!selections["series"]["go_gc_heap_goal_bytes"]
+        .as_array()
+        .unwrap()
+        .is_empty());
+
+    // The second run reuses the selections, and compresses the same
+    run_compressor(&args);
+    let cached = fs::read(test_dir.join(TEST_COMPRESSED_FILE_NAME)).unwrap();
+    assert_eq!(cached, uncached);
+
+    run_compressor(&[
+        "-u",
+        test_dir.join(TEST_COMPRESSED_FILE_NAME).to_str().unwrap(),
+    ]);
+
+    compare_samples_with_allowed_error(
+        &PathBuf::from(TEST_WBRO_PATH),
+        &test_dir.join(TEST_FILE_NAME),
+    )
+}
+
+#[test]
+fn test_compressor_selection_cache_directory() {
+    let test_dir = prepare_test_dir();
+    fs::copy(TEST_WBRO_PATH, test_dir.join("copy.wbro")).unwrap();
+    let cache = tempfile::tempdir()
+        .unwrap()
+        .into_path()
+        .join("selections.json");
+    run_compressor(&[
+        "--selection-cache",
+        cache.to_str().unwrap(),
+        test_dir.to_str().unwrap(),
+    ]);
+    // The files compress in parallel, the selections of all of them are kept
+    let selections: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&cache).unwrap()).unwrap();
+    for series in ["go_gc_heap_goal_bytes", "copy"] {
+        assert!(!selections["series"][series].as_array().unwrap().is_empty());
+    }
+
+    // A single series name for all the files is rejected
+    let exit_status = std::process::Command::new(env!("CARGO_BIN_EXE_atsc"))
+        .args([
+            "--selection-cache",
+            cache.to_str().unwrap(),
+            "--series-name",
+            "memory",
+            test_dir.to_str().unwrap(),
+        ])
+        .status()
+        .unwrap();
+    assert!(!exit_status.success());
+}
+
+#[test]
+fn test_compressor_threads() {
+    let mut outputs = Vec::new();
+    for threads in ["1", "4"] {
+        let test_dir = prepare_test_dir();
+
+        run_compressor(&[
+            "--threads",
+            threads,
+            test_dir.join(TEST_FILE_NAME).to_str().unwrap(),
+        ]);
+        outputs.push(fs::read(test_dir.join(TEST_COMPRESSED_FILE_NAME)).unwrap());
+
+        run_compressor(&[
+            "-u",
+            "--threads",
+            threads,
+            test_dir.join(TEST_COMPRESSED_FILE_NAME).to_str().unwrap(),
+        ]);
+
+        compare_samples_with_allowed_error(
+            &PathBuf::from(TEST_WBRO_PATH),
+            &test_dir.join(TEST_FILE_NAME),
+        )
+    }
+    // The output doesn't depend on the number of threads
+    assert_eq!(outputs[0], outputs[1]);
+}
+
+#[test]
+fn test_compressor_target_size() {
+    let test_dir = prepare_test_dir();
+    let original_samples = WavBrro::from_file(&PathBuf::from(TEST_WBRO_PATH)).unwrap();
+    let target_size = original_samples.len() * 8 / 50;
+
+    run_compressor(&[
+        "--target-size",
+        target_size.to_string().as_str(),
+        test_dir.join(TEST_FILE_NAME).to_str().unwrap(),
+    ]);
+
+    let compressed_size = fs::metadata(test_dir.join(TEST_COMPRESSED_FILE_NAME))
+        .unwrap()
+        .len();
+    assert!(compressed_size <= target_size as u64);
+
+    run_compressor(&[
+        "-u",
+        test_dir.join(TEST_COMPRESSED_FILE_NAME).to_str().unwrap(),
+    ]);
+    let uncompressed_samples = WavBrro::from_file(&test_dir.join(TEST_FILE_NAME)).unwrap();
+    assert_eq!(original_samples.len(), uncompressed_samples.len());
+}
+
 #[test]
 fn test_csv_input_compression_with_header() {
     let filepath = Path::new("./tests/csv/cpu_utilization.csv");
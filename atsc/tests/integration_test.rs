@@ -51,6 +51,16 @@ fn test_auto() {
     test_suite("auto");
 }
 
+#[test]
+fn test_adaptive_frames() {
+    let tmp_dir = tempdir().unwrap();
+    let path = tmp_dir.path();
+    std::fs::copy("tests/wbros/memory_used.wbro", path.join("1.wbro")).unwrap();
+
+    run_compressor(&[path.join("1.wbro").to_str().unwrap(), "--adaptive-frames"]);
+    assert!(path.join("1.bro").is_file());
+}
+
 #[test]
 fn test_compression_speed() {
     test_speed();
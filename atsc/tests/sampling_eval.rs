@@ -0,0 +1,114 @@
+/*
+Copyright 2024 NetApp, Inc.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    https://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+//! Evaluation of the sampling strategies used to select a compressor. For each input and speed level, compares
+//! the compressed size with the compressor each strategy picks against the exhaustive selection (speed level 0).
+//! Run with `cargo test --test sampling_eval -- --nocapture` to see the table.
+
+use atsc::compressor::Compressor;
+use atsc::csv::read_samples_with_headers;
+use atsc::data::CompressedStream;
+use atsc::optimizer::options::CompressionOptions;
+use atsc::optimizer::sampling::{SamplingStrategy, DEFAULT_SEED};
+use atsc::optimizer::OptimizerPlan;
+use std::path::Path;
+use wavbrro::wavbrro::WavBrro;
+
+const MAX_ERROR: f32 = 0.03;
+const STRATEGIES: [SamplingStrategy; 3] = [
+    SamplingStrategy::Prefix,
+    SamplingStrategy::Strided,
+    SamplingStrategy::Random { seed: DEFAULT_SEED },
+];
+
+/// Compressed size of the data with Auto selection on every frame
+fn compressed_size(data: &[f64], sample_level: usize, sampling: SamplingStrategy) -> usize {
+    let options = CompressionOptions {
+        sample_level,
+        sampling,
+        ..Default::default()
+    };
+    let plan = OptimizerPlan::plan(data);
+    let mut cs = CompressedStream::new();
+    for (_, chunk) in plan.get_execution() {
+        cs.compress_chunk_bounded_with_options(chunk, Compressor::Auto, MAX_ERROR, &options);
+    }
+    cs.to_bytes().len()
+}
+
+fn inputs() -> Vec<(String, Vec<f64>)> {
+    let mut inputs: Vec<(String, Vec<f64>)> = ["memory_used", "go_gc_heap_goal_bytes", "uptime"]
+        .iter()
+        .map(|name| {
+            let path = format!("tests/wbros/{name}.wbro");
+            (
+                name.to_string(),
+                WavBrro::from_file(Path::new(&path)).unwrap(),
+            )
+        })
+        .collect();
+    for name in ["cpu_utilization", "iowait"] {
+        let path = format!("tests/csv/{name}.csv");
+        let samples = read_samples_with_headers(Path::new(&path), "time", "value").unwrap();
+        inputs.push((
+            name.to_string(),
+            samples.into_iter().map(|s| s.value).collect(),
+        ));
+    }
+    // Starts quiet and becomes noisy, the prefix only sees the quiet part
+    let drifting = (0..8192)
+        .map(|x| match x {
+            _ if x < 4096 => 1000.0 + (x / 1024) as f64,
+            _ => 1000.0 + ((x * 7919) % 97) as f64 * (x as f64 / 4096.0),
+        })
+        .collect();
+    inputs.push(("quiet_then_noisy".to_string(), drifting));
+    inputs
+}
+
+#[test]
+fn test_sampling_strategies() {
+    let mut totals = [0; STRATEGIES.len()];
+    println!(
+        "{:<24} {:>5} {:>10} {:>10} {:>10} {:>10}",
+        "input", "level", "exhaustive", "prefix", "strided", "random"
+    );
+    for (name, data) in inputs() {
+        let exhaustive = compressed_size(&data, 0, SamplingStrategy::default());
+        for speed in 1..7 {
+            let sizes = STRATEGIES.map(|strategy| compressed_size(&data, speed, strategy));
+            println!(
+                "{:<24} {:>5} {:>10} {:>10} {:>10} {:>10}",
+                name, speed, exhaustive, sizes[0], sizes[1], sizes[2]
+            );
+            for (total, size) in totals.iter_mut().zip(sizes) {
+                *total += size;
+            }
+            // A single value or a line meets the error on both frames of this input, so the search is skipped and
+            // every strategy gives the same size. Without Constant and Trend as candidates, the prefix picks a
+            // lossless compressor from the quiet part at levels 2 to 6 and is about a thousand times bigger
+            if name == "quiet_then_noisy" {
+                assert!(sizes[1] <= sizes[0]);
+            }
+        }
+    }
+    println!(
+        "Totals. Prefix: {}, Strided: {}, Random: {}",
+        totals[0], totals[1], totals[2]
+    );
+    // Spreading the windows over the chunk picks better than the prefix overall
+    assert!(totals[1] <= totals[0]);
+}